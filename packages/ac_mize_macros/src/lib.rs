@@ -1,10 +1,84 @@
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{Data, DeriveInput, Fields, parse_macro_input};
+use syn::{Data, DeriveInput, Fields, Ident, Lit, LitStr, Type, parse_macro_input};
+
+// the methods of MizePart, an accessor with the same name would shadow them
+static PART_METHODS: &[&str] = &[
+    "name",
+    "get_mize",
+    "as_any",
+    "as_any_mut",
+    "into_any",
+    "init",
+    "run",
+    "deps",
+    "opts",
+    "async_run",
+    "async_init",
+    "shutdown",
+];
+
+// a field with #[opt(name = "...", default = "...", doc = "...")]
+// it is removed from the struct, the option is declared in opts_generated() and read with an
+// accessor named like the field, that returns the value as the type of the field
+// the name defaults to <part name>.<field name>
+struct Opt {
+    field: Ident,
+    ty: Type,
+    name: String,
+    default: Option<String>,
+    doc: Option<String>,
+}
+
+fn parse_opt(field: &syn::Field, part_name: &str) -> syn::Result<Option<Opt>> {
+    let attr = match field.attrs.iter().find(|attr| attr.path().is_ident("opt")) {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+    let ident = field.ident.clone().unwrap();
+    if PART_METHODS.contains(&ident.to_string().as_str()) {
+        return Err(syn::Error::new_spanned(
+            &ident,
+            "an option can't be named like a method of MizePart, give the field another name",
+        ));
+    }
+
+    let mut opt = Opt {
+        name: format!("{}.{}", part_name, ident),
+        field: ident,
+        ty: field.ty.clone(),
+        default: None,
+        doc: None,
+    };
+    // a bare #[opt] has no arguments
+    if matches!(attr.meta, syn::Meta::Path(_)) {
+        return Ok(Some(opt));
+    }
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("name") {
+            opt.name = meta.value()?.parse::<LitStr>()?.value();
+        } else if meta.path.is_ident("doc") {
+            opt.doc = Some(meta.value()?.parse::<LitStr>()?.value());
+        } else if meta.path.is_ident("default") {
+            // default = 10 is the same as default = "10"
+            opt.default = Some(match meta.value()?.parse::<Lit>()? {
+                Lit::Str(lit) => lit.value(),
+                Lit::Int(lit) => lit.base10_digits().to_owned(),
+                Lit::Float(lit) => lit.base10_digits().to_owned(),
+                Lit::Bool(lit) => lit.value.to_string(),
+                other => return Err(syn::Error::new_spanned(other, "unsupported default")),
+            });
+        } else {
+            return Err(meta.error("expected name, default or doc"));
+        }
+        Ok(())
+    })?;
+    Ok(Some(opt))
+}
 
 #[proc_macro_attribute]
 pub fn mize_part(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(item as DeriveInput);
+    let mut input = parse_macro_input!(item as DeriveInput);
     let struct_name = &input.ident;
 
     // Parse name from attribute
@@ -33,9 +107,56 @@ pub fn mize_part(attr: TokenStream, item: TokenStream) -> TokenStream {
             .into();
     }
 
+    // take the option fields out of the struct
+    let mut opts = Vec::new();
+    if let Data::Struct(s) = &mut input.data
+        && let Fields::Named(f) = &mut s.fields
+    {
+        let mut kept = syn::punctuated::Punctuated::new();
+        for field in f.named.iter() {
+            match parse_opt(field, part_name.as_str()) {
+                Ok(Some(opt)) => opts.push(opt),
+                Ok(None) => kept.push(field.clone()),
+                Err(err) => return err.to_compile_error().into(),
+            }
+        }
+        f.named = kept;
+    }
+
+    let declare_opts = opts.iter().map(|opt| {
+        let Opt { name, ty, .. } = opt;
+        let default = opt.default.as_ref().map(|default| {
+            quote! { .default_val(mize::item::ItemData::from_string(#default)) }
+        });
+        let doc = opt.doc.as_ref().map(|doc| quote! { .doc(#doc) });
+        quote! {
+            mize.new_opt(#name).typed::<#ty>()#default #doc;
+        }
+    });
+
+    let accessors = opts.iter().map(|opt| {
+        let Opt {
+            field, ty, name, ..
+        } = opt;
+        let doc = match &opt.doc {
+            Some(doc) => format!("the option `{}`: {}", name, doc),
+            None => format!("the option `{}`", name),
+        };
+        quote! {
+            #[doc = #doc]
+            pub fn #field(&self) -> mize::MizeResult<#ty> {
+                self.mize.get_config_as::<#ty>(#name)
+            }
+        }
+    });
+
     let expanded = quote! {
         #input
 
+        impl #struct_name {
+            #(#accessors)*
+        }
+
         // impl the default new impl
         impl mize::MizePartGenerated for #struct_name {
             fn name_generated(&self) -> &'static str {
@@ -53,9 +174,14 @@ pub fn mize_part(attr: TokenStream, item: TokenStream) -> TokenStream {
             fn into_any_generated(self: Box<Self>) -> Box<dyn std::any::Any> {
               self
             }
+            fn opts_generated(&self, mize: &mut Mize) {
+              #(#declare_opts)*
+            }
         }
 
         impl mize::MizePartCreateGenerated for #struct_name {
+            // a part can have only the mize field
+            #[allow(clippy::needless_update)]
             fn create_generated(mize: Mize) -> Self {
                 Self {
                     mize,
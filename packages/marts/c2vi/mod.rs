@@ -14,12 +14,28 @@ use crate::habitica::Habitica;
 #[derive(Default)]
 pub struct C2vi {
     mize: Mize,
+    // opened in init(), when the options are there
     db: Mutex<Option<Connection>>,
+    #[opt(doc = "the directory of the local database (data.db)")]
+    local_storage_path: String,
 }
 
 impl MizePart for C2vi {
-    fn opts(&self, mize: &mut Mize) {
-        mize.new_opt("c2vi.local_storage_path");
+    fn deps(&self) -> &'static [&'static str] {
+        &["cli", "habitica"]
+    }
+    fn init(&mut self, _mize: &mut Mize) -> MizeResult<()> {
+        let db_path = std::path::PathBuf::from(self.local_storage_path()?).join("data.db");
+        let conn = Connection::open(&db_path)
+            .map_err(|e| mize_err!("Failed to open C2vi database at {:?}: {}", db_path, e))?;
+
+        setup_tables(&conn)?;
+
+        *self
+            .db
+            .get_mut()
+            .map_err(|e| mize_err!("DB lock poisoned: {}", e))? = Some(conn);
+        Ok(())
     }
 }
 
@@ -40,16 +56,9 @@ impl C2vi {
 }
 
 pub fn c2vi(mize: &mut Mize) -> MizeResult<()> {
-    let storage_path = mize.get_config("c2vi.local_storage_path")?.to_string();
-    let db_path = std::path::PathBuf::from(&storage_path).join("data.db");
-    let conn = Connection::open(&db_path)
-        .map_err(|e| mize_err!("Failed to open C2vi database at {:?}: {}", db_path, e))?;
-
-    setup_tables(&conn)?;
-
     let c2vi_part = C2vi {
         mize: mize.clone(),
-        db: Mutex::new(Some(conn)),
+        db: Mutex::new(None),
     };
     mize.register_part(Box::new(c2vi_part))?;
 
@@ -57,6 +66,14 @@ pub fn c2vi(mize: &mut Mize) -> MizeResult<()> {
     let mut cli = mize.get_part_native::<CliPart>("cli")?;
     register_subcommands(&mut cli);
 
+    // so that a rule can do the same as `bed` (eg: when the last daily log item is done)
+    mize.add_rule_action("c2vi.bed", |mize, _id| {
+        let mut mize = mize.clone();
+        save_habitica_dailies(&mut mize)?;
+        save_habitica_logs(&mut mize)?;
+        Ok(())
+    })?;
+
     Ok(())
 }
 
@@ -97,12 +114,9 @@ fn register_subcommands(cli: &mut CliPart) {
         Command::new("clearTodos")
             .about("Clear all Habitica todos")
             .alias("clt"),
-        |_matches, mut mize| {
-            let todos = {
-                let mut hab = mize.get_part_native::<Habitica>("habitica")?;
-                hab.get_tasks("todos")?
-            };
-            let mut hab = mize.get_part_native::<Habitica>("habitica")?;
+        |_matches, mize| {
+            let hab = mize.read_part_native::<Habitica>("habitica")?;
+            let todos = hab.get_tasks("todos")?;
             if let Some(arr) = todos.as_array() {
                 for todo in arr {
                     if let Some(id) = todo["id"].as_str() {
@@ -118,8 +132,8 @@ fn register_subcommands(cli: &mut CliPart) {
         Command::new("listTodos")
             .about("List all Habitica todos")
             .alias("dut"),
-        |_matches, mut mize| {
-            let mut hab = mize.get_part_native::<Habitica>("habitica")?;
+        |_matches, mize| {
+            let hab = mize.read_part_native::<Habitica>("habitica")?;
             let todos = hab.get_tasks("todos")?;
             if let Some(arr) = todos.as_array() {
                 for todo in arr {
@@ -136,8 +150,8 @@ fn register_subcommands(cli: &mut CliPart) {
         Command::new("buyHealthPotion")
             .about("Buy a health potion")
             .alias("buyh"),
-        |_matches, mut mize| {
-            let mut hab = mize.get_part_native::<Habitica>("habitica")?;
+        |_matches, mize| {
+            let hab = mize.read_part_native::<Habitica>("habitica")?;
             hab.api_request(
                 reqwest::Method::POST,
                 "user/buy-health-potion".to_string(),
@@ -176,8 +190,8 @@ fn register_subcommands(cli: &mut CliPart) {
 
     cli.subcommand(
         Command::new("listDailies").about("List all dailies from local DB"),
-        |_matches, mut mize| {
-            let c2vi = mize.get_part_native::<C2vi>("c2vi")?;
+        |_matches, mize| {
+            let c2vi = mize.read_part_native::<C2vi>("c2vi")?;
             c2vi.with_db(|conn| {
                 let mut stmt = conn
                     .prepare("SELECT id, text FROM habitica_dailies")
@@ -203,9 +217,9 @@ fn register_subcommands(cli: &mut CliPart) {
         Command::new("printTask")
             .about("Print a specific task from Habitica")
             .arg(Arg::new("id").required(true)),
-        |matches, mut mize| {
+        |matches, mize| {
             let id = matches.get_one::<String>("id").unwrap();
-            let mut hab = mize.get_part_native::<Habitica>("habitica")?;
+            let hab = mize.read_part_native::<Habitica>("habitica")?;
             let data = hab.api_request(
                 reqwest::Method::GET,
                 format!("tasks/{}", id),
@@ -284,12 +298,12 @@ fn save_habitica_dailies(mize: &mut Mize) -> MizeResult<()> {
 
     // Fetch dailies from Habitica
     let dailies = {
-        let mut hab = mize.get_part_native::<Habitica>("habitica")?;
+        let hab = mize.read_part_native::<Habitica>("habitica")?;
         hab.get_tasks("dailys")?
     };
 
     // Write to database
-    let c2vi = mize.get_part_native::<C2vi>("c2vi")?;
+    let c2vi = mize.read_part_native::<C2vi>("c2vi")?;
     c2vi.with_db(|conn| {
         if let Some(arr) = dailies.as_array() {
             println!("Syncing {} dailies to database...", arr.len());
@@ -322,13 +336,13 @@ fn save_habitica_logs(mize: &mut Mize) -> MizeResult<()> {
 
     // Fetch dailies and completed todos from Habitica
     let (dailies, completed_todos) = {
-        let mut hab = mize.get_part_native::<Habitica>("habitica")?;
+        let hab = mize.read_part_native::<Habitica>("habitica")?;
         let dailies = hab.get_tasks("dailys")?;
         let completed_todos = hab.get_tasks("completedTodos")?;
         (dailies, completed_todos)
     };
 
-    let c2vi = mize.get_part_native::<C2vi>("c2vi")?;
+    let c2vi = mize.read_part_native::<C2vi>("c2vi")?;
     c2vi.with_db(|conn| {
         let mut data = get_or_create_log(conn, &date_str)?;
 
@@ -436,10 +450,9 @@ fn skip_habitica_todo(mize: &mut Mize, num: usize) -> MizeResult<()> {
     let (date_str, _) = get_target_date();
 
     // Fetch todos from Habitica
-    let todos = {
-        let mut hab = mize.get_part_native::<Habitica>("habitica")?;
-        hab.get_tasks("todos")?
-    };
+    let hab = mize.read_part_native::<Habitica>("habitica")?;
+    let c2vi = mize.read_part_native::<C2vi>("c2vi")?;
+    let todos = hab.get_tasks("todos")?;
 
     let arr = todos.as_array().ok_or_else(|| mize_err!("Expected array of todos"))?;
 
@@ -453,14 +466,10 @@ fn skip_habitica_todo(mize: &mut Mize, num: usize) -> MizeResult<()> {
         let todo_id = todo["id"].as_str().unwrap_or("");
 
         // Delete the todo and score the skip habit
-        {
-            let mut hab = mize.get_part_native::<Habitica>("habitica")?;
-            hab.delete_task(todo_id)?;
-        }
+        hab.delete_task(todo_id)?;
         increment_habit(mize)?;
 
         // Record in log
-        let c2vi = mize.get_part_native::<C2vi>("c2vi")?;
         c2vi.with_db(|conn| {
             let mut data = get_or_create_log(conn, &date_str)?;
             data["todos_skipped"].as_array_mut().unwrap().push(json!({
@@ -479,7 +488,7 @@ fn skip_habitica_todo(mize: &mut Mize, num: usize) -> MizeResult<()> {
 }
 
 fn increment_habit(mize: &mut Mize) -> MizeResult<()> {
-    let mut hab = mize.get_part_native::<Habitica>("habitica")?;
+    let hab = mize.read_part_native::<Habitica>("habitica")?;
     let habits = hab.get_tasks("habits")?;
     if let Some(arr) = habits.as_array() {
         for habit in arr {
@@ -544,7 +553,7 @@ bed 22:10";
     // Add tasks to Habitica
     let mut task_ids = Vec::new();
     {
-        let mut hab = mize.get_part_native::<Habitica>("habitica")?;
+        let hab = mize.read_part_native::<Habitica>("habitica")?;
         for todo_text in &tasks {
             let data = hab.api_request(
                 reqwest::Method::POST,
@@ -570,7 +579,7 @@ bed 22:10";
 }
 
 fn dump_habitica_logs(mize: &mut Mize) -> MizeResult<()> {
-    let c2vi = mize.get_part_native::<C2vi>("c2vi")?;
+    let c2vi = mize.read_part_native::<C2vi>("c2vi")?;
     c2vi.with_db(|conn| {
         let mut stmt = conn
             .prepare("SELECT date, data FROM habitica_log ORDER BY date")
@@ -1,7 +1,7 @@
 use clap::{ArgMatches, Command};
 use std::collections::HashMap;
 
-use mize::{mize_part, Mize, MizePart, MizeResult};
+use mize::{mize_part, BlockingRun, Mize, MizePart, MizeResult};
 
 #[mize_part("cli")]
 #[derive(Default)]
@@ -32,36 +32,46 @@ impl MizePart for CliPart {
     fn init(&mut self, _mize: &mut Mize) -> MizeResult<()> {
         Ok(())
     }
-    fn run(&mut self, mize: &mut Mize) -> MizeResult<()> {
-        let matches = self.cmd.take().unwrap().get_matches();
-        let sub_cmd = match matches.subcommand_name() {
-            Some(sub_cmd) => sub_cmd,
-            None => {
-                println!("No subcommand provided");
-                return Ok(());
-            }
-        };
-        let action = match self.actions.remove(sub_cmd) {
-            Some(action) => action,
-            None => {
-                // external parsers
-                for parser in self.parsers.take().unwrap() {
-                    parser(
-                        mize.clone(),
-                        matches
-                            .subcommand_matches(sub_cmd)
-                            .unwrap()
-                            .get_many::<String>("")
-                            .unwrap()
-                            .map(|s| s.to_string())
-                            .collect::<Vec<String>>(),
-                    )?;
+    // runs the command, without holding the part, so that the command can use it
+    fn run_blocks(&self) -> bool {
+        true
+    }
+    fn take_blocking_run(&mut self) -> Option<BlockingRun> {
+        let cmd = self.cmd.take()?;
+        let mut actions = std::mem::take(&mut self.actions);
+        let parsers = self.parsers.take().unwrap_or_default();
+
+        Some(Box::new(move |mize: &mut Mize| {
+            let matches = cmd.get_matches();
+            let sub_cmd = match matches.subcommand_name() {
+                Some(sub_cmd) => sub_cmd,
+                None => {
+                    println!("No subcommand provided");
+                    return Ok(());
                 }
-                return Ok(());
-            }
-        };
-        action(matches.subcommand_matches(sub_cmd).unwrap(), mize.clone())?;
-        Ok(())
+            };
+            let action = match actions.remove(sub_cmd) {
+                Some(action) => action,
+                None => {
+                    // external parsers
+                    for parser in parsers {
+                        parser(
+                            mize.clone(),
+                            matches
+                                .subcommand_matches(sub_cmd)
+                                .unwrap()
+                                .get_many::<String>("")
+                                .unwrap()
+                                .map(|s| s.to_string())
+                                .collect::<Vec<String>>(),
+                        )?;
+                    }
+                    return Ok(());
+                }
+            };
+            action(matches.subcommand_matches(sub_cmd).unwrap(), mize.clone())?;
+            Ok(())
+        }))
     }
     fn opts(&self, mize: &mut Mize) {
         mize.new_opt("cli.name");
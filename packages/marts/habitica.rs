@@ -10,6 +10,17 @@ use std::time::Duration;
 pub struct Habitica {
     mize: Mize,
     client: Client,
+    #[opt(
+        default = "https://habitica.com/api/v3",
+        doc = "the url of the Habitica API"
+    )]
+    api_url: String,
+    #[opt(doc = "the id of the Habitica user")]
+    user_id: String,
+    #[opt(doc = "the API token of the Habitica user")]
+    api_token: String,
+    #[opt(doc = "sent as x-client, <id of the author>-<name of the app>")]
+    client_name: String,
 }
 
 pub fn habitica(mize: &mut Mize) -> MizeResult<()> {
@@ -20,21 +31,14 @@ pub fn habitica(mize: &mut Mize) -> MizeResult<()> {
     }))
 }
 
-impl MizePart for Habitica {
-    fn opts(&self, mize: &mut Mize) {
-        mize.new_opt("habitica.api_url");
-        mize.new_opt("habitica.user_id");
-        mize.new_opt("habitica.api_token");
-        mize.new_opt("habitica.client_name");
-    }
-}
+impl MizePart for Habitica {}
 
 impl Habitica {
-    pub fn api_request(&mut self, method: Method, path: String, data: Value) -> MizeResult<Value> {
-        let api_url = self.mize.get_config("habitica.api_url")?.to_string();
-        let user_id = self.mize.get_config("habitica.user_id")?.to_string();
-        let api_token = self.mize.get_config("habitica.api_token")?.to_string();
-        let client_name = self.mize.get_config("habitica.client_name")?.to_string();
+    pub fn api_request(&self, method: Method, path: String, data: Value) -> MizeResult<Value> {
+        let api_url = self.api_url()?;
+        let user_id = self.user_id()?;
+        let api_token = self.api_token()?;
+        let client_name = self.client_name()?;
 
         let mut headers = header::HeaderMap::new();
         headers.insert(
@@ -73,7 +77,7 @@ impl Habitica {
         Ok(json_response.get("data").cloned().unwrap_or(Value::Null))
     }
 
-    pub fn get_tasks(&mut self, task_type: &str) -> MizeResult<Value> {
+    pub fn get_tasks(&self, task_type: &str) -> MizeResult<Value> {
         self.api_request(
             Method::GET,
             format!("tasks/user?type={}", task_type),
@@ -81,7 +85,7 @@ impl Habitica {
         )
     }
 
-    pub fn delete_task(&mut self, id: &str) -> MizeResult<Value> {
+    pub fn delete_task(&self, id: &str) -> MizeResult<Value> {
         self.api_request(Method::DELETE, format!("tasks/{}", id), json!({}))
     }
 }
@@ -8,7 +8,8 @@ use deno_core::{
 use deno_core::{FastStaticString, FsModuleLoader};
 use mize::async_trait;
 use mize::instance::MizePartCreate;
-use mize::{mize_part, Mize, MizePart, MizeResult};
+use mize::item::ItemData;
+use mize::{mize_err, mize_part, Mize, MizeError, MizePart, MizeResult};
 use std::future::Future;
 use std::path::Path;
 use std::pin::Pin;
@@ -83,6 +84,37 @@ impl JsPart {
             .unwrap();
         Ok(())
     }
+
+    // a computed item (see Mize::compute), whose value is the js expression, which has the values
+    // of the inputs in the array `inputs` (eg: "inputs.filter(day => day.done).length")
+    pub fn compute(&mut self, id: &str, inputs: Vec<&str>, expression: &str) -> MizeResult<()> {
+        let sender = self
+            .sender
+            .clone()
+            .ok_or(mize_err!("the js runtime thread is not running"))?;
+        let expression = expression.to_owned();
+
+        self.mize.compute(id, inputs, move |values| {
+            let values = values
+                .into_iter()
+                .map(|value| value.to_json())
+                .collect::<MizeResult<Vec<String>>>()?;
+            let code = format!(
+                "JSON.stringify(((inputs) => ({}))([{}]))",
+                expression,
+                values.join(",")
+            );
+
+            let (reply_tx, reply_rx) = flume::bounded(1);
+            sender
+                .send(JsRuntimeThreadMessage::Eval(code, reply_tx))
+                .map_err(|_| mize_err!("the js runtime thread is not running"))?;
+            let json = reply_rx
+                .recv()
+                .map_err(|_| mize_err!("the js runtime thread stopped"))??;
+            ItemData::from_json(json)
+        })
+    }
 }
 
 fn js_runtime_thread(
@@ -116,6 +148,17 @@ fn js_runtime_thread(
                 }
                 println!("done running js");
             }
+            JsRuntimeThreadMessage::Eval(js_code, reply) => {
+                let result = match js_runtime.execute_script("[compute]", js_code) {
+                    Ok(value) => {
+                        let scope = &mut js_runtime.handle_scope();
+                        let value = deno_core::v8::Local::new(scope, value);
+                        Ok(value.to_rust_string_lossy(scope))
+                    }
+                    Err(err) => Err(mize_err!("js expression failed: {}", err)),
+                };
+                let _ = reply.send(result);
+            }
             JsRuntimeThreadMessage::DoRunPhase => {
                 if let Err(err) = js_runtime.execute_script("[runPhase]", "mize.runPhase()") {
                     println!("err: {}", err);
@@ -132,5 +175,7 @@ enum JsRuntimeThreadMessage {
     //AsyncClosure(BoxClosure),
     RunInitJs(FastStaticString),
     RunJs(String),
+    // the result of a js expression, that returns a string
+    Eval(String, flume::Sender<MizeResult<String>>),
     DoRunPhase,
 }
@@ -20,3 +20,8 @@ pub use c2vi::*;
 pub mod js;
 #[cfg(feature = "target-os")]
 pub use js::*;
+
+#[cfg(feature = "target-os")]
+pub mod scheduler;
+#[cfg(feature = "target-os")]
+pub use scheduler::*;
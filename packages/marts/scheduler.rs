@@ -0,0 +1,535 @@
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use mize::item::ItemData;
+use mize::util::now_ms;
+use mize::{mize_err, mize_part, Mize, MizeError, MizePart, MizeResult};
+use serde_json::{json, Value};
+use std::thread::sleep;
+use std::time::Duration;
+
+// runs jobs at times of the day or in intervals, like cron
+//
+// the jobs are in the instance item at scheduler/jobs/<name>:
+//
+//   [scheduler.jobs.bed]
+//   cron = "30 22 * * *"     # minute hour day-of-month month day-of-week, in local time
+//   action = "c2vi.bed"      # an action added with Mize::add_rule_action()
+//
+//   [scheduler.jobs.logs]
+//   every = 3600             # seconds
+//   cli = ["save_habitica_logs"]  # a subcommand of this program (the CliPart)
+//   catch_up = false         # don't run it, if it's time was missed (default: true)
+//
+// the scheduler writes last_run, next_run (ms since the epoch) and last_error into the job
+// a run, that was missed while the instance was down, is done once, when it starts again
+// only the instance, that owns the namespace, runs the jobs, not every cli command connected to it
+
+#[mize_part("scheduler")]
+#[derive(Default)]
+pub struct Scheduler {
+    mize: Mize,
+}
+
+pub fn scheduler(mize: &mut Mize) -> MizeResult<()> {
+    mize.add_part(Box::new(Scheduler { mize: mize.clone() }))
+}
+
+impl MizePart for Scheduler {
+    fn run(&mut self, mize: &mut Mize) -> MizeResult<()> {
+        if !mize.we_are_namespace()? {
+            return Ok(());
+        }
+        let mize_clone = mize.clone();
+        mize.spawn_background("scheduler", move || scheduler_thread(mize_clone))
+    }
+}
+
+fn scheduler_thread(mize: Mize) -> MizeResult<()> {
+    while !mize.is_stopping()? {
+        let now = now_ms();
+        for job in jobs(&mize)? {
+            let name = job.name.clone();
+            if let Err(err) = tick(&mize, job, now) {
+                mize.report_err(err.msg(format!("scheduler job '{}' failed", name)));
+            }
+        }
+        sleep(Duration::from_secs(1));
+    }
+    Ok(())
+}
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    action: JobAction,
+    catch_up: bool,
+    next_run: Option<u64>,
+}
+
+enum Schedule {
+    Cron(Cron),
+    // in ms
+    Every(u64),
+}
+
+enum JobAction {
+    Action(String),
+    Cli(Vec<String>),
+}
+
+fn jobs(mize: &Mize) -> MizeResult<Vec<Job>> {
+    let jobs = match mize.get("0/scheduler/jobs")?.as_data_full() {
+        Ok(jobs) => serde_json::from_str::<Value>(jobs.to_json()?.as_str())?,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let jobs = match jobs {
+        Value::Object(jobs) => jobs,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut parsed = Vec::new();
+    for (name, job) in jobs {
+        match parse_job(name.as_str(), &job) {
+            Ok(job) => parsed.push(job),
+            Err(err) => mize.report_err(err),
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_job(name: &str, job: &Value) -> MizeResult<Job> {
+    let schedule = match (job.get("cron"), job.get("every")) {
+        (Some(Value::String(cron)), None) => Schedule::Cron(Cron::parse(cron)?),
+        (None, Some(Value::Number(every))) => {
+            let every = every.as_u64().filter(|every| *every > 0).ok_or(mize_err!(
+                "every of the job '{}' is not a number of seconds",
+                name
+            ))?;
+            Schedule::Every(every * 1000)
+        }
+        _ => {
+            return Err(mize_err!(
+                "the job '{}' needs either a cron expression or every",
+                name
+            ))
+        }
+    };
+
+    let action = match (job.get("action"), job.get("cli")) {
+        (Some(Value::String(action)), None) => JobAction::Action(action.clone()),
+        (None, Some(Value::Array(args))) if !args.is_empty() => JobAction::Cli(
+            args.iter()
+                .map(|arg| match arg {
+                    Value::String(arg) => arg.clone(),
+                    other => other.to_string(),
+                })
+                .collect(),
+        ),
+        _ => {
+            return Err(mize_err!(
+                "the job '{}' needs either an action or a cli subcommand",
+                name
+            ))
+        }
+    };
+
+    Ok(Job {
+        name: name.to_owned(),
+        schedule,
+        action,
+        catch_up: job.get("catch_up").and_then(Value::as_bool).unwrap_or(true),
+        next_run: job.get("next_run").and_then(Value::as_u64),
+    })
+}
+
+fn tick(mize: &Mize, job: Job, now: u64) -> MizeResult<()> {
+    let next_run = match job.next_run {
+        Some(next_run) => next_run,
+        // a new job, it's first run is the next time, that matches
+        None => {
+            let next_run = job.schedule.next_after(now)?;
+            return save(mize, &job.name, json!({ "next_run": next_run }));
+        }
+    };
+    if next_run > now {
+        return Ok(());
+    }
+
+    // missed by more than a minute, so the instance was down
+    let missed = now - next_run > 60_000;
+    let mut state = json!({ "next_run": job.schedule.next_after(now)? });
+    if missed && !job.catch_up {
+        return save(mize, &job.name, state);
+    }
+
+    let result = run_job(mize, &job);
+    state["last_run"] = json!(now);
+    state["last_error"] = match &result {
+        Ok(()) => Value::Null,
+        Err(err) => json!(err.messages.join("\n")),
+    };
+    save(mize, &job.name, state)?;
+    result
+}
+
+fn run_job(mize: &Mize, job: &Job) -> MizeResult<()> {
+    match &job.action {
+        JobAction::Action(action) => {
+            let id = mize.new_id(format!("0/scheduler/jobs/{}", job.name))?;
+            mize.call_rule_action(action, &id)
+        }
+        JobAction::Cli(args) => {
+            let program = std::env::current_exe()?;
+            let status = std::process::Command::new(&program)
+                .args(args)
+                .status()
+                .map_err(|err| mize_err!("could not run '{}': {}", program.display(), err))?;
+            if !status.success() {
+                return Err(mize_err!("'{}' exited with {}", args.join(" "), status));
+            }
+            Ok(())
+        }
+    }
+}
+
+fn save(mize: &Mize, name: &str, state: Value) -> MizeResult<()> {
+    mize.set_blocking(
+        format!("0/scheduler/jobs/{}", name),
+        ItemData::from_json(state.to_string())?,
+    )
+}
+
+impl Schedule {
+    fn next_after(&self, time: u64) -> MizeResult<u64> {
+        match self {
+            Schedule::Every(every) => Ok(time + every),
+            Schedule::Cron(cron) => {
+                let local = Local
+                    .timestamp_millis_opt(time as i64)
+                    .single()
+                    .ok_or(mize_err!("{} is not a valid time", time))?
+                    .naive_local();
+                cron.next_after(local, &Local)
+            }
+        }
+    }
+}
+
+// a cron expression: minute hour day-of-month month day-of-week
+// every field can be *, a number, a range (1-5), a step (*/15 or 1-30/2) or a list of those
+struct Cron {
+    minute: Vec<u32>,
+    hour: Vec<u32>,
+    day: Vec<u32>,
+    month: Vec<u32>,
+    weekday: Vec<u32>,
+    // with both restricted, one of them matching is enough (like in cron)
+    day_or_weekday: bool,
+}
+
+impl Cron {
+    fn parse(expr: &str) -> MizeResult<Cron> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(mize_err!(
+                "the cron expression '{}' doesn't have 5 fields (minute hour day month weekday)",
+                expr
+            ));
+        }
+
+        Ok(Cron {
+            minute: parse_field(fields[0], 0, 59)?,
+            hour: parse_field(fields[1], 0, 23)?,
+            day: parse_field(fields[2], 1, 31)?,
+            month: parse_field(fields[3], 1, 12)?,
+            // 7 is also sunday
+            weekday: parse_field(fields[4], 0, 7)?
+                .into_iter()
+                .map(|day| day % 7)
+                .collect(),
+            day_or_weekday: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.day.contains(&date.day());
+        let weekday = self
+            .weekday
+            .contains(&date.weekday().num_days_from_sunday());
+        if self.day_or_weekday {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    // the first matching minute after time (in tz), in ms since the epoch
+    fn next_after<Tz: TimeZone>(&self, time: NaiveDateTime, tz: &Tz) -> MizeResult<u64> {
+        let no_match = || mize_err!("the cron expression never matches");
+
+        let mut next = time
+            .with_second(0)
+            .and_then(|time| time.with_nanosecond(0))
+            .ok_or_else(no_match)?
+            + chrono::Duration::minutes(1);
+        // every expression, that can match, does so in 4 years (eg: on the 29th of february)
+        let end = next + chrono::Duration::days(366 * 4 + 1);
+
+        while next < end {
+            if !self.month.contains(&next.month()) || !self.matches_day(next.date()) {
+                next = next
+                    .date()
+                    .succ_opt()
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+                    .ok_or_else(no_match)?;
+            } else if !self.hour.contains(&next.hour()) {
+                next = next.with_minute(0).ok_or_else(no_match)? + chrono::Duration::hours(1);
+            } else if !self.minute.contains(&next.minute()) {
+                next += chrono::Duration::minutes(1);
+            } else {
+                // a time, that doesn't exist, because the clock is turned forward, is skipped
+                match tz.from_local_datetime(&next).earliest() {
+                    Some(local) => return Ok(local.timestamp_millis() as u64),
+                    None => next += chrono::Duration::minutes(1),
+                }
+            }
+        }
+        Err(no_match())
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> MizeResult<Vec<u32>> {
+    let invalid = || mize_err!("invalid cron field '{}' (allowed: {}-{})", field, min, max);
+
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+
+        let (from, to) = if range == "*" {
+            (min, max)
+        } else if let Some((from, to)) = range.split_once('-') {
+            (
+                from.parse().map_err(|_| invalid())?,
+                to.parse().map_err(|_| invalid())?,
+            )
+        } else {
+            let value: u32 = range.parse().map_err(|_| invalid())?;
+            // 5/10 is from 5 to the end, every 10
+            if part.contains('/') {
+                (value, max)
+            } else {
+                (value, value)
+            }
+        };
+        if from < min || to > max || from > to {
+            return Err(invalid());
+        }
+
+        values.extend((from..=to).step_by(step as usize));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{FixedOffset, LocalResult, Utc};
+    use std::sync::{Arc, Mutex};
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, 0))
+            .unwrap()
+    }
+
+    fn utc_ms(time: NaiveDateTime) -> u64 {
+        Utc.from_utc_datetime(&time).timestamp_millis() as u64
+    }
+
+    fn next(expr: &str, time: NaiveDateTime) -> MizeResult<u64> {
+        Cron::parse(expr)?.next_after(time, &Utc)
+    }
+
+    #[test]
+    fn test_parse_field() -> MizeResult<()> {
+        assert_eq!(parse_field("*", 0, 59)?, (0..=59).collect::<Vec<u32>>());
+        assert_eq!(parse_field("7", 0, 59)?, vec![7]);
+        assert_eq!(parse_field("1-5", 0, 59)?, vec![1, 2, 3, 4, 5]);
+        assert_eq!(parse_field("*/15", 0, 59)?, vec![0, 15, 30, 45]);
+        assert_eq!(parse_field("1-30/10", 0, 59)?, vec![1, 11, 21]);
+        assert_eq!(parse_field("5/20", 0, 59)?, vec![5, 25, 45]);
+        assert_eq!(parse_field("1,3,10-12", 1, 31)?, vec![1, 3, 10, 11, 12]);
+
+        for invalid in ["60", "*/0", "5-1", "0", "a", "1-", ""] {
+            assert!(parse_field(invalid, 1, 59).is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cron() -> MizeResult<()> {
+        // 7 is also sunday
+        assert_eq!(Cron::parse("* * * * 5-7")?.weekday, vec![5, 6, 0]);
+        assert!(Cron::parse("* * * *").is_err());
+        assert!(Cron::parse("* * * * * *").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_after() -> MizeResult<()> {
+        let monday = at(2026, 10, 19, 12, 0);
+        assert_eq!(
+            next("30 22 * * *", monday)?,
+            utc_ms(at(2026, 10, 19, 22, 30))
+        );
+        // strictly after
+        assert_eq!(
+            next("30 22 * * *", at(2026, 10, 19, 22, 30))?,
+            utc_ms(at(2026, 10, 20, 22, 30))
+        );
+        assert_eq!(
+            next("*/15 * * * *", monday)?,
+            utc_ms(at(2026, 10, 19, 12, 15))
+        );
+        assert_eq!(next("0 0 1 1 *", monday)?, utc_ms(at(2027, 1, 1, 0, 0)));
+        // the next 29th of february
+        assert_eq!(next("0 0 29 2 *", monday)?, utc_ms(at(2028, 2, 29, 0, 0)));
+        assert!(next("0 0 31 2 *", monday).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_day_or_weekday() -> MizeResult<()> {
+        let monday = at(2026, 10, 19, 12, 0);
+
+        // only one of them restricted, that one has to match
+        assert_eq!(next("0 0 25 * *", monday)?, utc_ms(at(2026, 10, 25, 0, 0)));
+        assert_eq!(next("0 0 * * 5", monday)?, utc_ms(at(2026, 10, 23, 0, 0)));
+        assert_eq!(next("0 0 * 11 1", monday)?, utc_ms(at(2026, 11, 2, 0, 0)));
+
+        // both restricted, the 25th or a friday, whichever is first
+        assert_eq!(next("0 0 25 * 5", monday)?, utc_ms(at(2026, 10, 23, 0, 0)));
+        assert_eq!(next("0 0 21 * 5", monday)?, utc_ms(at(2026, 10, 21, 0, 0)));
+        Ok(())
+    }
+
+    // central europe in 2026, so that the test doesn't have to set TZ for the whole process: the
+    // clock goes from 2:00 to 3:00 on the 29th of march and from 3:00 back to 2:00 on the 25th of
+    // october
+    #[derive(Clone)]
+    struct Cet2026;
+
+    impl TimeZone for Cet2026 {
+        type Offset = FixedOffset;
+
+        fn from_offset(_offset: &FixedOffset) -> Self {
+            Cet2026
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(12, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            // the offsets, that lead back to local, summer time first, it is the earlier one
+            let offsets: Vec<FixedOffset> = [2, 1]
+                .iter()
+                .map(|hours| FixedOffset::east_opt(hours * 3600).unwrap())
+                .filter(|offset| {
+                    let utc = *local - chrono::Duration::seconds(offset.local_minus_utc() as i64);
+                    self.offset_from_utc_datetime(&utc) == *offset
+                })
+                .collect();
+            match offsets[..] {
+                [] => LocalResult::None,
+                [offset] => LocalResult::Single(offset),
+                [summer, winter, ..] => LocalResult::Ambiguous(summer, winter),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let summer = *utc >= at(2026, 3, 29, 1, 0) && *utc < at(2026, 10, 25, 1, 0);
+            FixedOffset::east_opt(if summer { 2 * 3600 } else { 3600 }).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_dst_skip() -> MizeResult<()> {
+        let cron = Cron::parse("30 2 * * *")?;
+        let next = cron.next_after(at(2026, 3, 27, 12, 0), &Cet2026)?;
+        assert_eq!(next, utc_ms(at(2026, 3, 28, 1, 30)));
+        // 2:30 doesn't exist on the 29th
+        let next = cron.next_after(at(2026, 3, 28, 12, 0), &Cet2026)?;
+        assert_eq!(next, utc_ms(at(2026, 3, 30, 0, 30)));
+        // and exists twice on the 25th of october, the first one is taken
+        let next = cron.next_after(at(2026, 10, 24, 12, 0), &Cet2026)?;
+        assert_eq!(next, utc_ms(at(2026, 10, 25, 0, 30)));
+        Ok(())
+    }
+
+    fn job(catch_up: bool, next_run: Option<u64>) -> Job {
+        Job {
+            name: "test".to_owned(),
+            schedule: Schedule::Every(60_000),
+            action: JobAction::Action("count".to_owned()),
+            catch_up,
+            next_run,
+        }
+    }
+
+    fn saved_next_run(mize: &Mize) -> MizeResult<u64> {
+        let job = mize.get("0/scheduler/jobs/test")?.as_data_full()?;
+        let job = serde_json::from_str::<Value>(job.to_json()?.as_str())?;
+        job.get("next_run")
+            .and_then(Value::as_u64)
+            .ok_or(mize_err!("no next_run in {}", job))
+    }
+
+    #[test]
+    fn test_tick() -> MizeResult<()> {
+        let mize = Mize::empty()?;
+        let runs = Arc::new(Mutex::new(0));
+        let runs_clone = runs.clone();
+        mize.add_rule_action("count", move |_, _| {
+            *runs_clone.lock()? += 1;
+            Ok(())
+        })?;
+        mize.set_blocking(
+            "0/scheduler",
+            ItemData::from_toml("[jobs.test]\nevery = 60\naction = \"count\"")?,
+        )?;
+        let now = 1_800_000_000_000;
+
+        // a new job is only scheduled
+        tick(&mize, job(true, None), now)?;
+        assert_eq!(saved_next_run(&mize)?, now + 60_000);
+        assert_eq!(*runs.lock()?, 0);
+
+        // not yet
+        tick(&mize, job(true, Some(now + 1000)), now)?;
+        assert_eq!(*runs.lock()?, 0);
+
+        // on time
+        tick(&mize, job(false, Some(now - 1000)), now)?;
+        assert_eq!(*runs.lock()?, 1);
+        assert_eq!(saved_next_run(&mize)?, now + 60_000);
+
+        // missed while the instance was down, run once, unless catch_up is off
+        tick(&mize, job(true, Some(now - 3_600_000)), now)?;
+        assert_eq!(*runs.lock()?, 2);
+        tick(&mize, job(false, Some(now - 3_600_000)), now)?;
+        assert_eq!(*runs.lock()?, 2);
+        assert_eq!(saved_next_run(&mize)?, now + 60_000);
+
+        Ok(())
+    }
+}
@@ -1,19 +1,51 @@
 use crate::mize_err;
 use crate::MizeResult;
+use ciborium::Value as CborValue;
+use serde::de::DeserializeOwned;
+use std::any::type_name;
 use std::collections::HashMap;
 use std::env;
 use std::sync::{Arc, RwLock};
+use tracing::{debug, info};
 
 use crate::item::ItemData;
 use crate::Mize;
 use crate::MizeError;
 
+// the options of the parts
+//
+// a part declares it's options in opts() (or with #[opt] fields, see the mize_part macro), the
+// value of an option comes from, in this order:
+// - the env var MIZE_OPT_<NAME> (eg: MIZE_OPT_HABITICA_API_TOKEN for habitica.api_token), which
+//   is logged, when the parts are inited
+// - the config files in MIZE_CONFIG_FILES (eg: [habitica] api_token = "...")
+// - the thunk of the option (fun())
+// - the default of the option (default_val())
+//
+// an option can have a type (typed()) and validators (validate()), which are checked for every
+// value and for the values of the config files, env vars and defaults, when the parts are inited
+//
+// MIZE_CONFIG and MIZE_CONFIG_FILE are not options, they set the config of the instance
+// (self/config, see platform/os)
+// get_config_as() returns the value as a type, for an Option<T> a missing option is None
+
 type ConfigThunk = Box<dyn Fn() -> ItemData + Send + Sync>;
+type ConfigCheck = Arc<dyn Fn(&ItemData) -> MizeResult<()> + Send + Sync>;
 
+#[derive(Default)]
 pub struct ConfigOpt {
     pub name: String,
     pub val: Option<ItemData>,
-    pub thunk: Option<ConfigThunk>,
+    // an Arc, so that it can run without holding the lock of the options
+    pub thunk: Option<Arc<dyn Fn() -> ItemData + Send + Sync>>,
+    // used, when there is no val and no thunk
+    pub default: Option<ItemData>,
+    pub doc: Option<String>,
+    // where val is from, for error messages
+    pub source: Option<String>,
+    pub type_name: Option<&'static str>,
+    // the type check and the validators
+    pub checks: Vec<ConfigCheck>,
 }
 
 #[derive(Clone)]
@@ -23,42 +55,246 @@ pub struct ConfigOptNameAndMize {
 }
 
 impl ConfigOptNameAndMize {
+    // a value from a config file stays, also if it was read before the option was declared
     pub fn default_val(self, val: ItemData) -> ConfigOptNameAndMize {
         let mut config_opts = self.mize.config_opts.lock().unwrap();
         let opt = config_opts.get_mut(&self.name).unwrap();
-        opt.val = Some(val);
+        opt.default = Some(val);
+        self.clone()
+    }
+    pub fn doc(self, doc: &str) -> Self {
+        let mut config_opts = self.mize.config_opts.lock().unwrap();
+        let opt = config_opts.get_mut(&self.name).unwrap();
+        opt.doc = Some(doc.to_owned());
         self.clone()
     }
     pub fn fun(self, thunk: ConfigThunk) -> Self {
         let mut config_opts = self.mize.config_opts.lock().unwrap();
         let opt = config_opts.get_mut(&self.name).unwrap();
-        opt.thunk = Some(thunk);
+        opt.thunk = Some(Arc::from(thunk));
+        self.clone()
+    }
+    pub fn typed<T: DeserializeOwned>(self) -> Self {
+        let mut config_opts = self.mize.config_opts.lock().unwrap();
+        let opt = config_opts.get_mut(&self.name).unwrap();
+        opt.type_name = Some(type_name::<T>());
+        opt.checks
+            .push(Arc::new(|value| value.value_as::<T>().map(|_| ())));
+        self.clone()
+    }
+    // eg: .validate(|port: u16| if port > 1024 { Ok(()) } else { Err(mize_err!("...")) })
+    pub fn validate<T: DeserializeOwned>(
+        self,
+        func: impl Fn(T) -> MizeResult<()> + Send + Sync + 'static,
+    ) -> Self {
+        let mut config_opts = self.mize.config_opts.lock().unwrap();
+        let opt = config_opts.get_mut(&self.name).unwrap();
+        opt.checks
+            .push(Arc::new(move |value| func(value.value_as::<T>()?)));
         self.clone()
     }
 }
 
-pub fn gather_config(mize: &mut Mize) -> MizeResult<()> {
-    let mut config_opts = mize.config_opts.lock().unwrap();
-    let parts = mize.parts.lock().unwrap();
+impl Mize {
+    pub fn get_config_as<T: DeserializeOwned>(&self, name: &str) -> MizeResult<T> {
+        match config_value(self, name)? {
+            Some((value, source)) => value.value_as::<T>().map_err(|err| {
+                err.msg(format!(
+                    "the option '{}' (from {}) has the wrong type",
+                    name, source
+                ))
+            }),
+            // an Option<T> can be missing
+            None => ItemData::from_cbor(CborValue::Null)
+                .value_as::<T>()
+                .map_err(|_| missing_option(self, name)),
+        }
+    }
+}
+
+fn env_var_name(name: &str) -> String {
+    format!("MIZE_OPT_{}", name.to_uppercase().replace(['.', '-'], "_"))
+}
+
+// the value of an option and where it is from, None if it is not set
+pub(crate) fn config_value(mize: &Mize, name: &str) -> MizeResult<Option<(ItemData, String)>> {
+    let env_var = env_var_name(name);
+    if let Ok(text) = env::var(&env_var) {
+        let checks = match mize.config_opts.lock()?.get(name) {
+            Some(opt) => opt.checks.clone(),
+            None => Vec::new(),
+        };
+        return check(
+            name,
+            checks,
+            (
+                ItemData::from_string(text),
+                format!("the env var {}", env_var),
+            ),
+        );
+    }
+
+    // the thunk can take a while and read other options, so it runs without the lock
+    let thunk = match mize.config_opts.lock()?.get(name) {
+        Some(opt) if opt.val.is_none() => opt.thunk.clone(),
+        _ => None,
+    };
+    let computed = thunk.map(|thunk| thunk());
+
+    let (value, checks) = {
+        let mut config_opts = mize.config_opts.lock()?;
+        let opt = match config_opts.get_mut(name) {
+            Some(opt) => opt,
+            None => return Ok(None),
+        };
+        // evaluated once, unless another thread did so at the same time
+        if let (None, Some(computed)) = (&opt.val, computed) {
+            opt.val = Some(computed);
+            opt.source = Some("it's function".to_owned());
+            opt.thunk = None;
+        }
+        let value = match (&opt.val, &opt.default) {
+            (Some(val), _) => (
+                val.clone(),
+                opt.source.clone().unwrap_or("a config file".to_owned()),
+            ),
+            (None, Some(default)) => (default.clone(), "the default".to_owned()),
+            (None, None) => return Ok(None),
+        };
+        (value, opt.checks.clone())
+    };
 
-    for part in parts.values() {
-        part.as_deref().unwrap().opts(&mut mize.clone());
+    check(name, checks, value)
+}
+
+// not under the lock, a validator may read other options
+fn check(
+    name: &str,
+    checks: Vec<ConfigCheck>,
+    value: (ItemData, String),
+) -> MizeResult<Option<(ItemData, String)>> {
+    for check in checks {
+        check(&value.0).map_err(|err| {
+            err.msg(format!(
+                "the option '{}' (from {}) is not valid",
+                name, value.1
+            ))
+        })?;
+    }
+    Ok(Some(value))
+}
+
+pub(crate) fn missing_option(mize: &Mize, name: &str) -> MizeError {
+    let (doc, type_name) = match mize.config_opts.lock() {
+        Ok(config_opts) => match config_opts.get(name) {
+            Some(opt) => (opt.doc.clone(), opt.type_name),
+            None => (None, None),
+        },
+        Err(_) => (None, None),
+    };
+    let files = mize
+        .config_files
+        .lock()
+        .map(|files| files.clone())
+        .unwrap_or_default();
+
+    let mut msg = format!("the option '{}' is not set", name);
+    if let Some(type_name) = type_name {
+        msg += format!(", it's type is {}", short_type_name(type_name)).as_str();
+    }
+    if let Some(doc) = doc {
+        msg += format!(" ({})", doc).as_str();
     }
+    // a top-level key (without a dot) is not in a table
+    let place = match name.rsplit_once('.') {
+        Some((table, key)) => format!("as {} = ... in [{}]", key, table),
+        None => format!("as {} = ... at the top", name),
+    };
+    msg += format!(
+        "\nset it with the env var {} or {} of one of the config files",
+        env_var_name(name),
+        place
+    )
+    .as_str();
+    if files.is_empty() {
+        msg += " (no config files were read, they are listed in MIZE_CONFIG_FILES)";
+    } else {
+        msg += format!(" from MIZE_CONFIG_FILES: {}", files.join(", ")).as_str();
+    }
+    msg += "\nMIZE_CONFIG and MIZE_CONFIG_FILE don't set options, only the config of the instance \
+        (self/config)";
+    mize_err!("{}", msg)
+}
+
+// alloc::string::String -> String, core::option::Option<u16> -> Option<u16>
+fn short_type_name(type_name: &str) -> String {
+    let mut short = String::new();
+    let mut segment = String::new();
+    for char in type_name.chars() {
+        if char.is_alphanumeric() || char == '_' || char == ':' {
+            segment.push(char);
+        } else {
+            short += segment.rsplit("::").next().unwrap_or_default();
+            segment.clear();
+            short.push(char);
+        }
+    }
+    short += segment.rsplit("::").next().unwrap_or_default();
+    short
+}
+
+// the values from the config files, env vars and defaults are checked, when the parts have
+// declared their options, so that a wrong one is found at the start and not when it is first used
+// a thunk is not run for that
+pub(crate) fn check_all(mize: &Mize) -> MizeResult<()> {
+    let mut names: Vec<(String, bool)> = mize
+        .config_opts
+        .lock()?
+        .values()
+        .map(|opt| {
+            let from_env = env::var(env_var_name(&opt.name)).is_ok();
+            let checked =
+                !opt.checks.is_empty() && (opt.val.is_some() || from_env || opt.default.is_some());
+            (opt.name.clone(), from_env, checked)
+        })
+        .filter(|(_, from_env, checked)| *from_env || *checked)
+        .map(|(name, from_env, _)| (name, from_env))
+        .collect();
+    names.sort();
+
+    for (name, from_env) in names {
+        if from_env {
+            info!(
+                "the option '{}' is set by the env var {}",
+                name,
+                env_var_name(&name)
+            );
+        }
+        config_value(mize, name.as_str())?;
+    }
+    Ok(())
+}
+
+pub fn gather_config(mize: &mut Mize) -> MizeResult<()> {
+    // the opts() of the parts are called by Mize::init_parts() (see lifecycle.rs)
+    let mut config_opts = mize.config_opts.lock().unwrap();
 
     // populate values from config files
     let config_file_paths = env::var("MIZE_CONFIG_FILES")?;
     for config_file_path in config_file_paths.split(":") {
-        println!("reading config file: {config_file_path}");
+        debug!("reading config file: {config_file_path}");
+        mize.config_files.lock()?.push(config_file_path.to_owned());
         let content = std::fs::read_to_string(config_file_path)?;
         let mut data = ItemData::from_toml(content.as_str())?;
-        println!("config data: {data}");
+        debug!("config data: {data}");
         for path in data.get_paths_recursive()? {
             let conf_name = path.replace("/", ".");
-            println!("adding config {conf_name} from config file {config_file_path}");
+            debug!("adding config {conf_name} from config file {config_file_path}");
             let val = data.get_path(path.split("/").collect::<Vec<&str>>())?;
             match config_opts.get_mut(&conf_name) {
                 Some(opt) => {
                     opt.val = Some(val);
+                    opt.source = Some(config_file_path.to_owned());
                 }
                 None => {
                     config_opts.insert(
@@ -66,7 +302,8 @@ pub fn gather_config(mize: &mut Mize) -> MizeResult<()> {
                         ConfigOpt {
                             name: conf_name,
                             val: Some(val),
-                            thunk: None,
+                            source: Some(config_file_path.to_owned()),
+                            ..Default::default()
                         },
                     );
                 }
@@ -76,3 +313,15 @@ pub fn gather_config(mize: &mut Mize) -> MizeResult<()> {
 
     Ok(())
 }
+
+// a bool option can come as a cbor bool (from toml) or as text (from --config or MIZE_CONFIG)
+pub fn config_flag(instance: &Mize, id: &str) -> MizeResult<bool> {
+    match instance.get(id)?.as_data_full() {
+        Ok(data) => match data.cbor() {
+            CborValue::Bool(val) => Ok(*val),
+            CborValue::Text(text) => Ok(text == "true"),
+            _ => Ok(false),
+        },
+        Err(_) => Ok(false),
+    }
+}
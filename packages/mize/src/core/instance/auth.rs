@@ -0,0 +1,395 @@
+use ciborium::Value as CborValue;
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use crate::config::config_flag;
+use crate::error::{MizeError, MizeResult};
+use crate::id::Namespace;
+use crate::item::ItemData;
+use crate::mize_err;
+use crate::proto::{MessageCmd, MizeMessage};
+
+use super::Mize;
+
+// with the config option auth.enabled, every msg of a peer is checked against the acl, before it
+// touches the store or is routed on
+//
+// who a peer is, is decided by the transport:
+// - in process and stdio connections are the owner
+// - a peer on the unix socket is the owner, if it runs as our user, otherwise uid:<uid>
+// - a peer on a network transport (the websocket) is no one, until it answers our challenge with
+//   the secret of one of the keys in the config option auth.keys, then it is key:<name>
+// - a rest request is key:<name>, if it has the header `Authorization: Bearer <name>:<secret>`
+// - to answer a challenge ourselves, we use the config options auth.key and auth.secret
+//
+// without auth.enabled only inst/shutdown is checked, which only the owner may write then
+//
+// the owner may do everything, everyone else gets the rights of the acl rules, that match them
+// a rule is an item at self/acl/<name>, like:
+// { who = "key:laptop", prefix = "mize.home:3", rights = ["read", "write", "subscribe", "create"] }
+// who can also be "*" for anyone and the prefix "<namespace>:<path>" or a path in our namespace
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Principal {
+    Owner,
+    Uid(u32),
+    Key(String),
+}
+
+impl Principal {
+    pub fn as_string(&self) -> String {
+        match self {
+            Principal::Owner => "owner".to_owned(),
+            Principal::Uid(uid) => format!("uid:{}", uid),
+            Principal::Key(name) => format!("key:{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Right {
+    Read,
+    Write,
+    Subscribe,
+    Create,
+}
+
+impl Right {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Right::Read => "read",
+            Right::Write => "write",
+            Right::Subscribe => "subscribe",
+            Right::Create => "create",
+        }
+    }
+}
+
+pub fn auth_enabled(instance: &Mize) -> MizeResult<bool> {
+    config_flag(instance, "self/config/auth/enabled")
+}
+
+// items, that even without auth only a local peer (the owner) may write
+pub fn owner_only(instance: &Mize, namespace: &Namespace, path: &[String]) -> MizeResult<bool> {
+    Ok(instance.is_own_namespace(namespace)? && path == ["inst", "shutdown"])
+}
+
+// items, that no connection may write, not even the owner: the rules, because a rule can run a
+// command (see rules.rs), also not as a part of the whole item 0
+pub fn local_only(instance: &Mize, namespace: &Namespace, path: &[String]) -> MizeResult<bool> {
+    Ok(instance.is_own_namespace(namespace)?
+        && matches!(path.first().map(|part| part.as_str()), Some("0" | "self"))
+        && path.get(1).map_or(true, |part| part == "rules"))
+}
+
+// the rights a msg needs, replies and msgs, that don't touch items, need none
+fn needed_rights(msg: &mut MizeMessage, instance: &Mize) -> MizeResult<Vec<Right>> {
+    Ok(match msg.cmd()? {
+        MessageCmd::Get => vec![Right::Read],
+        MessageCmd::GetSub => vec![Right::Read, Right::Subscribe],
+        MessageCmd::Sub => vec![Right::Subscribe],
+        MessageCmd::UpdateRequest => vec![Right::Write],
+        MessageCmd::Create => vec![Right::Create],
+        // an Update of an item of another namespace comes from it's owner (see expected_peer),
+        // one of our own items would be written
+        MessageCmd::Update => {
+            let id = msg.id_with_ns(instance)?;
+            if instance.is_own_namespace(&id.namespace())? {
+                vec![Right::Write]
+            } else {
+                Vec::new()
+            }
+        }
+        // replies are only taken from the connection the request went out on (see the Give
+        // handling in updater.rs)
+        MessageCmd::Give
+        | MessageCmd::CreateReply
+        | MessageCmd::Routes
+        | MessageCmd::Auth
+        | MessageCmd::Denied => Vec::new(),
+    })
+}
+
+// some msgs need no rights, but may only come from certain peers
+fn expected_peer(msg: &mut MizeMessage, instance: &Mize) -> MizeResult<bool> {
+    Ok(match msg.cmd()? {
+        // a peer could pull the msgs for any namespace to itself with a route
+        MessageCmd::Routes => instance.get_connection(msg.conn_id)?.principal.is_some(),
+        // only the connection we reach the namespace over speaks for it's owner
+        MessageCmd::Update => {
+            let namespace = msg.id_with_ns(instance)?.namespace();
+            instance.is_own_namespace(&namespace)?
+                || instance
+                    .get_connection_by_ns(namespace)
+                    .is_ok_and(|conn| conn.id == msg.conn_id)
+        }
+        _ => true,
+    })
+}
+
+// returns false and answers with a Denied msg, if the peer may not do what msg asks for
+pub fn check_msg(msg: &mut MizeMessage, instance: &Mize) -> MizeResult<bool> {
+    let enabled = auth_enabled(instance)?;
+    let writes = matches!(msg.cmd()?, MessageCmd::UpdateRequest | MessageCmd::Update);
+    if !enabled && !writes {
+        return Ok(true);
+    }
+
+    if enabled && !expected_peer(msg, instance)? {
+        warn!(
+            "connection {}: dropping a {:?} msg, that it may not send",
+            msg.conn_id,
+            msg.cmd()?
+        );
+        return Ok(false);
+    }
+
+    let rights = needed_rights(msg, instance)?;
+    if rights.is_empty() {
+        return Ok(true);
+    }
+
+    let connection = instance.get_connection(msg.conn_id)?;
+
+    // a Create has no id, so it is checked against the namespace it is for
+    let (namespace, path) = match msg.cmd()? {
+        MessageCmd::Create => match msg.ns() {
+            Some(ns) => (instance.namespace_from_string(ns.to_owned())?, Vec::new()),
+            None => (instance.get_self_namespace()?, Vec::new()),
+        },
+        _ => {
+            let id = msg.id_with_ns(instance)?;
+            (id.namespace(), msg.id_str()?)
+        }
+    };
+
+    for right in rights {
+        let permitted = if right == Right::Write && local_only(instance, &namespace, &path)? {
+            false
+        } else if enabled {
+            allowed(
+                instance,
+                connection.principal.as_ref(),
+                &namespace,
+                &path,
+                right,
+            )?
+        } else {
+            !owner_only(instance, &namespace, &path)?
+                || connection.principal == Some(Principal::Owner)
+        };
+        if permitted {
+            continue;
+        }
+
+        let who = connection
+            .principal
+            .as_ref()
+            .map(|principal| principal.as_string())
+            .unwrap_or("an unauthenticated peer".to_owned());
+        let reason = format!(
+            "{} may not {} '{}:{}'",
+            who,
+            right.name(),
+            namespace.as_string(),
+            path.join("/")
+        );
+        warn!("connection {}: {}", msg.conn_id, reason);
+
+        let id = match msg.cmd()? {
+            MessageCmd::Create => None,
+            _ => Some(msg.id_str()?),
+        };
+        let reply = MizeMessage::new_denied(id, reason, writes, msg.conn_id);
+        connection.send(super::routing::with_request_ns(reply, msg, instance)?)?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+pub fn allowed(
+    instance: &Mize,
+    principal: Option<&Principal>,
+    namespace: &Namespace,
+    path: &[String],
+    right: Right,
+) -> MizeResult<bool> {
+    if principal == Some(&Principal::Owner) {
+        return Ok(true);
+    }
+    let who = principal.map(|principal| principal.as_string());
+
+    let rules = match instance.get("self/acl")?.as_data_full() {
+        Ok(ItemData(CborValue::Map(rules))) => rules,
+        _ => return Ok(false),
+    };
+
+    for (_, rule) in rules {
+        let rule = ItemData::from_cbor(rule);
+        let rule_who = rule.get_path("who")?.value_string().unwrap_or_default();
+        if rule_who != "*" && Some(&rule_who) != who.as_ref() {
+            continue;
+        }
+
+        let grants = match rule.get_path("rights")?.cbor() {
+            CborValue::Array(rights) => rights.contains(&CborValue::Text(right.name().to_owned())),
+            _ => false,
+        };
+        if !grants {
+            continue;
+        }
+
+        let prefix = rule.get_path("prefix")?.value_string().unwrap_or_default();
+        if prefix_matches(instance, &prefix, namespace, path)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn prefix_matches(
+    instance: &Mize,
+    prefix: &str,
+    namespace: &Namespace,
+    path: &[String],
+) -> MizeResult<bool> {
+    // we have more than one name for our own namespace
+    let ns_matches = match prefix.split_once(':') {
+        Some((ns, _)) => {
+            let rule_ns = instance.namespace_from_string(ns.to_owned())?;
+            &rule_ns == namespace
+                || (instance.is_own_namespace(&rule_ns)? && instance.is_own_namespace(namespace)?)
+        }
+        None => instance.is_own_namespace(namespace)?,
+    };
+    if !ns_matches {
+        return Ok(false);
+    }
+
+    let rule_path = prefix.split_once(':').map_or(prefix, |(_, path)| path);
+
+    let rule_path: Vec<&str> = rule_path
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect();
+    Ok(rule_path.len() <= path.len()
+        && rule_path
+            .iter()
+            .zip(path.iter())
+            .all(|(rule_part, part)| rule_part == part))
+}
+
+////// the key handshake
+
+// ask the peer on conn_id to prove, that it knows the secret of a key
+pub fn challenge(instance: &Mize, conn_id: u64) -> MizeResult<()> {
+    if !auth_enabled(instance)? {
+        return Ok(());
+    }
+
+    let nonce = new_nonce()?;
+    let mut connection = instance.get_connection(conn_id)?;
+    connection.challenge = Some(nonce.clone());
+    instance.set_connection(conn_id, connection.clone())?;
+
+    let data = CborValue::Map(vec![(
+        CborValue::Text("nonce".to_owned()),
+        CborValue::Text(nonce),
+    )]);
+    connection.send(MizeMessage::new_auth(data, conn_id))
+}
+
+pub fn handle_auth_msg(msg: &mut MizeMessage, instance: &Mize) -> MizeResult<()> {
+    let data = msg.data()?;
+    let mut connection = instance.get_connection(msg.conn_id)?;
+
+    // we are challenged
+    if let Ok(nonce) = data.get_path("nonce")?.value_string() {
+        let key = instance.get("self/config/auth/key")?.value_string();
+        let secret = instance.get("self/config/auth/secret")?.value_string();
+        let (key, secret) = match (key, secret) {
+            (Ok(key), Ok(secret)) => (key, secret),
+            _ => {
+                debug!(
+                    "connection {} wants us to authenticate, but auth.key and auth.secret are not set",
+                    msg.conn_id
+                );
+                return Ok(());
+            }
+        };
+
+        let data = CborValue::Map(vec![
+            (CborValue::Text("key".to_owned()), CborValue::Text(key)),
+            (
+                CborValue::Text("proof".to_owned()),
+                CborValue::Text(proof(&nonce, &secret)),
+            ),
+        ]);
+        return connection.send(MizeMessage::new_auth(data, msg.conn_id));
+    }
+
+    // the answer to our challenge
+    let key = data.get_path("key")?.value_string()?;
+    let peer_proof = data.get_path("proof")?.value_string()?;
+    let nonce = connection.challenge.take().ok_or(mize_err!(
+        "connection {} answered a challenge, that we did not send",
+        msg.conn_id
+    ))?;
+
+    let secret = instance
+        .get(format!("self/config/auth/keys/{}", key))?
+        .value_string();
+    match secret {
+        Ok(secret) if constant_time_eq(&proof(&nonce, &secret), &peer_proof) => {
+            info!("connection {} authenticated as key '{}'", msg.conn_id, key);
+            connection.principal = Some(Principal::Key(key));
+        }
+        _ => warn!(
+            "connection {} failed to authenticate as key '{}'",
+            msg.conn_id, key
+        ),
+    }
+
+    // a nonce is only good for one try
+    instance.set_connection(msg.conn_id, connection)
+}
+
+// for transports without a handshake, that get the secret itself (eg: the rest api over https)
+pub fn key_principal(instance: &Mize, key: &str, secret: &str) -> MizeResult<Option<Principal>> {
+    let expected = instance
+        .get(format!("self/config/auth/keys/{}", key))?
+        .value_string();
+    Ok(match expected {
+        Ok(expected) if constant_time_eq(&expected, secret) => Some(Principal::Key(key.to_owned())),
+        _ => None,
+    })
+}
+
+fn proof(nonce: &str, secret: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(nonce.as_bytes());
+    hasher.update(b":");
+    hasher.update(secret.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn new_nonce() -> MizeResult<String> {
+    let mut nonce = [0u8; 32];
+    getrandom::getrandom(&mut nonce)
+        .map_err(|err| mize_err!("could not get random bytes for a nonce: {}", err))?;
+    Ok(nonce.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
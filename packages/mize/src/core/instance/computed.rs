@@ -0,0 +1,261 @@
+use ciborium::Value as CborValue;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::{MizeError, MizeResult};
+use crate::id::{IntoMizeId, MizeId};
+use crate::item::ItemData;
+use crate::mize_err;
+
+use super::introspect;
+use super::provider::Provider;
+use super::subscription::{Subscription, Update};
+use super::worker::{self, Worker};
+use super::Mize;
+
+// items, whose value is a function of other items (eg: a habit streak computed from the daily
+// log items)
+//
+// Mize::compute() registers a function of the values of a list of input ids, the js part has the
+// same for a js expression
+// the instance subs to every input, one thread (the "computed" thread) recomputes the items, that
+// depend on an input, when it changes, and notifies the subscribers of a computed item, when it's
+// value changed
+// a computed item can be the input of another one, but an item can't depend on itself, also not
+// over other computed items
+//
+// the value is served by a provider (see provider.rs) and kept until an input changes
+// the computed items and their inputs are readable as inst/computed
+
+pub type ComputeFn = Arc<dyn Fn(Vec<ItemData>) -> MizeResult<ItemData> + Send + Sync>;
+
+struct Computed {
+    inputs: Vec<MizeId>,
+    func: ComputeFn,
+    // None until it is first read, or if the last try failed
+    value: Option<ItemData>,
+}
+
+#[derive(Default)]
+pub struct ComputedItems {
+    items: HashMap<MizeId, Computed>,
+    // the subscription to every input sends to the "computed" thread
+    pub(crate) updates: Worker<Update>,
+}
+
+impl ComputedItems {
+    // would id depend on itself, if it had these inputs
+    fn is_cycle(&self, id: &MizeId, inputs: &[MizeId]) -> bool {
+        is_cycle(id, inputs, |input| match self.items.get(input) {
+            Some(computed) => computed.inputs.clone(),
+            None => Vec::new(),
+        })
+    }
+
+    // the computed items, that use input
+    fn dependents(&self, input: &MizeId) -> Vec<MizeId> {
+        self.items
+            .iter()
+            .filter(|(_, computed)| computed.inputs.contains(input))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+impl Mize {
+    pub fn compute<I: IntoMizeId, J: IntoMizeId>(
+        &self,
+        id: I,
+        inputs: Vec<J>,
+        func: impl Fn(Vec<ItemData>) -> MizeResult<ItemData> + Send + Sync + 'static,
+    ) -> MizeResult<()> {
+        let id = id.to_mize_id(self)?;
+        let inputs = inputs
+            .into_iter()
+            .map(|input| input.to_mize_id(self))
+            .collect::<MizeResult<Vec<MizeId>>>()?;
+
+        let mut computed_inner = self.computed.lock()?;
+        if computed_inner.items.contains_key(&id) {
+            return Err(mize_err!("'{}' is already a computed item", id));
+        }
+        if computed_inner.is_cycle(&id, &inputs) {
+            return Err(mize_err!(
+                "the computed item '{}' would depend on itself over it's inputs",
+                id
+            ));
+        }
+        computed_inner.items.insert(
+            id.clone(),
+            Computed {
+                inputs: inputs.clone(),
+                func: Arc::new(func),
+                value: None,
+            },
+        );
+
+        let updates = computed_inner
+            .updates
+            .sender(self, "computed", handle_update)?;
+        drop(computed_inner);
+
+        let path: Vec<String> = id.path().into_iter().map(|p| p.to_owned()).collect();
+        self.add_provider(path.join("/").as_str(), ComputedProvider { id: id.clone() })?;
+
+        for input in inputs {
+            let updates = updates.clone();
+            self.sub(
+                input,
+                Subscription::from_closure(Box::new(move |update| worker::send(&updates, update))),
+            )?;
+        }
+
+        introspect::changed(self, "computed")
+    }
+}
+
+// the value of id, computed now
+fn compute_now(instance: &Mize, id: &MizeId) -> MizeResult<ItemData> {
+    // the inputs may be on a peer, so don't hold the lock, while we get them
+    let (inputs, func) = {
+        let computed_inner = instance.computed.lock()?;
+        let computed = computed_inner
+            .items
+            .get(id)
+            .ok_or(mize_err!("'{}' is not a computed item", id))?;
+        (computed.inputs.clone(), computed.func.clone())
+    };
+
+    let values = inputs
+        .into_iter()
+        .map(|input| instance.get(input)?.as_data_full())
+        .collect::<MizeResult<Vec<ItemData>>>()?;
+
+    func(values).map_err(|err| err.msg(format!("computing '{}' failed", id)))
+}
+
+// recompute id and tell it's subscribers, if the value changed
+fn recompute(instance: &Mize, id: &MizeId) -> MizeResult<()> {
+    let result = compute_now(instance, id);
+
+    let changed = {
+        let mut computed_inner = instance.computed.lock()?;
+        let computed = match computed_inner.items.get_mut(id) {
+            Some(computed) => computed,
+            None => return Ok(()),
+        };
+        let new_value = result.as_ref().ok().cloned();
+        let changed = computed.value != new_value;
+        computed.value = new_value;
+        changed
+    };
+
+    if changed {
+        instance.changed(id.clone())?;
+    }
+    result.map(|_| ())
+}
+
+// on the "computed" thread
+fn handle_update(instance: &Mize, update: Update) -> MizeResult<()> {
+    let dependents = instance.computed.lock()?.dependents(&update.id);
+    for id in dependents {
+        if let Err(err) = recompute(instance, &id) {
+            instance.report_err(err);
+        }
+    }
+    Ok(())
+}
+
+// would id depend on itself, if it had these inputs, inputs_of() gives the inputs of an other id
+// (also used for the rules, where the item, that a rule sets, depends on the one it watches)
+pub(crate) fn is_cycle(
+    id: &MizeId,
+    inputs: &[MizeId],
+    inputs_of: impl Fn(&MizeId) -> Vec<MizeId>,
+) -> bool {
+    let mut todo: Vec<MizeId> = inputs.to_vec();
+    let mut seen: Vec<MizeId> = Vec::new();
+    while let Some(input) = todo.pop() {
+        if &input == id {
+            return true;
+        }
+        if seen.contains(&input) {
+            continue;
+        }
+        todo.extend(inputs_of(&input));
+        seen.push(input);
+    }
+    false
+}
+
+// serves the value of one computed item
+struct ComputedProvider {
+    id: MizeId,
+}
+
+impl Provider for ComputedProvider {
+    fn handles(&self, instance: &Mize, id: &MizeId) -> MizeResult<bool> {
+        Ok(id.namespace() == self.id.namespace())
+    }
+
+    fn get(&self, instance: &Mize, id: &MizeId) -> MizeResult<ItemData> {
+        let cached = match instance.computed.lock()?.items.get(&self.id) {
+            Some(computed) => computed.value.clone(),
+            None => return Err(mize_err!("'{}' is not a computed item", self.id)),
+        };
+
+        let value = match cached {
+            Some(value) => value,
+            None => {
+                let value = compute_now(instance, &self.id)?;
+                if let Some(computed) = instance.computed.lock()?.items.get_mut(&self.id) {
+                    computed.value = Some(value.clone());
+                }
+                value
+            }
+        };
+
+        // the rest of the path, after the computed id
+        let rest: Vec<String> = id
+            .path()
+            .into_iter()
+            .skip(self.id.path().into_iter().count())
+            .map(|p| p.to_owned())
+            .collect();
+        value.get_path(rest)
+    }
+
+    fn set(&self, instance: &Mize, id: &MizeId, data: ItemData) -> MizeResult<()> {
+        Err(mize_err!(
+            "'{}' is a computed item, it can't be written",
+            id
+        ))
+    }
+}
+
+// inst/computed: id -> it's inputs
+pub(crate) fn inst_item(instance: &Mize) -> MizeResult<CborValue> {
+    let mut items: Vec<(String, Vec<CborValue>)> = instance
+        .computed
+        .lock()?
+        .items
+        .iter()
+        .map(|(id, computed)| {
+            let inputs = computed
+                .inputs
+                .iter()
+                .map(|input| CborValue::Text(format!("{}", input)))
+                .collect();
+            (format!("{}", id), inputs)
+        })
+        .collect();
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(CborValue::Map(
+        items
+            .into_iter()
+            .map(|(id, inputs)| (CborValue::Text(id), CborValue::Array(inputs)))
+            .collect(),
+    ))
+}
@@ -6,15 +6,26 @@ use crate::id::Namespace;
 use crate::mize_err;
 use crate::proto::MizeMessage;
 
+use super::auth::Principal;
+use super::queue::{self, Queue};
+use super::recorder::{Direction, Recorder};
+use super::updater::Operation;
 use super::Mize;
 use crate::item::{get_raw_from_cbor, Item, ItemData};
 
 // only created with Instance::new_connection()
 #[derive(Clone, Debug)]
 pub struct Connection {
-    pub tx: Sender<MizeMessage>,
+    pub(crate) queue: Queue<MizeMessage>,
     pub id: u64,
     pub ns: Option<Namespace>,
+    pub(crate) recorder: Recorder,
+    // to ask the updater to disconnect us, when our queue is full
+    pub(crate) ops: Queue<Operation>,
+    // who the peer is, see auth.rs
+    pub principal: Option<Principal>,
+    // the nonce of the auth challenge we sent, that the peer did not answer yet
+    pub(crate) challenge: Option<String>,
 }
 
 pub trait ConnListener: Send + Sync {
@@ -23,7 +34,19 @@ pub trait ConnListener: Send + Sync {
 
 impl Connection {
     pub fn send(&self, msg: MizeMessage) -> MizeResult<()> {
-        Ok(self.tx.send(msg)?)
+        self.recorder.record(Direction::Out, self.id, &msg);
+        if !self.queue.push(msg, queue::same_update)? {
+            warn!(
+                "send queue of connection {} is full, disconnecting",
+                self.id
+            );
+            self.ops.push_unbounded(Operation::Disconnect(self.id))?;
+            return Err(mize_err!(
+                "send queue of connection {} is full, it is disconnected",
+                self.id
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -49,9 +72,9 @@ pub fn value_raw_from_peer(item: &mut Item) -> MizeResult<ItemData> {
 
     let msg = MizeMessage::new_get(new_id.clone(), connection.id);
 
-    connection.tx.send(msg)?;
-
-    let data = item.instance.give_msg_wait(new_id)?;
+    let data = item
+        .instance
+        .give_msg_wait(new_id, conn_id, move || connection.send(msg))?;
 
     return Ok(data);
 }
@@ -0,0 +1,69 @@
+use flume::r#async::RecvStream;
+
+use crate::error::MizeResult;
+use crate::id::IntoMizeId;
+use crate::item::{Item, ItemData};
+
+use super::subscription::{Subscription, Update};
+use super::updater::{self, handle_operation, Operation};
+use super::{validator, InstanceAsync, Mize};
+
+// the public API for async code (eg: async parts or the web listener)
+//
+// everything, that waits for a peer (a Get or Create msg) or for space in the operation queue, is
+// awaited with recv_async() instead of blocking, so it never blocks a thread of the runtime and
+// doesn't need spawn_async_blocking() inside of a runtime
+//
+// what is done right away (the store, providers, validators) is the same as for Mize
+
+impl Mize {
+    pub fn as_async(&self) -> InstanceAsync {
+        InstanceAsync {
+            inner: self.clone(),
+        }
+    }
+}
+
+impl InstanceAsync {
+    pub fn new(instance: &Mize) -> InstanceAsync {
+        instance.as_async()
+    }
+
+    // the blocking API of the same instance
+    pub fn instance(&self) -> &Mize {
+        &self.inner
+    }
+
+    pub async fn get<I: IntoMizeId>(&self, id: I) -> MizeResult<ItemData> {
+        let id = id.to_mize_id(&self.inner)?;
+        Item::new(id, &self.inner).as_data_full_async().await
+    }
+
+    pub async fn set<I: IntoMizeId, V: Into<ItemData>>(&self, id: I, value: V) -> MizeResult<()> {
+        let id = id.to_mize_id(&self.inner)?;
+        let value = validator::validate(&self.inner, &id, value.into(), false)?;
+        let mut operation = Operation::Set(id, value, None);
+
+        // an updater thread can't wait for space in the queue it empties itself
+        if updater::on_updater_thread() {
+            return handle_operation(&mut operation, &self.inner);
+        }
+        self.inner.ops.push_async(operation).await
+    }
+
+    pub async fn new_item(&self) -> MizeResult<Item<'_>> {
+        if self.inner.get_namespace()? != self.inner.get_self_namespace()? {
+            let id = self.inner.request_create()?.recv_async().await??;
+            return Ok(Item::new(id, &self.inner));
+        }
+
+        self.inner.new_local_item()
+    }
+
+    // a futures Stream of the updates to id, the subscription ends, when the stream is dropped
+    pub fn sub<I: IntoMizeId>(&self, id: I) -> MizeResult<RecvStream<'static, Update>> {
+        let (tx, rx) = flume::unbounded::<Update>();
+        self.inner.sub(id, Subscription::from_sender(tx))?;
+        Ok(rx.into_stream())
+    }
+}
@@ -0,0 +1,178 @@
+use ciborium::Value as CborValue;
+
+use crate::error::MizeResult;
+use crate::instance::subscription::Subscription;
+use crate::item::ItemData;
+
+use super::{computed, updater, Mize};
+
+// the state of the instance as items, to look at it while debugging (eg: `mize get inst/threads`)
+//
+// - inst/connections: id -> {ns, principal, queue}
+// - inst/subs: id -> the kinds of subscriptions to it (connection:<id>, closure or channel)
+// - inst/threads: id -> name, a thread is removed, when it stops
+// - inst/modules: the names of the loaded modules
+// - inst/parts: name -> available, read or written (see part.rs)
+// - inst/config_opts: name -> the value, null if it was not evaluated yet
+// - inst/mounts: name -> {from, to, read_only} (see mount.rs)
+// - inst/computed: id -> the ids of it's inputs (see computed.rs)
+//
+// all of them, except config_opts, can be subscribed to
+
+pub fn inst_item(instance: &Mize, name: &str, path: Vec<String>) -> MizeResult<Option<ItemData>> {
+    let data = match name {
+        "connections" => connections(instance)?,
+        "subs" => subs(instance)?,
+        "threads" => threads(instance)?,
+        "modules" => modules(instance)?,
+        "parts" => parts(instance)?,
+        "config_opts" => config_opts(instance)?,
+        "mounts" => mounts(instance)?,
+        "computed" => computed::inst_item(instance)?,
+        _ => return Ok(None),
+    };
+    Ok(Some(ItemData::from_cbor(data).get_path(path)?))
+}
+
+// tell the subscribers of inst/<name>, that it changed
+// must not be called, while holding the lock it reads from
+pub(crate) fn changed(instance: &Mize, name: &str) -> MizeResult<()> {
+    let id = instance.new_id(format!("inst/{}", name))?;
+    // this is called a lot (eg: on every lock of a part), mostly with no one listening
+    if !instance.subs.lock()?.contains_key(&id) {
+        return Ok(());
+    }
+    updater::notify_subs(instance, &id, &None)
+}
+
+fn text(string: impl Into<String>) -> CborValue {
+    CborValue::Text(string.into())
+}
+
+fn connections(instance: &Mize) -> MizeResult<CborValue> {
+    let connections = instance
+        .connections
+        .lock()?
+        .iter()
+        .map(|conn| {
+            let ns = match &conn.ns {
+                Some(ns) => text(ns.as_real_string()),
+                None => CborValue::Null,
+            };
+            let principal = match &conn.principal {
+                Some(principal) => text(principal.as_string()),
+                None => CborValue::Null,
+            };
+            (
+                text(format!("{}", conn.id)),
+                CborValue::Map(vec![
+                    (text("ns"), ns),
+                    (text("principal"), principal),
+                    (text("queue"), CborValue::from(conn.queue.len() as u64)),
+                ]),
+            )
+        })
+        .collect();
+    Ok(CborValue::Map(connections))
+}
+
+fn subs(instance: &Mize) -> MizeResult<CborValue> {
+    let mut subs: Vec<(String, Vec<CborValue>)> = instance
+        .subs
+        .lock()?
+        .iter()
+        .filter(|(_, vec)| !vec.is_empty())
+        .map(|(id, vec)| {
+            let kinds = vec
+                .iter()
+                .map(|sub| match sub {
+                    Subscription::Connection(conn) => text(format!("connection:{}", conn.id)),
+                    Subscription::Closure(_) => text("closure"),
+                    Subscription::Channel(_) => text("channel"),
+                })
+                .collect();
+            (format!("{}", id), kinds)
+        })
+        .collect();
+    subs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(CborValue::Map(
+        subs.into_iter()
+            .map(|(id, kinds)| (text(id), CborValue::Array(kinds)))
+            .collect(),
+    ))
+}
+
+fn threads(instance: &Mize) -> MizeResult<CborValue> {
+    let threads = instance
+        .threads
+        .lock()?
+        .iter()
+        .map(|(id, name, _)| (text(format!("{}", id)), text(name.as_str())))
+        .collect();
+    Ok(CborValue::Map(threads))
+}
+
+fn modules(instance: &Mize) -> MizeResult<CborValue> {
+    let mut names: Vec<String> = instance.modules.lock()?.keys().cloned().collect();
+    names.sort();
+    Ok(CborValue::Array(names.into_iter().map(text).collect()))
+}
+
+fn parts(instance: &Mize) -> MizeResult<CborValue> {
+    let mut parts = instance
+        .parts
+        .lock()?
+        .iter()
+        .map(|(name, lock)| Ok((*name, lock.state()?)))
+        .collect::<MizeResult<Vec<(&'static str, &'static str)>>>()?;
+    parts.sort();
+
+    Ok(CborValue::Map(
+        parts
+            .into_iter()
+            .map(|(name, state)| (text(name), text(state)))
+            .collect(),
+    ))
+}
+
+fn config_opts(instance: &Mize) -> MizeResult<CborValue> {
+    let mut opts: Vec<(String, CborValue)> = instance
+        .config_opts
+        .lock()?
+        .values()
+        .map(|opt| {
+            let val = match &opt.val {
+                Some(val) => val.cbor().clone(),
+                None => CborValue::Null,
+            };
+            (opt.name.clone(), val)
+        })
+        .collect();
+    opts.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(CborValue::Map(
+        opts.into_iter()
+            .map(|(name, val)| (text(name), val))
+            .collect(),
+    ))
+}
+
+fn mounts(instance: &Mize) -> MizeResult<CborValue> {
+    let mounts = instance
+        .mounts
+        .lock()?
+        .iter()
+        .map(|mount| {
+            (
+                text(mount.name.as_str()),
+                CborValue::Map(vec![
+                    (text("from"), CborValue::from(mount.from)),
+                    (text("to"), CborValue::from(mount.to)),
+                    (text("read_only"), CborValue::Bool(mount.read_only)),
+                ]),
+            )
+        })
+        .collect();
+    Ok(CborValue::Map(mounts))
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use crate::config;
+use crate::error::{MizeError, MizeResult};
+use crate::mize_err;
+
+use super::Mize;
+
+// the lifecycle of the parts
+//
+// the parts are ordered by their deps(): a part comes after the parts it depends on, parts
+// without a dependency between them stay in the order they were added
+// a dependency on a part, that was not added, or parts depending on each other is an error
+//
+// in that order:
+// - Mize::init_parts() (called by Mize::init(), Mize::run() and add_parts!) calls opts() of every
+//   new part, so that all options exist before a part reads one, checks the values of the options
+//   (see config.rs), then calls init() and async_init()
+// - Mize::run() calls run() and async_run() and then waits for the threads of the instance
+//   a part, whose run blocks (run_blocks()), and the parts depending on it are run last, so
+//   that the others don't wait for it, what blocks (take_blocking_run()) runs without holding
+//   the part
+// - shutdown() is called in the reverse order, by Mize::shutdown() or, for a part, that was used
+//   then, at the end of Mize::run()
+//
+// a part is written (see part.rs) while one of it's methods runs, opts() only reads it
+// the async methods are run on the runtime of the instance, also when a part is inited or run
+// from a thread of a runtime
+
+#[derive(Default)]
+pub struct Lifecycle {
+    // the deps of every part, that was added
+    deps: HashMap<&'static str, &'static [&'static str]>,
+    // in the order they were inited
+    inited: Vec<&'static str>,
+    shut_down: Vec<&'static str>,
+}
+
+impl Lifecycle {
+    pub(crate) fn added(&mut self, name: &'static str, deps: &'static [&'static str]) {
+        self.deps.insert(name, deps);
+    }
+}
+
+impl Mize {
+    // the names of the parts, a part after it's dependencies
+    pub fn part_order(&self) -> MizeResult<Vec<&'static str>> {
+        let names = self.part_names.lock()?.clone();
+        let lifecycle = self.lifecycle.lock()?;
+
+        let mut ordered = Vec::new();
+        for name in names {
+            visit(name, &lifecycle.deps, &mut Vec::new(), &mut ordered)?;
+        }
+        Ok(ordered)
+    }
+
+    // part_order(), but with the parts, whose run() blocks, and the ones depending on them last
+    pub fn run_order(&self) -> MizeResult<Vec<&'static str>> {
+        let order = self.part_order()?;
+        let deps = self.lifecycle.lock()?.deps.clone();
+
+        // a part comes after it's deps in order, so they are already sorted in
+        let mut late: Vec<&'static str> = Vec::new();
+        for name in order.iter() {
+            let depends_on_late = deps
+                .get(name)
+                .is_some_and(|deps| deps.iter().any(|dep| late.contains(dep)));
+            if depends_on_late || self.read_part(name)?.run_blocks() {
+                late.push(name);
+            }
+        }
+
+        Ok(order
+            .iter()
+            .filter(|name| !late.contains(name))
+            .chain(late.iter())
+            .cloned()
+            .collect())
+    }
+
+    pub fn init_parts(&mut self) -> MizeResult<()> {
+        let inited = self.lifecycle.lock()?.inited.clone();
+        let new: Vec<&'static str> = self
+            .part_order()?
+            .into_iter()
+            .filter(|name| !inited.contains(name))
+            .collect();
+
+        for name in new.iter() {
+            let part = self.read_part(name)?;
+            part.opts(&mut self.clone());
+        }
+        if !new.is_empty() {
+            config::check_all(self)?;
+        }
+
+        for name in new {
+            let mut part = self.get_part(name)?;
+            let mut mize = self.clone();
+            part.init(&mut mize)
+                .map_err(|err| err.msg(format!("init of the part '{}' failed", name)))?;
+            block_on(self, part.async_init(&mut mize))?
+                .map_err(|err| err.msg(format!("async_init of the part '{}' failed", name)))?;
+            drop(part);
+            self.lifecycle.lock()?.inited.push(name);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn run_parts(&mut self) -> MizeResult<()> {
+        self.init_parts()?;
+
+        for name in self.run_order()? {
+            let mut part = self.get_part(name)?;
+            let mut mize = self.clone();
+            part.run(&mut mize)
+                .map_err(|err| err.msg(format!("run of the part '{}' failed", name)))?;
+            block_on(self, part.async_run(&mut mize))?
+                .map_err(|err| err.msg(format!("async_run of the part '{}' failed", name)))?;
+            let blocking_run = part.take_blocking_run();
+            drop(part);
+
+            if let Some(blocking_run) = blocking_run {
+                blocking_run(&mut mize)
+                    .map_err(|err| err.msg(format!("run of the part '{}' failed", name)))?;
+            }
+        }
+        Ok(())
+    }
+
+    // a part, that someone uses right now, is left for a later call
+    pub(crate) fn shutdown_parts(&self) -> MizeResult<()> {
+        let todo: Vec<&'static str> = {
+            let lifecycle = self.lifecycle.lock()?;
+            lifecycle
+                .inited
+                .iter()
+                .rev()
+                .filter(|name| !lifecycle.shut_down.contains(name))
+                .cloned()
+                .collect()
+        };
+
+        for name in todo {
+            let mut part = match self.try_get_part(name) {
+                Some(part) => part,
+                None => continue,
+            };
+            if let Err(err) = part.shutdown(&mut self.clone()) {
+                self.report_err(err.msg(format!("shutdown of the part '{}' failed", name)));
+            }
+            drop(part);
+            self.lifecycle.lock()?.shut_down.push(name);
+        }
+        Ok(())
+    }
+}
+
+fn visit(
+    name: &'static str,
+    deps: &HashMap<&'static str, &'static [&'static str]>,
+    path: &mut Vec<&'static str>,
+    ordered: &mut Vec<&'static str>,
+) -> MizeResult<()> {
+    if ordered.contains(&name) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|other| *other == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name);
+        return Err(mize_err!(
+            "the parts depend on each other: {}",
+            cycle.join(" -> ")
+        ));
+    }
+
+    let part_deps = deps
+        .get(name)
+        .ok_or(mize_err!("the part '{}' was not added", name))?;
+    path.push(name);
+    for dep in part_deps.iter() {
+        if !deps.contains_key(dep) {
+            return Err(mize_err!(
+                "the part '{}' depends on '{}', which was not added",
+                name,
+                dep
+            ));
+        }
+        visit(dep, deps, path, ordered)?;
+    }
+    path.pop();
+
+    ordered.push(name);
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+fn block_on<T>(instance: &Mize, future: impl Future<Output = T>) -> MizeResult<T> {
+    use tokio::runtime::{Handle, RuntimeFlavor};
+
+    // Handle::block_on() panics on a thread of a runtime, a multi threaded one can hand the other
+    // tasks of this thread to another one, while we block
+    match Handle::try_current() {
+        Err(_) => Ok(instance.async_get_handle().block_on(future)),
+        Ok(current) if current.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            Ok(tokio::task::block_in_place(|| {
+                instance.async_get_handle().block_on(future)
+            }))
+        }
+        Ok(_) => Err(mize_err!(
+            "can't init or run a part from a current thread runtime, it would block it"
+        )),
+    }
+}
+
+// without a runtime, the future is polled on this thread, which is unparked by the waker
+#[cfg(not(feature = "async"))]
+fn block_on<T>(instance: &Mize, future: impl Future<Output = T>) -> MizeResult<T> {
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    struct Unpark(Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(value) => return Ok(value),
+            Poll::Pending => thread::park(),
+        }
+    }
+}
@@ -10,13 +10,13 @@ use std::fs::create_dir;
 use std::fs::File;
 use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 use std::{thread, vec};
 use tracing::{debug, error, info, trace, warn, Instrument};
 use uuid::Uuid;
 
 use crate::config::ConfigOptNameAndMize;
-use crate::config::{gather_config, ConfigOpt};
+use crate::config::{self, gather_config, ConfigOpt};
 use crate::error::{IntoMizeResult, MizeError, MizeResult, MizeResultTrait};
 use crate::id::{IntoMizeId, MizeId, Namespace};
 use crate::instance::store::Store;
@@ -28,8 +28,21 @@ use crate::memstore::MemStore;
 use crate::proto::MizeMessage;
 use crate::{mize_err, Module};
 
+use self::auth::Principal;
+use self::computed::ComputedItems;
 use self::connection::{ConnListener, Connection};
+use self::lifecycle::Lifecycle;
+use self::mount::{Mount, MountStore};
+use self::part::PartLock;
+use self::provider::Providers;
+use self::queue::{FullPolicy, Queue, QueueReceiver};
+use self::recorder::{Direction, Recorder};
+use self::replica::ReplicaState;
+use self::routing::RoutingTable;
+use self::rules::Rules;
+use self::sync::SyncState;
 use self::updater::handle_operation;
+use self::validator::Validators;
 
 #[cfg(feature = "async")]
 use tokio::runtime::Handle;
@@ -39,17 +52,36 @@ use tokio::runtime::Runtime;
 use core::future::Future;
 use std::thread::JoinHandle;
 
+pub mod auth;
+pub mod computed;
 pub mod connection;
+pub mod instance_async;
+pub mod introspect;
+pub mod lifecycle;
 pub mod module;
+pub mod mount;
 pub mod msg_thread;
+pub mod part;
+pub mod provider;
+pub mod queue;
+pub mod recorder;
+pub mod replica;
+pub mod routing;
+pub mod rules;
 pub mod store;
 pub mod subscription;
+pub mod sync;
 pub mod updater;
+pub mod validator;
+pub mod worker;
 
 #[cfg(test)]
 mod tests;
 
-static MSG_CHANNEL_SIZE: usize = 200;
+// the default size of the send queue of a connection and of the operation queue
+pub(crate) static MSG_CHANNEL_SIZE: usize = 200;
+
+static UPDATER_THREADS: usize = 2;
 
 static BUILD_TIME_CONFIG: &str = include_str!(std::env!("MIZE_BUILD_CONFIG"));
 
@@ -57,19 +89,34 @@ static BUILD_TIME_CONFIG: &str = include_str!(std::env!("MIZE_BUILD_CONFIG"));
 #[derive(Clone)]
 pub struct Mize {
     // a bit a lot of Mutexes isn't it???
+    // always a MountStore, see mount.rs
     pub(crate) store: Arc<Mutex<Box<dyn Store>>>,
+    pub(crate) mounts: Arc<Mutex<Vec<Mount>>>,
     connections: Arc<Mutex<Vec<Connection>>>,
     next_con_id: Arc<Mutex<u64>>,
+    pub(crate) routing: Arc<Mutex<RoutingTable>>,
+    pub(crate) sync: Arc<Mutex<SyncState>>,
+    pub(crate) replica: Arc<Mutex<ReplicaState>>,
+    pub(crate) recorder: Recorder,
     subs: Arc<Mutex<HashMap<MizeId, Vec<Subscription>>>>,
+    pub(crate) validators: Arc<Mutex<Validators>>,
+    pub(crate) providers: Arc<Mutex<Providers>>,
+    pub(crate) computed: Arc<Mutex<ComputedItems>>,
+    pub(crate) rules: Arc<Mutex<Rules>>,
     pub(crate) modules: Arc<Mutex<HashMap<String, Box<dyn Module + Sync + Send>>>>,
     pub(crate) id_pool: Arc<Mutex<VecStringPool>>,
     pub(crate) namespace_pool: Arc<Mutex<StringPool>>,
 
-    pub(crate) parts: Arc<Mutex<HashMap<&'static str, Option<Box<dyn MizePart + Sync + Send>>>>>,
+    // every part is behind it's own lock (see part.rs)
+    pub(crate) parts: Arc<Mutex<HashMap<&'static str, Arc<PartLock>>>>,
 
     part_names: Arc<Mutex<Vec<&'static str>>>,
+    // the order, in which the parts are inited, run and shut down (see lifecycle.rs)
+    pub(crate) lifecycle: Arc<Mutex<Lifecycle>>,
 
     pub(crate) config_opts: Arc<Mutex<HashMap<String, ConfigOpt>>>,
+    // the config files, the options were read from (MIZE_CONFIG_FILES)
+    pub(crate) config_files: Arc<Mutex<Vec<String>>>,
 
     // the namespace the instance operates in
     pub(crate) namespace: Arc<Mutex<Namespace>>,
@@ -77,81 +124,42 @@ pub struct Mize {
     // the namespace of the instance itself
     // TODO: set to a random uuid
     pub(crate) self_namespace: Arc<Mutex<Namespace>>,
-    pub(crate) op_tx: Sender<Operation>,
+    pub(crate) ops: Queue<Operation>,
     threads: Arc<Mutex<Vec<(u32, String, Option<JoinHandle<MizeResult<()>>>)>>>,
+    // the threads of spawn_background(), that wait() joins
+    background: Arc<Mutex<Vec<(u32, JoinHandle<MizeResult<()>>)>>>,
+    // shutdown() joins them, before it flushes the store
+    updaters: Arc<Mutex<Vec<(u32, JoinHandle<MizeResult<()>>)>>>,
     next_thread_id: Arc<Mutex<u32>>,
-    give_msg_wait: Arc<Mutex<HashMap<MizeId, Vec<Sender<ItemData>>>>>,
-    create_msg_wait: Arc<Mutex<Option<Sender<MizeId>>>>,
+    // a Denied msg makes the waiting request fail
+    // a reply is only taken from the connection (the u64), that the request went out on
+    give_msg_wait: Arc<Mutex<HashMap<MizeId, Vec<(u64, Sender<MizeResult<ItemData>>)>>>>,
+    create_msg_wait: Arc<Mutex<Option<(u64, Sender<MizeResult<MizeId>>)>>>,
+
+    // set by shutdown(), wait() waits on the Condvar for it
+    stopping: Arc<(Mutex<bool>, Condvar)>,
+    // for async code, that runs until the instance stops (eg: the web listener)
+    #[cfg(feature = "async")]
+    stop_notify: Arc<tokio::sync::Notify>,
 
     #[cfg(feature = "async")]
     pub(crate) runtime: Arc<Mutex<Runtime>>,
 }
 
+pub fn build_time_config() -> MizeResult<ItemData> {
+    ItemData::from_toml(BUILD_TIME_CONFIG)
+}
+
 // the public Mize API
 
 pub struct InstanceRef {
     inner: Arc<Mutex<Mize>>,
 }
 
+// the same API for async code, that never blocks a thread of the runtime (see instance_async.rs)
+#[derive(Clone)]
 pub struct InstanceAsync {
-    inner: Arc<Mutex<Mize>>,
-}
-
-pub struct DynMizePartGuard {
-    pub mize: Mize,
-    pub part: Option<Box<dyn MizePart + Send + Sync>>,
-}
-
-pub struct MizePartGuard<T: MizePart + Send + Sync + 'static> {
-    pub mize: Mize,
-    pub part: Option<T>,
-}
-
-impl<T: MizePart + Send + Sync> Deref for MizePartGuard<T> {
-    type Target = T;
-
-    fn deref(&self) -> &Self::Target {
-        &*self.part.as_ref().unwrap()
-    }
-}
-
-impl<T: MizePart + Send + Sync> DerefMut for MizePartGuard<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut *self.part.as_mut().unwrap()
-    }
-}
-
-impl<T: MizePart + Send + Sync> Drop for MizePartGuard<T> {
-    fn drop(&mut self) {
-        if self.part.is_none() {
-            return;
-        }
-        let part = self.part.take().unwrap();
-        self.mize.give_back_part(Box::new(part));
-    }
-}
-
-impl Deref for DynMizePartGuard {
-    type Target = dyn MizePart + Send + Sync;
-
-    fn deref(&self) -> &Self::Target {
-        &*self.part.as_deref().unwrap()
-    }
-}
-
-impl DerefMut for DynMizePartGuard {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut *self.part.as_deref_mut().unwrap()
-    }
-}
-
-impl Drop for DynMizePartGuard {
-    fn drop(&mut self) {
-        if self.part.is_none() {
-            return;
-        }
-        self.mize.give_back_part(self.part.take().unwrap());
-    }
+    inner: Mize,
 }
 
 pub trait MizePartCreateGenerated: Sized {
@@ -163,6 +171,8 @@ pub trait MizePartGenerated {
     fn as_any_generated(&self) -> &dyn Any;
     fn as_any_mut_generated(&mut self) -> &mut dyn Any;
     fn into_any_generated(self: Box<Self>) -> Box<dyn Any>;
+    // declares the options of the #[opt] fields
+    fn opts_generated(&self, mize: &mut Mize) {}
 }
 
 pub trait MizePartCreate: MizePartCreateGenerated + MizePart + 'static {
@@ -171,6 +181,8 @@ pub trait MizePartCreate: MizePartCreateGenerated + MizePart + 'static {
     }
 }
 
+pub type BlockingRun = Box<dyn FnOnce(&mut Mize) -> MizeResult<()> + Send>;
+
 #[async_trait]
 pub trait MizePart: MizePartGenerated {
     fn name(&self) -> &'static str {
@@ -197,8 +209,19 @@ pub trait MizePart: MizePartGenerated {
     fn deps(&self) -> &'static [&'static str] {
         &[]
     }
+    // a part, whose run blocks (eg: the cli part, while the command runs), is run after the
+    // parts, that don't depend on it (see lifecycle.rs)
+    fn run_blocks(&self) -> bool {
+        false
+    }
+    // what blocks is taken out of such a part after run() and async_run(), and is run without
+    // holding the part, so that others can still use it
+    fn take_blocking_run(&mut self) -> Option<BlockingRun> {
+        None
+    }
+    // a part, that declares it's own options, and has #[opt] fields, calls opts_generated() too
     fn opts(&self, mize: &mut Mize) {
-        ()
+        self.opts_generated(mize)
     }
     async fn async_run(&mut self, mize: &mut Mize) -> MizeResult<()> {
         Ok(())
@@ -206,6 +229,9 @@ pub trait MizePart: MizePartGenerated {
     async fn async_init(&mut self, mize: &mut Mize) -> MizeResult<()> {
         Ok(())
     }
+    fn shutdown(&mut self, mize: &mut Mize) -> MizeResult<()> {
+        Ok(())
+    }
 }
 
 impl Mize {
@@ -213,8 +239,10 @@ impl Mize {
         let id_pool = Arc::new(Mutex::new(VecStringPool::default()));
         let namespace_pool_raw = StringPool::default();
         let connections = Arc::new(Mutex::new(Vec::new()));
+        let mounts = Arc::new(Mutex::new(Vec::new()));
         let subs = Arc::new(Mutex::new(HashMap::new()));
-        let (op_tx, op_rx) = unbounded();
+        let ops = Queue::new(MSG_CHANNEL_SIZE, FullPolicy::Block);
+        let op_rx = ops.receiver();
         let give_msg_wait = Arc::new(Mutex::new(HashMap::new()));
         let create_msg_wait = Arc::new(Mutex::new(None));
         let namespace = Arc::new(Mutex::new(Namespace(
@@ -225,23 +253,42 @@ impl Mize {
         )));
 
         let mut instance = Mize {
-            store: Arc::new(Mutex::new(Box::new(MemStore::new()))),
+            store: Arc::new(Mutex::new(Box::new(MountStore::new(
+                Box::new(MemStore::new()),
+                mounts.clone(),
+            )))),
+            mounts,
             parts: Arc::new(Mutex::new(HashMap::new())),
             part_names: Arc::new(Mutex::new(Vec::new())),
+            lifecycle: Arc::new(Mutex::new(Lifecycle::default())),
             config_opts: Arc::new(Mutex::new(HashMap::new())),
+            config_files: Arc::new(Mutex::new(Vec::new())),
             connections,
+            routing: Arc::new(Mutex::new(RoutingTable::default())),
+            sync: Arc::new(Mutex::new(SyncState::default())),
+            replica: Arc::new(Mutex::new(ReplicaState::default())),
+            recorder: Recorder::default(),
             subs,
+            validators: Arc::new(Mutex::new(Validators::default())),
+            providers: Arc::new(Mutex::new(Providers::default())),
+            computed: Arc::new(Mutex::new(ComputedItems::default())),
+            rules: Arc::new(Mutex::new(Rules::default())),
             id_pool,
             namespace,
             self_namespace,
-            op_tx,
+            ops,
             namespace_pool: Arc::new(Mutex::new(namespace_pool_raw)),
             modules: Arc::new(Mutex::new(HashMap::new())),
             threads: Arc::new(Mutex::new(Vec::new())),
+            background: Arc::new(Mutex::new(Vec::new())),
+            updaters: Arc::new(Mutex::new(Vec::new())),
             next_thread_id: Arc::new(Mutex::new(0)),
             next_con_id: Arc::new(Mutex::new(1)),
             give_msg_wait,
             create_msg_wait,
+            stopping: Arc::new((Mutex::new(false), Condvar::new())),
+            #[cfg(feature = "async")]
+            stop_notify: Arc::new(tokio::sync::Notify::new()),
 
             #[cfg(feature = "async")]
             runtime: Arc::new(Mutex::new(
@@ -250,15 +297,12 @@ impl Mize {
         };
 
         #[cfg(feature = "target-os")]
-        {
+        for _ in 0..UPDATER_THREADS {
             let instance_clone = instance.clone();
             let op_rx_clone = op_rx.clone();
             let closure = move || updater_thread(op_rx_clone, &instance_clone);
-            instance.spawn_background("updater_thread", closure)?;
-
-            let instance_clone_two = instance.clone();
-            let closure_two = move || updater_thread(op_rx, &instance_clone_two);
-            instance.spawn_background("updater_thread", closure_two)?;
+            let updaters = instance.updaters.clone();
+            instance.spawn_thread("updater_thread", closure, Some(updaters))?;
         }
 
         // set up async update "threads" when using wasm
@@ -281,8 +325,10 @@ impl Mize {
         #[cfg(feature = "target-wasm ")]
         console_log!("before loading build time config");
 
+        rules::add_validators(&instance)?;
+
         // load the config from build time
-        let config = ItemData::from_toml(BUILD_TIME_CONFIG)?;
+        let config = build_time_config()?;
         instance.set_blocking("0", config);
 
         #[cfg(feature = "target-wasm ")]
@@ -306,36 +352,31 @@ impl Mize {
         return Ok(instance);
     }
 
-    pub fn get_part(&mut self, name: &str) -> MizeResult<DynMizePartGuard> {
-        match self.parts.lock().unwrap().get_mut(name) {
-            Some(part) => Ok(DynMizePartGuard {
-                part: Some(part.take().unwrap()),
-                mize: self.clone(),
-            }),
-            None => Err(mize_err!("Part not found or currently taken")),
-        }
-    }
     pub fn has_part(&mut self, name: &str) -> bool {
         self.parts.lock().unwrap().contains_key(name)
     }
     pub fn add_part(&mut self, part: Box<dyn MizePart + Send + Sync>) -> MizeResult<()> {
         self.part_names.lock().unwrap().push(part.name());
-        self.parts.lock().unwrap().insert(part.name(), Some(part));
-        Ok(())
+        self.lifecycle.lock()?.added(part.name(), part.deps());
+        self.parts
+            .lock()
+            .unwrap()
+            .insert(part.name(), PartLock::new(part));
+        introspect::changed(self, "parts")
     }
     pub fn register_part(&mut self, part: Box<dyn MizePart + Send + Sync>) -> MizeResult<()> {
         self.part_names.lock().unwrap().push(part.name());
-        self.parts.lock().unwrap().insert(part.name(), Some(part));
-        Ok(())
+        self.lifecycle.lock()?.added(part.name(), part.deps());
+        self.parts
+            .lock()
+            .unwrap()
+            .insert(part.name(), PartLock::new(part));
+        introspect::changed(self, "parts")
     }
     fn part_names(&mut self) -> Vec<&'static str> {
         self.part_names.lock().unwrap().clone()
     }
 
-    pub(crate) fn give_back_part(&mut self, part: Box<dyn MizePart + Send + Sync>) {
-        self.parts.lock().unwrap().insert(part.name(), Some(part));
-    }
-
     pub fn init(&mut self) -> MizeResult<()> {
         // gather options
         gather_config(self)?;
@@ -345,6 +386,8 @@ impl Mize {
 
         // end of platform specific init code
 
+        queue::apply_config(self)?;
+
         // load the modules, ad specified in the load_modules config
         match self.get("0/config/load_modules")?.value_string() {
             Ok(modules_to_load) => {
@@ -359,6 +402,9 @@ impl Mize {
             }
         }
 
+        // the parts added before init
+        self.init_parts()?;
+
         debug!("INSTANCE INIT DONE");
         Ok(())
     }
@@ -381,6 +427,7 @@ impl Mize {
         let mut old_store = self.store.lock()?;
 
         let id = self.id_from_string("0".to_owned())?;
+        let stored_sync = sync::stored(self, new_store.as_ref())?;
         let inst_data = old_store.get_value_data_full(id.clone())?;
         new_store.set(id, inst_data.to_owned())?;
 
@@ -391,34 +438,54 @@ impl Mize {
             new_store.set(self.id_from_string(id_of_new_store)?, data.to_owned())?;
         }
 
-        *old_store = new_store;
+        // the mounts stay, only the root store is replaced
+        *old_store = Box::new(MountStore::new(new_store, self.mounts.clone()));
+        drop(old_store);
 
-        Ok(())
+        sync::store_replaced(self, stored_sync)
     }
 
     pub fn new_item(&self) -> MizeResult<Item> {
         if self.get_namespace()? != self.get_self_namespace()? {
-            // need to send create msg and wait for it
-            let mut connection = self.get_connection_by_ns(self.get_namespace()?)?;
+            let id = self.request_create()?.recv()??;
+            debug!("new_item namespace: {:?}", id.namespace());
+            return Ok(Item::new(id, self));
+        }
 
-            let msg = MizeMessage::new_create(connection.id);
+        self.new_local_item()
+    }
 
-            connection.send(msg)?;
+    // send a create msg to the owner of the namespace, the receiver gets the id of the new item
+    pub(crate) fn request_create(&self) -> MizeResult<Receiver<MizeResult<MizeId>>> {
+        let mut connection = self.get_connection_by_ns(self.get_namespace()?)?;
 
-            let (tx, rx) = bounded::<MizeId>(1);
+        let msg = MizeMessage::new_create(connection.id).with_ns(&self.get_namespace()?);
 
-            let mut msg_wait_inner = self.create_msg_wait.lock()?;
-            *msg_wait_inner = Some(tx);
-            drop(msg_wait_inner);
+        // wait for the CreateReply before sending, so that a fast reply can't get lost
+        let (tx, rx) = bounded::<MizeResult<MizeId>>(1);
 
-            let id = rx.recv()?;
-            println!("new_item namespace: {:?}", id.namespace());
+        let mut msg_wait_inner = self.create_msg_wait.lock()?;
+        *msg_wait_inner = Some((connection.id, tx));
+        drop(msg_wait_inner);
 
-            return Ok(Item::new(id, self));
-        }
+        connection.send(msg)?;
 
+        Ok(rx)
+    }
+
+    pub(crate) fn new_local_item(&self) -> MizeResult<Item> {
         let store_inner = self.store.lock()?;
         let id = self.id_from_string(store_inner.new_id()?)?;
+        drop(store_inner);
+
+        // replicas sub to inst/new_item, they only get the new id, not the whole inst/ids
+        updater::notify_subs_with(
+            self,
+            &self.new_id("inst/new_item")?,
+            &None,
+            Some(ItemData::from_string(id.store_part())),
+        )?;
+
         return Ok(Item::new(id, self));
     }
 
@@ -429,8 +496,8 @@ impl Mize {
 
     pub fn set<I: IntoMizeId, V: Into<ItemData>>(&self, id: I, value: V) -> MizeResult<()> {
         let id = id.to_mize_id(self)?;
-        self.op_tx.send(Operation::Set(id, value.into(), None));
-        Ok(())
+        let value = validator::validate(self, &id, value.into(), false)?;
+        self.queue_op(Operation::Set(id, value, None))
     }
 
     pub fn set_blocking<I: IntoMizeId, V: Into<ItemData>>(
@@ -438,10 +505,16 @@ impl Mize {
         id: I,
         value: V,
     ) -> MizeResult<()> {
-        handle_operation(
-            &mut Operation::Set(id.to_mize_id(self)?, value.into(), None),
-            self,
-        )?;
+        let id = id.to_mize_id(self)?;
+        let value = validator::validate(self, &id, value.into(), false)?;
+
+        // the owner of the item may reject the change
+        if !self.is_own_namespace(&id.namespace())? {
+            self.get(id.clone())?.merge_acked(value)?;
+            return updater::notify_subs(self, &id, &None);
+        }
+
+        handle_operation(&mut Operation::Set(id, value, None), self)?;
         Ok(())
     }
 
@@ -456,11 +529,18 @@ impl Mize {
                 subs_inner.insert(id.clone(), vec![sub]);
             }
         }
+        drop(subs_inner);
+        introspect::changed(self, "subs")?;
+
+        // a provided item is ours, whatever it's namespace
+        if let Some(provider) = provider::provider_for(self, &id)? {
+            return provider.sub(self, &id);
+        }
 
         // if we are not the owner of this item, send a sub msg to them
-        if !self.we_are_namespace()? {
+        if id.namespace() != self.get_self_namespace()? {
             let con = self.get_connection_by_ns(id.namespace())?;
-            let msg = MizeMessage::new_sub(id, con.id);
+            let msg = MizeMessage::new_sub(id.clone(), con.id).with_ns(&id.namespace());
             con.send(msg)?;
         }
 
@@ -567,66 +647,265 @@ impl Mize {
         Ok(self.get_namespace()? == self.get_self_namespace()?)
     }
 
+    // the namespace others know us by, which is the configured one, if we own our namespace
+    pub fn public_namespace(&self) -> MizeResult<Option<Namespace>> {
+        if !self.we_are_namespace()? {
+            return Ok(None);
+        }
+
+        match self.get("0/config/namespace")?.value_string() {
+            Ok(ns) => Ok(Some(self.namespace_from_string(ns)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn is_own_namespace(&self, ns: &Namespace) -> MizeResult<bool> {
+        if *ns == self.get_self_namespace()? {
+            return Ok(true);
+        }
+        Ok(self.public_namespace()?.as_ref() == Some(ns))
+    }
+
+    // who we are in edits of crdt values (see types::crdt)
+    pub fn replica_id(&self) -> MizeResult<String> {
+        Ok(self.sync.lock()?.replica_id.clone())
+    }
+
+    // how we call ourselves in the via field of forwarded msgs
+    pub(crate) fn route_name(&self) -> MizeResult<String> {
+        match self.public_namespace()? {
+            Some(ns) => Ok(ns.as_real_string()),
+            None => Ok(self.get_self_namespace()?.as_real_string()),
+        }
+    }
+
     pub fn add_listener<T: ConnListener + 'static>(&mut self, listener: T) -> MizeResult<()> {
         let mut instance_clone = self.clone();
         self.spawn_background("some_listener", move || listener.listen(instance_clone));
         Ok(())
     }
 
-    pub fn new_connection(&self, tx: Sender<MizeMessage>) -> MizeResult<u64> {
+    pub fn new_connection(&self, queue: Queue<MizeMessage>) -> MizeResult<u64> {
         let mut conn_inner = self.connections.lock()?;
         let mut next_con_id = self.next_con_id.lock()?;
         let old_next_con_id = *next_con_id;
 
         let connection = Connection {
             id: next_con_id.to_owned(),
-            tx,
+            queue,
             ns: None,
+            recorder: self.recorder.clone(),
+            ops: self.ops.clone(),
+            principal: None,
+            challenge: None,
         };
-        conn_inner.push(connection);
+        conn_inner.push(connection.clone());
         *next_con_id += 1;
+        drop(conn_inner);
+        drop(next_con_id);
+        introspect::changed(self, "connections")?;
+
+        // tell the new peer, what it can reach over us
+        if routing::routing_enabled(self)? {
+            routing::advertise_routes_to(self, &connection)?;
+        }
+
         Ok(old_next_con_id)
     }
 
-    pub fn new_connection_join_namespace(&self, tx: Sender<MizeMessage>) -> MizeResult<u64> {
-        let conn_id = self.new_connection(tx)?;
+    pub fn new_connection_join_namespace(&self, queue: Queue<MizeMessage>) -> MizeResult<u64> {
+        let conn_id = self.new_connection(queue)?;
+
+        self.join_namespace_of_peer(conn_id)?;
 
+        Ok(conn_id)
+    }
+
+    // ask the peer on conn_id for it's namespace and make it ours
+    pub fn join_namespace_of_peer(&self, conn_id: u64) -> MizeResult<()> {
+        // self and not inst, because inst is in the namespace we are in, which is already theirs,
+        // if we joined before
         let ns_of_peer_str = self
             .get(format!(
-                "inst/con_by_id/{}/peer/0/config/namespace",
+                "self/con_by_id/{}/peer/0/config/namespace",
                 conn_id
             ))?
             .value_string()?;
         let ns_of_peer = self.namespace_from_string(ns_of_peer_str)?;
 
-        self.connection_set_namespace(conn_id, ns_of_peer.clone());
-        self.set_namespace(ns_of_peer);
+        debug!("joining namespace of peer: {}", ns_of_peer.as_string());
+
+        self.connection_set_namespace(conn_id, ns_of_peer.clone())?;
+        self.set_namespace(ns_of_peer)?;
+
+        if replica::replica_enabled(self)? {
+            replica::start(self, conn_id)?;
+        }
+
+        Ok(())
+    }
+
+    // connect two instances in the same process, as if they were connected by a socket
+    // we join the namespace of other, so this is like connecting to a running instance
+    #[cfg(feature = "target-os")]
+    pub fn connect_in_process(&mut self, other: &Mize) -> MizeResult<u64> {
+        let conn_id = self.peer_in_process(other)?;
+
+        self.join_namespace_of_peer(conn_id)?;
 
         Ok(conn_id)
     }
 
+    // like connect_in_process, but both instances stay in their own namespace
+    #[cfg(feature = "target-os")]
+    pub fn peer_in_process(&mut self, other: &Mize) -> MizeResult<u64> {
+        let (our_tx, our_rx) = self.new_conn_queue()?;
+        let (their_tx, their_rx) = other.new_conn_queue()?;
+
+        let our_conn_id = self.new_connection(our_tx)?;
+        let their_conn_id = other.new_connection(their_tx)?;
+        self.connection_set_principal(our_conn_id, Principal::Owner)?;
+        other.connection_set_principal(their_conn_id, Principal::Owner)?;
+
+        // what is sent on one connection, comes in on the other one
+        let other_clone = other.clone();
+        self.spawn_detached("in process outgoing", move || {
+            for msg in our_rx {
+                other_clone.got_msg(MizeMessage::new(msg.value(), their_conn_id))?;
+            }
+            Ok(())
+        })?;
+
+        let self_clone = self.clone();
+        other
+            .clone()
+            .spawn_detached("in process outgoing", move || {
+                for msg in their_rx {
+                    self_clone.got_msg(MizeMessage::new(msg.value(), our_conn_id))?;
+                }
+                Ok(())
+            })?;
+
+        Ok(our_conn_id)
+    }
+
+    pub fn remove_connection(&self, conn_id: u64) -> MizeResult<()> {
+        let mut conn_inner = self.connections.lock()?;
+        conn_inner.retain(|connection| connection.id != conn_id);
+        drop(conn_inner);
+
+        // also drop all subscriptions, that would send updates to this connection
+        let mut subs_inner = self.subs.lock()?;
+        for vec in subs_inner.values_mut() {
+            vec.retain(|sub| match sub {
+                Subscription::Connection(conn) => conn.id != conn_id,
+                _ => true,
+            });
+        }
+        drop(subs_inner);
+
+        introspect::changed(self, "connections")?;
+        introspect::changed(self, "subs")?;
+
+        // no reply will come over it anymore
+        let closed = || mize_err!("connection {} closed, before it replied", conn_id);
+        let mut give_msg_wait_inner = self.give_msg_wait.lock()?;
+        for waiters in give_msg_wait_inner.values_mut() {
+            waiters.retain(|(waiting_for, tx)| {
+                if *waiting_for == conn_id {
+                    tx.send(Err(closed()));
+                }
+                *waiting_for != conn_id
+            });
+        }
+        give_msg_wait_inner.retain(|_, waiters| !waiters.is_empty());
+        drop(give_msg_wait_inner);
+        let mut create_msg_wait_inner = self.create_msg_wait.lock()?;
+        if let Some((waiting_for, tx)) = create_msg_wait_inner.as_ref() {
+            if *waiting_for == conn_id {
+                tx.send(Err(closed()));
+                *create_msg_wait_inner = None;
+            }
+        }
+        drop(create_msg_wait_inner);
+
+        // and all routes over it
+        self.routing.lock()?.remove_connection(conn_id);
+        routing::advertise_routes(self)?;
+
+        Ok(())
+    }
+
     pub fn connection_set_namespace(&self, conn_id: u64, namespace: Namespace) -> MizeResult<()> {
         let mut connection = self.get_connection(conn_id)?;
         connection.ns = Some(namespace);
-        self.set_connection(conn_id, connection);
+        self.set_connection(conn_id, connection)?;
+
+        // we can reach a new namespace
+        routing::advertise_routes(self)?;
+        sync::replay_in_background(self)?;
         Ok(())
     }
 
     pub fn got_msg(&self, msg: MizeMessage) -> MizeResult<()> {
-        Ok(self.op_tx.send(Operation::Msg(msg))?)
+        self.recorder.record(Direction::In, msg.conn_id, &msg);
+        self.queue_op(Operation::Msg(msg))
+    }
+
+    pub(crate) fn queue_op(&self, mut operation: Operation) -> MizeResult<()> {
+        // an updater thread waiting for space in the queue, that it empties itself, could wait
+        // forever, so it does the operation right away
+        if updater::on_updater_thread() {
+            return handle_operation(&mut operation, self);
+        }
+
+        // only a full queue of msgs from a connection can be a reason to close it
+        let conn_id = match &operation {
+            Operation::Msg(msg) => msg.conn_id,
+            _ => return self.ops.push_blocking(operation),
+        };
+        if !self.ops.push(operation, queue::same_update_op)? {
+            warn!(
+                "operation queue is full, disconnecting connection {}",
+                conn_id
+            );
+            self.remove_connection(conn_id)?;
+        }
+        Ok(())
+    }
+
+    // a send queue, sized by the config, for a new connection
+    pub fn new_conn_queue(&self) -> MizeResult<(Queue<MizeMessage>, QueueReceiver<MizeMessage>)> {
+        let queue = queue::new_conn_queue(self)?;
+        let rx = queue.receiver();
+        Ok((queue, rx))
+    }
+
+    // write every msg of every connection to writer, see recorder.rs for the format
+    pub fn record_to(&self, writer: Box<dyn std::io::Write + Send>) -> MizeResult<()> {
+        self.recorder.start(writer)
+    }
+
+    pub fn stop_recording(&self) -> MizeResult<()> {
+        self.recorder.stop()
     }
 
     pub fn report_err(&self, err: MizeError) {
         err.log();
     }
 
-    fn set_connection(&self, conn_id: u64, new_connection: Connection) -> MizeResult<()> {
+    pub(crate) fn set_connection(
+        &self,
+        conn_id: u64,
+        new_connection: Connection,
+    ) -> MizeResult<()> {
         let mut conn_inner = self.connections.lock()?;
 
         for connection in conn_inner.iter_mut() {
             if connection.id == conn_id {
                 *connection = new_connection;
-                return Ok(());
+                drop(conn_inner);
+                return introspect::changed(self, "connections");
             }
         }
 
@@ -637,6 +916,13 @@ impl Mize {
         Ok(())
     }
 
+    // set by the transport or the auth handshake, see auth.rs
+    pub fn connection_set_principal(&self, conn_id: u64, principal: Principal) -> MizeResult<()> {
+        let mut connection = self.get_connection(conn_id)?;
+        connection.principal = Some(principal);
+        self.set_connection(conn_id, connection)
+    }
+
     pub fn get_connection(&self, conn_id: u64) -> MizeResult<Connection> {
         let mut conn_inner = self.connections.lock()?;
 
@@ -660,6 +946,13 @@ impl Mize {
                 return Ok(connection.clone());
             }
         }
+        drop(conn_inner);
+
+        // not a direct peer, but maybe someone told us how to reach it
+        let route = self.routing.lock()?.routes.get(&ns).cloned();
+        if let Some(route) = route {
+            return self.get_connection(route.conn_id);
+        }
 
         return Err(mize_err!(
             "Connection with namespace {} not known to instance",
@@ -667,6 +960,14 @@ impl Mize {
         ));
     }
 
+    // a thread removes itself from the threads list (inst/threads), when it stops
+    fn thread_stopped(&self, thread_id: u32) -> MizeResult<()> {
+        self.threads.lock()?.retain(|(id, _, _)| *id != thread_id);
+        self.background.lock()?.retain(|(id, _)| *id != thread_id);
+        self.updaters.lock()?.retain(|(id, _)| *id != thread_id);
+        introspect::changed(self, "threads")
+    }
+
     pub fn spawn_and_wait(
         &mut self,
         name: &str,
@@ -677,7 +978,6 @@ impl Mize {
         let mut next_thread_id = self.next_thread_id.lock()?;
 
         let my_thread_id_no_mutex_guard = *next_thread_id;
-        let thread_mutex = self.threads.clone();
         let name_to_move = name.to_owned();
         let to_spawn = move || -> MizeResult<()> {
             debug!("spawning thread: {}", name_to_move);
@@ -687,17 +987,9 @@ impl Mize {
                 mize_clone.report_err(err);
             }
 
-            //let mut threads_inner = thread_mutex.lock()?;
-            /*
-            *threads_inner = threads_inner
-                .clone()
-                .into_iter()
-                .filter(|el| match el {
-                    (my_thread_id, _) => false,
-                    (_, _) => true,
-                })
-                .collect();
-                 */
+            if let Err(err) = mize_clone.thread_stopped(my_thread_id) {
+                mize_clone.report_err(err);
+            }
             debug!("thread '{}' stopped", name_to_move);
             Ok(())
         };
@@ -707,7 +999,10 @@ impl Mize {
         #[cfg(feature = "target-os")]
         let handle = thread::spawn(move || to_spawn());
 
-        threads_inner.push((*next_thread_id, name.to_owned(), Some(handle)));
+        threads_inner.push((my_thread_id_no_mutex_guard, name.to_owned(), Some(handle)));
+        drop(threads_inner);
+        drop(next_thread_id);
+        introspect::changed(self, "threads")?;
 
         #[cfg(feature = "target-wasm ")]
         {
@@ -719,44 +1014,68 @@ impl Mize {
         Ok(())
     }
 
+    // a thread, that works on the instance, wait() joins it, so it has to end, once the instance
+    // is stopping
     pub fn spawn_background(
         &mut self,
         name: &str,
         func: impl FnOnce() -> MizeResult<()> + Send + 'static,
     ) -> MizeResult<()> {
+        let background = self.background.clone();
+        self.spawn_thread(name, func, Some(background))
+    }
+
+    // a thread, that blocks on something outside of the instance (eg: reading a socket or waiting
+    // for a signal), so wait() can't join it
+    pub fn spawn_detached(
+        &mut self,
+        name: &str,
+        func: impl FnOnce() -> MizeResult<()> + Send + 'static,
+    ) -> MizeResult<()> {
+        self.spawn_thread(name, func, None)
+    }
+
+    // the handle of the thread goes into join, if it is given
+    fn spawn_thread(
+        &mut self,
+        name: &str,
+        func: impl FnOnce() -> MizeResult<()> + Send + 'static,
+        join: Option<Arc<Mutex<Vec<(u32, JoinHandle<MizeResult<()>>)>>>>,
+    ) -> MizeResult<()> {
+        let mize_clone = self.clone();
         let mut threads_inner = self.threads.lock()?;
         let mut next_thread_id = self.next_thread_id.lock()?;
 
         let my_thread_id_no_mutex_guard = *next_thread_id;
-        let thread_mutex = self.threads.clone();
         let name_to_move = name.to_owned();
         let to_spawn = move || -> MizeResult<()> {
             debug!("spawning thread: {}", name_to_move);
             let my_thread_id = my_thread_id_no_mutex_guard;
 
-            func()?;
-
-            let mut threads_inner = thread_mutex.lock()?;
-            /*
-            *threads_inner = threads_inner
-                .clone()
-                .into_iter()
-                .filter(|el| match el {
-                    (my_thread_id, _) => false,
-                    (_, _) => true,
-                })
-                .collect();
-                 */
+            let result = func();
+
+            mize_clone.thread_stopped(my_thread_id)?;
             debug!("thread '{}' stopped", name_to_move);
-            Ok(())
+            result
         };
 
         *next_thread_id += 1;
 
         #[cfg(feature = "target-os")]
-        thread::spawn(move || to_spawn());
+        {
+            let handle = thread::spawn(move || to_spawn());
+            if let Some(join) = join {
+                let mut join_inner = join.lock()?;
+                // a thread, that stopped before we got here, could not remove itself
+                join_inner.retain(|(_, handle)| !handle.is_finished());
+                join_inner.push((my_thread_id_no_mutex_guard, handle));
+            }
+        }
 
-        threads_inner.push((*next_thread_id, name.to_owned(), None));
+        threads_inner.push((my_thread_id_no_mutex_guard, name.to_owned(), None));
+        drop(threads_inner);
+        drop(next_thread_id);
+        introspect::changed(self, "threads")?;
 
         #[cfg(feature = "target-wasm ")]
         {
@@ -768,10 +1087,27 @@ impl Mize {
         Ok(())
     }
 
-    pub fn give_msg_wait(&self, id: MizeId) -> MizeResult<ItemData> {
+    // wait for the Give msg for id from the connection conn_id, the request is sent with send()
+    // only after we registered as waiting, so that a fast reply can't get lost
+    pub fn give_msg_wait(
+        &self,
+        id: MizeId,
+        conn_id: u64,
+        send: impl FnOnce() -> MizeResult<()>,
+    ) -> MizeResult<ItemData> {
+        self.give_msg_receiver(id, conn_id, send)?.recv()?
+    }
+
+    // like give_msg_wait(), but the waiting is left to the caller (eg: recv_async() in async code)
+    pub(crate) fn give_msg_receiver(
+        &self,
+        id: MizeId,
+        conn_id: u64,
+        send: impl FnOnce() -> MizeResult<()>,
+    ) -> MizeResult<Receiver<MizeResult<ItemData>>> {
         let mut give_msg_wait_inner = self.give_msg_wait.lock()?;
 
-        let (tx, rx) = bounded::<ItemData>(1);
+        let (tx, rx) = bounded::<MizeResult<ItemData>>(1);
 
         let vec = match give_msg_wait_inner.get_mut(&id) {
             Some(vec) => vec,
@@ -781,15 +1117,15 @@ impl Mize {
             }
         };
 
-        vec.push(tx);
+        vec.push((conn_id, tx));
 
         // so that another thread can also give_msg_wait(), while we wait in the recv() of rx
         drop(vec);
         drop(give_msg_wait_inner);
 
-        let data = rx.recv()?;
+        send()?;
 
-        return Ok(data);
+        Ok(rx)
     }
 
     #[cfg(feature = "async")]
@@ -848,41 +1184,123 @@ impl Mize {
         return result;
     }
 
+    // returns, when the instance was shut down
     pub fn wait(&self) {
         info!("Instance main thread waiting");
-        loop {
-            thread::sleep_ms(10000000)
+        let (stopping, stopped) = &*self.stopping;
+        let mut stopping_inner = stopping.lock().expect("mutex lock failed in wait");
+        while !*stopping_inner {
+            stopping_inner = stopped
+                .wait(stopping_inner)
+                .expect("mutex lock failed in wait");
         }
-    }
+        drop(stopping_inner);
 
-    pub fn report_error(err: MizeError) {
-        err.log();
+        // so that eg: the outbox replay, rules and computed items are done, when we return
+        // a stopping thread locks background, so we can't hold it while joining
+        let background: Vec<_> = self
+            .background
+            .lock()
+            .expect("mutex lock failed in wait")
+            .drain(..)
+            .collect();
+        for (_, handle) in background {
+            if handle.thread().id() == thread::current().id() {
+                continue;
+            }
+            match handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => self.report_err(err),
+                Err(_) => error!("a background thread panicked"),
+            }
+        }
+        info!("Instance stopped");
     }
 
-    pub fn get_config(&mut self, name: &str) -> MizeResult<ItemData> {
-        let mut config_opts = self.config_opts.lock().unwrap();
+    // stops the parts and the updater threads after they did what is already queued, flushes the
+    // store, removes our socket and then closes all connections
+    // can be called from any thread (also an updater thread, when a peer asks us to stop)
+    pub fn shutdown(&self) -> MizeResult<()> {
+        {
+            let mut stopping_inner = self.stopping.0.lock()?;
+            if *stopping_inner {
+                return Ok(());
+            }
+            *stopping_inner = true;
+        }
+        info!("shutting down");
 
-        let opt = config_opts
-            .get_mut(name)
-            .ok_or_else(|| mize_err!("Config opt for {name} not in config_opts"))?;
+        self.shutdown_parts()?;
 
-        // get the cached value
-        if let Some(val) = opt.val.clone() {
-            println!("get_config: {name} has cached value: {val}");
-            return Ok(val);
+        #[cfg(feature = "async")]
+        self.stop_notify.notify_waiters();
+
+        // queue_op() would handle it right away on an updater thread
+        // what is queued after the Stops is not handled anymore
+        for _ in 0..UPDATER_THREADS {
+            self.ops.push_unbounded(Operation::Stop)?;
+        }
+        // each of them ends on one Stop, after it finished what it took before
+        // when we are on an updater thread, we get our Stop after we return
+        let updaters: Vec<_> = self.updaters.lock()?.drain(..).collect();
+        for (_, handle) in updaters {
+            if handle.thread().id() == thread::current().id() {
+                continue;
+            }
+            match handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => self.report_err(err),
+                Err(_) => error!("an updater thread panicked"),
+            }
+        }
+
+        // the computed and the rules thread end, when their sender is gone
+        self.computed.lock()?.updates.stop();
+        self.rules.lock()?.triggered.stop();
+
+        self.stop_recording()?;
+        self.store.lock()?.flush()?;
+        crate::platform::any::instance_shutdown(self)?;
+
+        // last, so that a peer, that waits for us to close it's connection (eg: `mize stop`),
+        // knows that the store is flushed and our socket is gone
+        let connections = self.connections.lock()?.clone();
+        for connection in connections {
+            self.remove_connection(connection.id)?;
         }
 
-        // Evaluate the thunk
-        let thunk = opt
-            .thunk
-            .take()
-            .ok_or_else(|| mize_err!("ConfigOpt {name} does not have a thunk"))?;
-        let result = thunk();
+        self.stopping.1.notify_all();
+        info!("shut down");
+        Ok(())
+    }
+
+    pub fn is_stopping(&self) -> MizeResult<bool> {
+        Ok(*self.stopping.0.lock()?)
+    }
+
+    // resolves, when shutdown() is called
+    #[cfg(feature = "async")]
+    pub async fn stopped(&self) {
+        // created before the check, so that a notify in between is not missed
+        let notified = self.stop_notify.notified();
+        if self.is_stopping().unwrap_or(true) {
+            return;
+        }
+        notified.await;
+    }
 
-        // Cache the result
-        opt.val = Some(result.clone());
+    pub fn report_error(err: MizeError) {
+        err.log();
+    }
 
-        return Ok(result);
+    pub fn get_config(&self, name: &str) -> MizeResult<ItemData> {
+        match config::config_value(self, name)? {
+            Some((value, source)) => {
+                debug!("get_config: {name} from {source}: {value}");
+                Ok(value)
+            }
+            None => Err(config::missing_option(self, name)),
+        }
     }
 
     pub fn new_opt(&mut self, name: &str) -> ConfigOptNameAndMize {
@@ -892,8 +1310,7 @@ impl Mize {
             None => {
                 let opt = ConfigOpt {
                     name: name.to_owned(),
-                    val: None,
-                    thunk: None,
+                    ..Default::default()
                 };
                 config_opts.insert(name.to_string(), opt);
             }
@@ -905,31 +1322,17 @@ impl Mize {
     }
 
     pub fn run(&mut self) -> MizeResult<()> {
-        let mut parts = self.parts.lock().unwrap();
-        for part in parts.values_mut() {
-            part.as_deref_mut().unwrap().run(&mut self.clone())?;
-        }
-        let mut threads_inner = self.threads.lock().unwrap();
-        for (id, name, handle) in threads_inner.drain(..) {
+        self.run_parts()?;
+        // a stopping thread locks threads, so we can't hold it while joining
+        let threads: Vec<_> = self.threads.lock().unwrap().drain(..).collect();
+        for (id, name, handle) in threads {
             if let Some(handle) = handle {
                 handle.join().unwrap()?;
             }
         }
-        Ok(())
+        self.shutdown_parts()
     }
 
-    pub fn get_part_native<T: MizePart + Send + Sync + 'static>(
-        &mut self,
-        name: &str,
-    ) -> MizeResult<MizePartGuard<T>> {
-        let mut dyn_guard = self.get_part(name)?;
-        let part = dyn_guard.part.take().unwrap();
-        let concrete_part = part.into_any().downcast::<T>().unwrap();
-        Ok(MizePartGuard {
-            mize: self.clone(),
-            part: Some(*concrete_part),
-        })
-    }
     pub fn add_name_only_part(&mut self, name: &'static str) {
         let part = NameOnlyPart {
             mize: self.clone(),
@@ -948,16 +1351,16 @@ struct NameOnlyPart {
 impl MizePart for NameOnlyPart {}
 impl MizePartGenerated for NameOnlyPart {
     fn as_any_generated(&self) -> &dyn Any {
-        self.as_any()
+        self
     }
     fn as_any_mut_generated(&mut self) -> &mut dyn Any {
-        self.as_any_mut()
+        self
     }
     fn get_mize_generated(&mut self) -> &mut Mize {
         &mut self.mize
     }
     fn into_any_generated(self: Box<Self>) -> Box<dyn Any> {
-        self.into_any()
+        self
     }
     fn name_generated(&self) -> &'static str {
         self.name
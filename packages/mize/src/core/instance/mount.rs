@@ -0,0 +1,303 @@
+use std::sync::{Arc, Mutex};
+
+use crate::error::{MizeError, MizeResult};
+use crate::id::MizeId;
+use crate::instance::store::{IdIter, Store};
+use crate::item::{Item, ItemData};
+use crate::mize_err;
+
+use super::{introspect, updater, Mize};
+
+// the mount table: a range of store_parts can be kept in a different store, than the rest
+// (eg: item 0 in memory and bulk data in an other folder), all other store_parts are in the root
+// store
+//
+// the store of an instance is always a MountStore, that asks the store of the mount an id is in
+// new ids are allocated in the range of a mount (Mize::new_item_in()) or around all mounts
+// (Mize::new_item()), so that ids never end up in the wrong store
+//
+// config options:
+// - mount.<name>.from and mount.<name>.to: the range of store_parts (to is not part of it)
+// - mount.<name>.store: mem or file
+// - mount.<name>.path: the folder of a file store
+// - mount.<name>.read_only: no set and no new ids
+//
+// the mounts are readable as inst/mounts
+
+#[derive(Clone, Debug)]
+pub struct Mount {
+    pub name: String,
+    pub from: u64,
+    pub to: u64,
+    pub read_only: bool,
+    pub store: Box<dyn Store>,
+}
+
+impl Mount {
+    fn contains(&self, store_part: u64) -> bool {
+        (self.from..self.to).contains(&store_part)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MountStore {
+    root: Box<dyn Store>,
+    // shared with the instance, sorted by from
+    mounts: Arc<Mutex<Vec<Mount>>>,
+}
+
+impl MountStore {
+    pub fn new(root: Box<dyn Store>, mounts: Arc<Mutex<Vec<Mount>>>) -> MountStore {
+        MountStore { root, mounts }
+    }
+
+    // the mount, that id is in, None for the root store
+    fn mount_of(&self, store_part: &str) -> MizeResult<Option<Mount>> {
+        let store_part: u64 = match store_part.parse() {
+            Ok(store_part) => store_part,
+            Err(_) => return Ok(None),
+        };
+        Ok(self
+            .mounts
+            .lock()?
+            .iter()
+            .find(|mount| mount.contains(store_part))
+            .cloned())
+    }
+
+    fn store_for(&self, id: &MizeId) -> MizeResult<Box<dyn Store>> {
+        Ok(match self.mount_of(id.store_part())? {
+            Some(mount) => mount.store,
+            None => self.root.clone(),
+        })
+    }
+}
+
+impl Store for MountStore {
+    fn set(&self, id: MizeId, data: ItemData) -> MizeResult<()> {
+        match self.mount_of(id.store_part())? {
+            Some(mount) if mount.read_only => Err(mize_err!(
+                "'{}' is in the read only mount '{}'",
+                id,
+                mount.name
+            )),
+            Some(mount) => mount.store.set(id, data),
+            None => self.root.set(id, data),
+        }
+    }
+
+    fn get_version(&self, id: MizeId) -> MizeResult<u64> {
+        self.store_for(&id)?.get_version(id)
+    }
+
+    fn get_links(&self, item: Item) -> MizeResult<Vec<MizeId>> {
+        self.store_for(&item.id())?.get_links(item)
+    }
+
+    fn get_backlinks(&self, item: Item) -> MizeResult<Vec<MizeId>> {
+        self.store_for(&item.id())?.get_backlinks(item)
+    }
+
+    // in the root store, but not in the range of a mount
+    fn new_id(&self) -> MizeResult<String> {
+        let mounts = self.mounts.lock()?.clone();
+
+        let mut from = 0;
+        for mount in mounts {
+            if mount.from > from {
+                if let Some(id) = self.root.new_id_in(from, mount.from)? {
+                    return Ok(id);
+                }
+            }
+            from = from.max(mount.to);
+        }
+
+        self.root
+            .new_id_in(from, u64::MAX)?
+            .ok_or(mize_err!("the root store has no ids left"))
+    }
+
+    fn new_id_in(&self, from: u64, to: u64) -> MizeResult<Option<String>> {
+        self.root.new_id_in(from, to)
+    }
+
+    fn get_value_raw(&self, id: MizeId) -> MizeResult<Vec<u8>> {
+        self.store_for(&id)?.get_value_raw(id)
+    }
+
+    fn get_value_data_full(&self, id: MizeId) -> MizeResult<ItemData> {
+        self.store_for(&id)?.get_value_data_full(id)
+    }
+
+    fn id_iter(&self) -> MizeResult<IdIter> {
+        IdIter::new(Box::new(self.to_owned()))
+    }
+
+    // the smallest of what the root and every mount has after prev, each only in it's own range
+    fn next_id(&self, prev_id: &str) -> MizeResult<Option<String>> {
+        let prev: u64 = prev_id.parse()?;
+        let mounts = self.mounts.lock()?.clone();
+
+        let mut next: Option<u64> = None;
+        let mut candidate = |id: u64| {
+            if next.map_or(true, |next| id < next) {
+                next = Some(id);
+            }
+        };
+
+        // what the root has in the range of a mount is hidden by the mount
+        let mut root_prev = prev_id.to_owned();
+        while let Some(id) = self.root.next_id(&root_prev)? {
+            let store_part: u64 = id.parse()?;
+            match mounts.iter().find(|mount| mount.contains(store_part)) {
+                Some(mount) => root_prev = format!("{}", mount.to - 1),
+                None => {
+                    candidate(store_part);
+                    break;
+                }
+            }
+        }
+
+        for mount in mounts.iter() {
+            if mount.to == 0 || prev >= mount.to - 1 {
+                continue;
+            }
+            let after = if prev < mount.from && mount.from > 0 {
+                mount.from - 1
+            } else {
+                prev
+            };
+            if let Some(id) = mount.store.next_id(&format!("{}", after))? {
+                let store_part: u64 = id.parse()?;
+                if mount.contains(store_part) {
+                    candidate(store_part);
+                }
+            }
+        }
+
+        Ok(next.map(|id| format!("{}", id)))
+    }
+
+    fn first_id(&self) -> MizeResult<String> {
+        self.root.first_id()
+    }
+
+    fn flush(&self) -> MizeResult<()> {
+        self.root.flush()?;
+        for mount in self.mounts.lock()?.clone() {
+            mount.store.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Mize {
+    // keep the store_parts in from..to in store
+    pub fn mount(
+        &self,
+        name: &str,
+        from: u64,
+        to: u64,
+        store: Box<dyn Store>,
+        read_only: bool,
+    ) -> MizeResult<()> {
+        if from >= to {
+            return Err(mize_err!(
+                "the mount '{}' has an empty range {}..{}",
+                name,
+                from,
+                to
+            ));
+        }
+
+        if let Some(err) = clash(&self.mounts.lock()?, name, from, to) {
+            return Err(err);
+        }
+
+        // what the root has in the range would be hidden by the mount, so the mount gets a copy
+        // of it (eg: the config in item 0), unless it already has something there itself
+        // the mounts lock is not held here, the MountStore takes it on every call
+        let root = self.store.lock()?.clone();
+        let mut store_part = Some(format!("{}", from));
+        while let Some(part) = store_part {
+            let part_num: u64 = part.parse()?;
+            if part_num >= to {
+                break;
+            }
+            let id = self.id_from_string(part.clone())?;
+            let data = root
+                .get_value_data_full(id.clone())
+                .unwrap_or(ItemData::new());
+            if data != ItemData::new() && store.get_value_data_full(id.clone())? == ItemData::new()
+            {
+                store.set(id, data)?;
+            }
+            store_part = root.next_id(&part)?;
+        }
+
+        let mut mounts = self.mounts.lock()?;
+        // an other mount could have been added in the meantime
+        if let Some(err) = clash(&mounts, name, from, to) {
+            return Err(err);
+        }
+        mounts.push(Mount {
+            name: name.to_owned(),
+            from,
+            to,
+            read_only,
+            store,
+        });
+        mounts.sort_by_key(|mount| mount.from);
+        drop(mounts);
+
+        introspect::changed(self, "mounts")?;
+        updater::notify_subs(self, &self.new_id("inst/ids")?, &None)
+    }
+
+    // a new item in the range of the mount name
+    pub fn new_item_in(&self, name: &str) -> MizeResult<Item> {
+        if !self.we_are_namespace()? {
+            return Err(mize_err!(
+                "can't create an item in the mount '{}', we are not the owner of the namespace",
+                name
+            ));
+        }
+
+        let mount = self
+            .mounts
+            .lock()?
+            .iter()
+            .find(|mount| mount.name == name)
+            .cloned()
+            .ok_or(mize_err!("there is no mount '{}'", name))?;
+        if mount.read_only {
+            return Err(mize_err!("the mount '{}' is read only", name));
+        }
+
+        let id = mount
+            .store
+            .new_id_in(mount.from, mount.to)?
+            .ok_or(mize_err!("the mount '{}' has no ids left", name))?;
+        let id = self.id_from_string(id)?;
+
+        // replicas sub to the list of ids
+        updater::notify_subs(self, &self.new_id("inst/ids")?, &None)?;
+
+        Ok(Item::new(id, self))
+    }
+}
+
+fn clash(mounts: &[Mount], name: &str, from: u64, to: u64) -> Option<MizeError> {
+    let other = mounts
+        .iter()
+        .find(|other| other.name == name || (from < other.to && other.from < to))?;
+    Some(mize_err!(
+        "the mount '{}' ({}..{}) clashes with the mount '{}' ({}..{})",
+        name,
+        from,
+        to,
+        other.name,
+        other.from,
+        other.to
+    ))
+}
@@ -0,0 +1,359 @@
+use std::any::type_name;
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use crate::error::{MizeError, MizeResult};
+use crate::mize_err;
+
+use super::updater::Operation;
+use super::{introspect, Mize, MizePart};
+
+// shared access to the parts of an instance
+//
+// every part is behind a PartLock, which works like a RwLock:
+// - any number of threads can read a part at the same time (read_part(), read_part_native())
+// - one thread can write it (get_part(), get_part_native()), when no one else uses it
+// - a thread waits up to PART_WAIT_TIMEOUT for the others and then fails, a waiting writer goes
+//   before new readers, so that they can't starve it
+// - it knows, which threads use the part, so a thread, that already uses it and then asks to write
+//   it (or to read it while writing it), gets a deadlock error instead of waiting for itself
+//   reading a part, that the thread already reads, is fine
+// - the guards are not Send, a part is unlocked by the thread, that locked it, so the threads tell
+//   the users apart, a guard must not be held across an .await either (two tasks on the same
+//   thread would look like one user)
+//
+// inst/parts shows, whether a part is available, read or written
+// it's subscribers are told, when that changes: by the thread, that locked the part, and, for an
+// unlock, by an updater thread, because the guard, that is dropped, could be dropped anywhere (eg:
+// while the subs are locked)
+
+pub(crate) static PART_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type BoxedPart = Box<dyn MizePart + Send + Sync>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Access {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Users {
+    readers: Vec<ThreadId>,
+    writer: Option<ThreadId>,
+    writers_waiting: usize,
+}
+
+impl Users {
+    fn state(&self) -> &'static str {
+        if self.writer.is_some() {
+            "written"
+        } else if !self.readers.is_empty() {
+            "read"
+        } else {
+            "available"
+        }
+    }
+}
+
+pub(crate) struct PartLock {
+    name: &'static str,
+    users: Mutex<Users>,
+    released: Condvar,
+    part: UnsafeCell<BoxedPart>,
+}
+
+// SAFETY: the part is only reached through a PartAccess, which lock() only hands out shared (any
+// number of readers) or exclusive (one writer, no readers), like the guards of a RwLock, and the
+// part itself is Send + Sync
+unsafe impl Sync for PartLock {}
+
+impl PartLock {
+    pub(crate) fn new(part: BoxedPart) -> Arc<PartLock> {
+        Arc::new(PartLock {
+            name: part.name(),
+            users: Mutex::new(Users::default()),
+            released: Condvar::new(),
+            part: UnsafeCell::new(part),
+        })
+    }
+
+    // also returns, whether the state (see state()) changed
+    fn lock(&self, access: Access, timeout: Duration) -> MizeResult<(ThreadId, bool)> {
+        let me = thread::current().id();
+        let mut users = self.users.lock()?;
+
+        let nested =
+            users.writer == Some(me) || (access == Access::Write && users.readers.contains(&me));
+        if nested {
+            return Err(mize_err!(
+                "deadlock: this thread already uses the part '{}' and wants to {} it",
+                self.name,
+                access
+            ));
+        }
+
+        let deadline = Instant::now() + timeout;
+        if access == Access::Write {
+            users.writers_waiting += 1;
+        }
+        loop {
+            let free = match access {
+                Access::Read => {
+                    users.writer.is_none()
+                        && (users.writers_waiting == 0 || users.readers.contains(&me))
+                }
+                Access::Write => users.writer.is_none() && users.readers.is_empty(),
+            };
+            if free {
+                break;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                if access == Access::Write {
+                    users.writers_waiting -= 1;
+                    // the readers, that waited for us
+                    self.released.notify_all();
+                }
+                return Err(mize_err!(
+                    "the part '{}' is still used by another thread after {:?}, can't {} it",
+                    self.name,
+                    timeout,
+                    access
+                ));
+            }
+            users = self.released.wait_timeout(users, deadline - now)?.0;
+        }
+
+        let before = users.state();
+        match access {
+            Access::Read => users.readers.push(me),
+            Access::Write => {
+                users.writers_waiting -= 1;
+                users.writer = Some(me);
+            }
+        }
+        Ok((me, users.state() != before))
+    }
+
+    // returns, whether the state changed
+    fn unlock(&self, access: Access, thread: ThreadId) -> bool {
+        // a poisoned lock is still unlocked, or no one could use the part again
+        let mut users = self.users.lock().unwrap_or_else(|err| err.into_inner());
+        let before = users.state();
+        match access {
+            Access::Read => {
+                if let Some(pos) = users.readers.iter().position(|reader| *reader == thread) {
+                    users.readers.remove(pos);
+                }
+            }
+            Access::Write => users.writer = None,
+        }
+        self.released.notify_all();
+        users.state() != before
+    }
+
+    pub(crate) fn state(&self) -> MizeResult<&'static str> {
+        Ok(self.users.lock()?.state())
+    }
+}
+
+// holds a part for reading or writing, until it is dropped
+struct PartAccess {
+    lock: Arc<PartLock>,
+    access: Access,
+    thread: ThreadId,
+    mize: Mize,
+    // not Send, like the guard of a Mutex (see the top)
+    not_send: PhantomData<MutexGuard<'static, ()>>,
+}
+
+impl PartAccess {
+    fn part(&self) -> &(dyn MizePart + Send + Sync + 'static) {
+        // SAFETY: we hold the lock for reading or writing until we are dropped, so no one else
+        // writes the part, while the reference lives, it can't outlive &self
+        unsafe { &**self.lock.part.get() }
+    }
+
+    fn part_mut(&mut self) -> &mut (dyn MizePart + Send + Sync + 'static) {
+        debug_assert_eq!(self.access, Access::Write);
+        // SAFETY: only the guards for writing call this, so we hold the lock for writing and no
+        // one else reads or writes the part, while the reference lives, it can't outlive &mut
+        // self, so there is only one of it
+        unsafe { &mut **self.lock.part.get() }
+    }
+}
+
+impl Drop for PartAccess {
+    fn drop(&mut self) {
+        // while stopping, the updater threads might be gone already
+        let changed = self.lock.unlock(self.access, self.thread);
+        if changed && !self.mize.is_stopping().unwrap_or(true) {
+            if let Err(err) = self.mize.ops.push_unbounded(Operation::Changed("parts")) {
+                self.mize.report_err(err);
+            }
+        }
+    }
+}
+
+pub struct DynMizePartGuard {
+    inner: PartAccess,
+}
+
+pub struct DynMizePartReadGuard {
+    inner: PartAccess,
+}
+
+pub struct MizePartGuard<T: MizePart + Send + Sync + 'static> {
+    inner: PartAccess,
+    part: PhantomData<T>,
+}
+
+pub struct MizePartReadGuard<T: MizePart + Send + Sync + 'static> {
+    inner: PartAccess,
+    part: PhantomData<T>,
+}
+
+impl Deref for DynMizePartGuard {
+    type Target = dyn MizePart + Send + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.part()
+    }
+}
+
+impl DerefMut for DynMizePartGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner.part_mut()
+    }
+}
+
+impl Deref for DynMizePartReadGuard {
+    type Target = dyn MizePart + Send + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.part()
+    }
+}
+
+// the type was checked, when the guard was made
+impl<T: MizePart + Send + Sync> Deref for MizePartGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.part().as_any().downcast_ref::<T>().unwrap()
+    }
+}
+
+impl<T: MizePart + Send + Sync> DerefMut for MizePartGuard<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner
+            .part_mut()
+            .as_any_mut()
+            .downcast_mut::<T>()
+            .unwrap()
+    }
+}
+
+impl<T: MizePart + Send + Sync> Deref for MizePartReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.part().as_any().downcast_ref::<T>().unwrap()
+    }
+}
+
+impl Mize {
+    // write access to the part
+    pub fn get_part(&self, name: &str) -> MizeResult<DynMizePartGuard> {
+        Ok(DynMizePartGuard {
+            inner: self.access_part(name, Access::Write, PART_WAIT_TIMEOUT)?,
+        })
+    }
+
+    pub fn read_part(&self, name: &str) -> MizeResult<DynMizePartReadGuard> {
+        Ok(DynMizePartReadGuard {
+            inner: self.access_part(name, Access::Read, PART_WAIT_TIMEOUT)?,
+        })
+    }
+
+    pub fn get_part_native<T: MizePart + Send + Sync + 'static>(
+        &self,
+        name: &str,
+    ) -> MizeResult<MizePartGuard<T>> {
+        let inner = self.access_part(name, Access::Write, PART_WAIT_TIMEOUT)?;
+        check_type::<T>(name, &inner)?;
+        Ok(MizePartGuard {
+            inner,
+            part: PhantomData,
+        })
+    }
+
+    pub fn read_part_native<T: MizePart + Send + Sync + 'static>(
+        &self,
+        name: &str,
+    ) -> MizeResult<MizePartReadGuard<T>> {
+        let inner = self.access_part(name, Access::Read, PART_WAIT_TIMEOUT)?;
+        check_type::<T>(name, &inner)?;
+        Ok(MizePartReadGuard {
+            inner,
+            part: PhantomData,
+        })
+    }
+
+    // write access, only if no one uses the part right now
+    pub(crate) fn try_get_part(&self, name: &str) -> Option<DynMizePartGuard> {
+        self.access_part(name, Access::Write, Duration::ZERO)
+            .ok()
+            .map(|inner| DynMizePartGuard { inner })
+    }
+
+    fn access_part(&self, name: &str, access: Access, timeout: Duration) -> MizeResult<PartAccess> {
+        let lock = self
+            .parts
+            .lock()?
+            .get(name)
+            .cloned()
+            .ok_or(mize_err!("there is no part '{}'", name))?;
+        let (thread, changed) = lock.lock(access, timeout)?;
+
+        // made first, so that the part is unlocked again, if this fails
+        let part_access = PartAccess {
+            lock,
+            access,
+            thread,
+            mize: self.clone(),
+            not_send: PhantomData,
+        };
+        if changed {
+            introspect::changed(self, "parts")?;
+        }
+        Ok(part_access)
+    }
+}
+
+fn check_type<T: MizePart + 'static>(name: &str, access: &PartAccess) -> MizeResult<()> {
+    if !access.part().as_any().is::<T>() {
+        return Err(mize_err!(
+            "the part '{}' is not a {}",
+            name,
+            type_name::<T>()
+        ));
+    }
+    Ok(())
+}
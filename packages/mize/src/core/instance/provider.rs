@@ -0,0 +1,204 @@
+use ciborium::Value as CborValue;
+use std::sync::Arc;
+
+use crate::error::{MizeError, MizeResult};
+use crate::id::{IntoMizeId, MizeId};
+use crate::instance::connection::value_raw_con_by_id;
+use crate::item::{Item, ItemData};
+use crate::mize_err;
+
+use super::{introspect, queue, replica, sync, updater, Mize};
+
+// items, that are not in the store, but made up by code (eg: the state of the instance, system
+// stats or the time) come from providers
+//
+// a provider is added for a prefix of ids (eg: "inst" or "sys/stats") and gets every get, set and
+// sub of an id under it, for which handles() is true, all other ids are treated like any other
+// the provider with the longest prefix is asked first
+//
+// a provider calls Mize::changed(), when one of it's items changes, so that subscribers get an
+// update
+//
+// built in are the providers for inst/* and self/*
+
+pub trait Provider: Send + Sync {
+    // by default all ids under the prefix
+    fn handles(&self, instance: &Mize, id: &MizeId) -> MizeResult<bool> {
+        Ok(true)
+    }
+
+    fn get(&self, instance: &Mize, id: &MizeId) -> MizeResult<ItemData>;
+
+    fn set(&self, instance: &Mize, id: &MizeId, data: ItemData) -> MizeResult<()> {
+        Err(mize_err!("'{}' can't be written", id))
+    }
+
+    // someone subscribed to id, the subscription itself is kept by the instance
+    fn sub(&self, instance: &Mize, id: &MizeId) -> MizeResult<()> {
+        Ok(())
+    }
+}
+
+// providers are added rarely, but looked up on every get and set, so adding copies the list and
+// a lookup only clones the Arc of it
+pub struct Providers {
+    entries: Arc<[(Vec<String>, Arc<dyn Provider>)]>,
+}
+
+impl Default for Providers {
+    fn default() -> Self {
+        let mut providers = Providers {
+            entries: Arc::new([]),
+        };
+        providers.add("inst", Arc::new(InstProvider));
+        providers.add("self", Arc::new(SelfProvider));
+        providers
+    }
+}
+
+impl Providers {
+    fn add(&mut self, prefix: &str, provider: Arc<dyn Provider>) {
+        let prefix: Vec<String> = prefix
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .map(|part| part.to_owned())
+            .collect();
+        let mut entries = self.entries.to_vec();
+        entries.push((prefix, provider));
+
+        // stable, so of two providers for the same prefix, the first one added is asked first
+        entries.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        self.entries = entries.into();
+    }
+}
+
+impl Mize {
+    pub fn add_provider(&self, prefix: &str, provider: impl Provider + 'static) -> MizeResult<()> {
+        self.providers.lock()?.add(prefix, Arc::new(provider));
+        Ok(())
+    }
+
+    // tell the subscribers of a provided item, that it changed
+    pub fn changed<I: IntoMizeId>(&self, id: I) -> MizeResult<()> {
+        let id = id.to_mize_id(self)?;
+        updater::notify_subs(self, &id, &None)
+    }
+}
+
+// the provider, that serves id, if there is one
+pub fn provider_for(instance: &Mize, id: &MizeId) -> MizeResult<Option<Arc<dyn Provider>>> {
+    // a provider may use the instance, so it can't run while we hold the lock
+    let entries = instance.providers.lock()?.entries.clone();
+
+    let path: Vec<String> = id.path().into_iter().map(|p| p.to_owned()).collect();
+    for (prefix, provider) in entries.iter() {
+        let matches =
+            prefix.len() <= path.len() && prefix.iter().zip(path.iter()).all(|(a, b)| a == b);
+        if matches && provider.handles(instance, id)? {
+            return Ok(Some(provider.clone()));
+        }
+    }
+    Ok(None)
+}
+
+// inst/*: the state of the instance, not in the store
+// only our own, except for the state of a replica and our queues, which are ours, even in the
+// namespace of the owner
+struct InstProvider;
+
+impl Provider for InstProvider {
+    fn handles(&self, instance: &Mize, id: &MizeId) -> MizeResult<bool> {
+        Ok(instance.is_own_namespace(&id.namespace())?
+            || matches!(id.nth_part(1)?, "replica" | "queues"))
+    }
+
+    fn get(&self, instance: &Mize, id: &MizeId) -> MizeResult<ItemData> {
+        let rest = id.after_store_part()[1..].to_vec();
+        match id.nth_part(1)? {
+            "con_by_id" => value_raw_con_by_id(&mut Item::new(id.clone(), instance)),
+            "namespace" => Ok(ItemData::from_string(
+                instance.get_namespace()?.as_real_string(),
+            )),
+            "self_namespace" => Ok(ItemData::from_string(
+                instance.get_self_namespace()?.as_real_string(),
+            )),
+            // only sent to subscribers (replicas), with the id of an item, when it is created
+            "new_item" => Ok(ItemData::new()),
+            // all store_parts we have, replicas get this once
+            "ids" => {
+                let store_inner = instance.store.lock()?;
+                let ids = store_inner
+                    .id_iter()?
+                    .map(|id| id.map(CborValue::Text))
+                    .collect::<MizeResult<Vec<CborValue>>>()?;
+                Ok(ItemData::from_cbor(CborValue::Array(ids)))
+            }
+            "replica" => replica::inst_item(instance, rest),
+            "queues" => queue::inst_item(instance, rest),
+            // if we are stopping, writing it stops us (see `mize stop`)
+            "shutdown" => Ok(ItemData::from_cbor(CborValue::Bool(
+                instance.is_stopping()?,
+            ))),
+            name => introspect::inst_item(instance, name, rest)?
+                .ok_or(mize_err!("'{}' is not an inst item", id)),
+        }
+    }
+
+    fn set(&self, instance: &Mize, id: &MizeId, data: ItemData) -> MizeResult<()> {
+        match id.nth_part(1)? {
+            "shutdown" => instance.shutdown(),
+            other => Err(mize_err!("inst/{} can't be written", other)),
+        }
+    }
+}
+
+// self/*: the instance we are, the config, the acl rules and the automation rules and their runs
+// are in 0/config, 0/acl, 0/rules and 0/rule_runs, whatever the namespace is
+// the outbox and conflicts of offline writes (self/sync) are kept by sync.rs
+struct SelfProvider;
+
+impl SelfProvider {
+    fn id_in_store(instance: &Mize, id: &MizeId) -> MizeResult<MizeId> {
+        match id.nth_part(1)? {
+            "config" | "acl" | "rules" | "rule_runs" => {
+                let rest_path = id.after_store_part().join("/");
+                instance.new_id("0/".to_owned() + rest_path.as_str())
+            }
+            other => Err(mize_err!(
+                "a /self path, but the next element in the path '{}' is not valid",
+                other
+            )),
+        }
+    }
+}
+
+impl Provider for SelfProvider {
+    fn get(&self, instance: &Mize, id: &MizeId) -> MizeResult<ItemData> {
+        match id.nth_part(1)? {
+            "con_by_id" => value_raw_con_by_id(&mut Item::new(id.clone(), instance)),
+            "namespace" => Ok(ItemData::from_string(
+                instance.get_namespace()?.as_real_string(),
+            )),
+            "self_namespace" => Ok(ItemData::from_string(
+                instance.get_self_namespace()?.as_real_string(),
+            )),
+            "sync" => sync::get(instance, id.after_store_part()[1..].to_vec()),
+            _ => {
+                let id_in_store = Self::id_in_store(instance, id)?;
+                let store = instance.store.lock()?;
+                store.get_value_data_full(id_in_store)
+            }
+        }
+    }
+
+    fn set(&self, instance: &Mize, id: &MizeId, data: ItemData) -> MizeResult<()> {
+        if id.nth_part(1)? == "sync" {
+            return sync::set(instance, id.after_store_part()[1..].to_vec(), data);
+        }
+        let id_in_store = Self::id_in_store(instance, id)?;
+        let store = instance.store.lock()?;
+        let mut full = store.get_value_data_full(id_in_store.clone())?;
+        full.merge(data);
+        store.set(id_in_store, full)
+    }
+}
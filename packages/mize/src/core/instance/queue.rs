@@ -0,0 +1,390 @@
+use ciborium::Value as CborValue;
+use flume::{unbounded, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
+use std::fmt;
+use std::future::poll_fn;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Poll, Waker};
+use std::time::Duration;
+
+use crate::error::{MizeError, MizeResult};
+use crate::item::ItemData;
+use crate::mize_err;
+use crate::proto::{MessageCmd, MizeMessage};
+
+use super::updater::Operation;
+use super::{Mize, MSG_CHANNEL_SIZE};
+
+// the send queue of every connection and the operation queue have a size
+// what happens, when a queue is full, is up to the config option queue.full
+//
+// config options:
+// - queue.size: the size of the send queue of a connection
+// - queue.ops: the size of the operation queue
+// - queue.full: block, drop-oldest or disconnect
+//
+// the depths are readable as inst/queues
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FullPolicy {
+    // wait until there is space again
+    Block,
+    // drop the oldest queued Update for the same id, if there is none, wait like Block
+    DropOldest,
+    // close the connection, that fills the queue
+    Disconnect,
+}
+
+impl FullPolicy {
+    pub fn from_config(instance: &Mize) -> MizeResult<FullPolicy> {
+        let policy = match instance.get("self/config/queue/full")?.value_string() {
+            Ok(policy) => policy,
+            Err(_) => return Ok(FullPolicy::Block),
+        };
+
+        match policy.as_str() {
+            "block" => Ok(FullPolicy::Block),
+            "drop-oldest" => Ok(FullPolicy::DropOldest),
+            "disconnect" => Ok(FullPolicy::Disconnect),
+            other => Err(mize_err!(
+                "unknown queue.full policy '{}', expected one of: block, drop-oldest, disconnect",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    size: usize,
+    policy: FullPolicy,
+}
+
+struct State {
+    limits: Limits,
+    // the QueueReceivers, that still exist
+    receivers: usize,
+    // async pushers, that wait for space
+    wakers: Vec<Waker>,
+}
+
+struct Shared {
+    // held while pushing, so that concurrent pushes keep their order, but never while waiting
+    state: Mutex<State>,
+    // a QueueReceiver took something out or went away
+    freed: Condvar,
+}
+
+impl Shared {
+    fn freed(&self) {
+        // a poisoned lock still has to wake the pushers
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+        self.freed.notify_all();
+    }
+}
+
+// a channel with a size and a FullPolicy, that can be changed after it is created
+// the consumer gets a QueueReceiver, which wakes the pushers waiting for space
+pub struct Queue<T> {
+    tx: Sender<T>,
+    // our own receiver, to take old entries out again
+    rx: Receiver<T>,
+    shared: Arc<Shared>,
+}
+
+impl<T> Clone for Queue<T> {
+    fn clone(&self) -> Queue<T> {
+        Queue {
+            tx: self.tx.clone(),
+            rx: self.rx.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Queue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Queue {{ len: {} }}", self.len())
+    }
+}
+
+impl<T> Queue<T> {
+    pub fn new(size: usize, policy: FullPolicy) -> Queue<T> {
+        let (tx, rx) = unbounded();
+        Queue {
+            tx,
+            rx,
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    limits: Limits { size, policy },
+                    receivers: 0,
+                    wakers: Vec::new(),
+                }),
+                freed: Condvar::new(),
+            }),
+        }
+    }
+
+    pub fn receiver(&self) -> QueueReceiver<T> {
+        QueueReceiver::new(self.rx.clone(), self.shared.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tx.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tx.is_empty()
+    }
+
+    pub fn set_limits(&self, size: usize, policy: FullPolicy) -> MizeResult<()> {
+        self.shared.state.lock()?.limits = Limits { size, policy };
+        // a bigger size makes space
+        self.shared.freed();
+        Ok(())
+    }
+
+    // returns false, if the queue is full and it's policy is to disconnect
+    // same tells, if an old entry may be dropped for the new one
+    pub fn push(&self, item: T, same: impl Fn(&T, &T) -> bool) -> MizeResult<bool> {
+        let state = self.shared.state.lock()?;
+        if self.tx.len() < state.limits.size {
+            self.tx.send(item)?;
+            return Ok(true);
+        }
+
+        match state.limits.policy {
+            FullPolicy::Disconnect => return Ok(false),
+            FullPolicy::DropOldest => {
+                let mut queued: Vec<T> = self.rx.drain().collect();
+                let dropped = match queued.iter().position(|old| same(old, &item)) {
+                    Some(pos) => {
+                        queued.remove(pos);
+                        true
+                    }
+                    None => false,
+                };
+                for old in queued {
+                    self.tx.send(old)?;
+                }
+                if dropped {
+                    self.tx.send(item)?;
+                    return Ok(true);
+                }
+            }
+            FullPolicy::Block => {}
+        }
+
+        let _state = self.wait_for_space(state)?;
+        self.tx.send(item)?;
+        Ok(true)
+    }
+
+    // ignores the policy, a full queue always waits
+    pub fn push_blocking(&self, item: T) -> MizeResult<()> {
+        let _state = self.wait_for_space(self.shared.state.lock()?)?;
+        self.tx.send(item)?;
+        Ok(())
+    }
+
+    // like push_blocking(), but for async code, that must not block the thread it runs on
+    pub async fn push_async(&self, item: T) -> MizeResult<()> {
+        let mut item = Some(item);
+        poll_fn(|cx| {
+            let mut state = self.shared.state.lock()?;
+            if self.tx.len() < state.limits.size {
+                if let Some(item) = item.take() {
+                    self.tx.send(item)?;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            if state.receivers == 0 {
+                return Poll::Ready(Err(mize_err!(
+                    "queue is full and no one is receiving from it anymore"
+                )));
+            }
+            state.wakers.push(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+
+    // ignores the size, for the few things, that must never wait (eg: a disconnect)
+    pub fn push_unbounded(&self, item: T) -> MizeResult<()> {
+        self.tx.send(item)?;
+        Ok(())
+    }
+
+    // the lock is given up while waiting and held again, when there is space
+    fn wait_for_space<'a>(
+        &self,
+        mut state: MutexGuard<'a, State>,
+    ) -> MizeResult<MutexGuard<'a, State>> {
+        while self.tx.len() >= state.limits.size {
+            // no one will ever make space
+            if state.receivers == 0 {
+                return Err(mize_err!(
+                    "queue is full and no one is receiving from it anymore"
+                ));
+            }
+            state = self.shared.freed.wait(state)?;
+        }
+        Ok(state)
+    }
+}
+
+// the receiving end of a Queue, like a flume Receiver
+pub struct QueueReceiver<T> {
+    rx: Receiver<T>,
+    shared: Arc<Shared>,
+}
+
+impl<T> QueueReceiver<T> {
+    fn new(rx: Receiver<T>, shared: Arc<Shared>) -> QueueReceiver<T> {
+        shared
+            .state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .receivers += 1;
+        QueueReceiver { rx, shared }
+    }
+
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let item = self.rx.recv()?;
+        self.shared.freed();
+        Ok(item)
+    }
+
+    pub async fn recv_async(&self) -> Result<T, RecvError> {
+        let item = self.rx.recv_async().await?;
+        self.shared.freed();
+        Ok(item)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let item = self.rx.recv_timeout(timeout)?;
+        self.shared.freed();
+        Ok(item)
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let item = self.rx.try_recv()?;
+        self.shared.freed();
+        Ok(item)
+    }
+
+    // everything, that is queued right now
+    pub fn drain(&self) -> std::vec::IntoIter<T> {
+        let items: Vec<T> = self.rx.drain().collect();
+        self.shared.freed();
+        items.into_iter()
+    }
+}
+
+// ends, when every Queue for it is dropped
+impl<T> Iterator for QueueReceiver<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.recv().ok()
+    }
+}
+
+impl<T> Clone for QueueReceiver<T> {
+    fn clone(&self) -> QueueReceiver<T> {
+        QueueReceiver::new(self.rx.clone(), self.shared.clone())
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .receivers -= 1;
+        // a pusher, that waits for us, has to give up
+        self.shared.freed();
+    }
+}
+
+// an Update, that is still queued, is replaced by a newer one for the same id (they carry the
+// whole item)
+pub fn same_update(old: &MizeMessage, new: &MizeMessage) -> bool {
+    let is_update = |msg: &MizeMessage| matches!(msg.cmd(), Ok(MessageCmd::Update));
+    is_update(old)
+        && is_update(new)
+        && old.conn_id == new.conn_id
+        && old.ns() == new.ns()
+        && matches!((old.clone().id_str(), new.clone().id_str()), (Ok(a), Ok(b)) if a == b)
+}
+
+pub fn same_update_op(old: &Operation, new: &Operation) -> bool {
+    match (old, new) {
+        (Operation::Msg(old), Operation::Msg(new)) => same_update(old, new),
+        _ => false,
+    }
+}
+
+fn config_size(instance: &Mize, id: &str) -> MizeResult<usize> {
+    let data = match instance.get(id)?.as_data_full() {
+        Ok(data) => data,
+        Err(_) => return Ok(MSG_CHANNEL_SIZE),
+    };
+    match data.cbor() {
+        CborValue::Integer(size) => usize::try_from(*size)
+            .map_err(|_| mize_err!("config option {} is not a valid size", id)),
+        CborValue::Text(size) => Ok(size.parse()?),
+        CborValue::Null => Ok(MSG_CHANNEL_SIZE),
+        other => Err(mize_err!(
+            "config option {} is not a number: {:?}",
+            id,
+            other
+        )),
+    }
+}
+
+pub fn new_conn_queue(instance: &Mize) -> MizeResult<Queue<MizeMessage>> {
+    Ok(Queue::new(
+        config_size(instance, "self/config/queue/size")?,
+        FullPolicy::from_config(instance)?,
+    ))
+}
+
+// the operation queue exists before the config is loaded, so it's limits are set later
+pub fn apply_config(instance: &Mize) -> MizeResult<()> {
+    instance.ops.set_limits(
+        config_size(instance, "self/config/queue/ops")?,
+        FullPolicy::from_config(instance)?,
+    )
+}
+
+// the inst/queues item
+pub fn inst_item(instance: &Mize, path: Vec<String>) -> MizeResult<ItemData> {
+    let connections = instance
+        .connections
+        .lock()?
+        .iter()
+        .map(|conn| {
+            (
+                CborValue::Text(format!("{}", conn.id)),
+                CborValue::from(conn.queue.len() as u64),
+            )
+        })
+        .collect();
+
+    let data = ItemData::from_cbor(CborValue::Map(vec![
+        (
+            CborValue::Text("ops".to_owned()),
+            CborValue::from(instance.ops.len() as u64),
+        ),
+        (
+            CborValue::Text("connections".to_owned()),
+            CborValue::Map(connections),
+        ),
+    ]));
+
+    data.get_path(path)
+}
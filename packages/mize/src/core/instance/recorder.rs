@@ -0,0 +1,221 @@
+use ciborium::Value as CborValue;
+use std::fmt;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, warn};
+
+use crate::error::{IntoMizeResult, MizeError, MizeResult};
+use crate::mize_err;
+use crate::proto::MizeMessage;
+use crate::util::now_ms;
+
+use super::queue::{FullPolicy, Queue, QueueReceiver};
+use super::Mize;
+
+// the recorder writes every msg, that goes over a connection, to a log
+// the log is a sequence of cbor maps (one per msg) like:
+// { "dir": "in" | "out", "conn": <conn_id>, "time": <unix time in ms>, "msg": <the msg> }
+//
+// enabled with the config option record (the path of the log) or Mize::record_to()
+// `mize format-cbor` prints such a log and `mize replay <file>` sends it's incoming msgs to a
+// fresh instance again
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub dir: Direction,
+    pub conn_id: u64,
+    pub time: u64,
+    pub msg: MizeMessage,
+}
+
+// shared by the instance and all it's connections, so that recording can start at any time
+#[derive(Clone, Default)]
+pub struct Recorder {
+    writer: Arc<Mutex<Option<Box<dyn Write + Send>>>>,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let recording = self
+            .writer
+            .lock()
+            .map(|writer| writer.is_some())
+            .unwrap_or(false);
+        write!(f, "Recorder {{ recording: {} }}", recording)
+    }
+}
+
+impl Recorder {
+    pub fn start(&self, writer: Box<dyn Write + Send>) -> MizeResult<()> {
+        *self.writer.lock()? = Some(writer);
+        Ok(())
+    }
+
+    pub fn stop(&self) -> MizeResult<()> {
+        if let Some(mut writer) = self.writer.lock()?.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn record(&self, dir: Direction, conn_id: u64, msg: &MizeMessage) {
+        let mut writer_inner = match self.writer.lock() {
+            Ok(writer) => writer,
+            Err(_) => return,
+        };
+        let writer = match writer_inner.as_mut() {
+            Some(writer) => writer,
+            None => return,
+        };
+
+        let record = Record {
+            dir,
+            conn_id,
+            time: now_ms(),
+            msg: msg.clone(),
+        };
+
+        // a broken log should never break the connection
+        if let Err(err) = ciborium::into_writer(&record.to_cbor(), writer.as_mut()) {
+            warn!("could not record msg on connection {}: {}", conn_id, err);
+        }
+    }
+}
+
+impl Record {
+    pub fn to_cbor(&self) -> CborValue {
+        let dir = match self.dir {
+            Direction::In => "in",
+            Direction::Out => "out",
+        };
+        CborValue::Map(vec![
+            (
+                CborValue::Text("dir".to_owned()),
+                CborValue::Text(dir.to_owned()),
+            ),
+            (CborValue::Text("conn".to_owned()), self.conn_id.into()),
+            (CborValue::Text("time".to_owned()), self.time.into()),
+            (CborValue::Text("msg".to_owned()), self.msg.clone().value()),
+        ])
+    }
+
+    pub fn from_cbor(value: CborValue) -> MizeResult<Record> {
+        let map = match value {
+            CborValue::Map(map) => map,
+            other => return Err(mize_err!("record is not a map: {:?}", other)),
+        };
+        let field = |name: &str| {
+            map.iter()
+                .find(|(key, _)| key == &CborValue::Text(name.to_owned()))
+                .map(|(_, val)| val.to_owned())
+                .ok_or(mize_err!("record has no field '{}'", name))
+        };
+        let int_field = |name: &str| match field(name)? {
+            CborValue::Integer(int) => u64::try_from(int)
+                .mize_result_msg(format!("field '{}' of record is not a u64", name)),
+            other => Err(mize_err!(
+                "field '{}' of record is not a u64: {:?}",
+                name,
+                other
+            )),
+        };
+
+        let dir = match field("dir")? {
+            CborValue::Text(dir) if dir == "in" => Direction::In,
+            CborValue::Text(dir) if dir == "out" => Direction::Out,
+            other => return Err(mize_err!("record has an invalid dir: {:?}", other)),
+        };
+        let conn_id = int_field("conn")?;
+
+        Ok(Record {
+            dir,
+            conn_id,
+            time: int_field("time")?,
+            msg: MizeMessage::new(field("msg")?, conn_id),
+        })
+    }
+
+    // if value looks like a record
+    pub fn is_record(value: &CborValue) -> bool {
+        match value {
+            CborValue::Map(map) => ["dir", "conn", "time", "msg"].iter().all(|name| {
+                map.iter()
+                    .any(|(key, _)| key == &CborValue::Text((*name).to_owned()))
+            }),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dir = match self.dir {
+            Direction::In => "<- in ",
+            Direction::Out => "-> out",
+        };
+        writeln!(f, "[{}] {} connection {}", self.time, dir, self.conn_id)?;
+        write!(f, "{}", self.msg)
+    }
+}
+
+// all cbor values in reader, until it ends
+pub fn read_values(mut reader: impl Read) -> MizeResult<Vec<CborValue>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let mut values = Vec::new();
+    let mut rest = bytes.as_slice();
+    while !rest.is_empty() {
+        values.push(ciborium::from_reader(&mut rest)?);
+    }
+    Ok(values)
+}
+
+pub fn read_records(reader: impl Read) -> MizeResult<Vec<Record>> {
+    read_values(reader)?
+        .into_iter()
+        .map(Record::from_cbor)
+        .collect()
+}
+
+// sends the incoming msgs of records to instance, as if they came in over new connections
+// (one for every connection in the log)
+// what the instance sends back can be read from the returned receiver
+pub fn replay(instance: &Mize, records: Vec<Record>) -> MizeResult<QueueReceiver<MizeMessage>> {
+    // the replies are collected by the caller, so they never have to wait
+    let tx = Queue::new(usize::MAX, FullPolicy::Block);
+    let rx = tx.receiver();
+    let mut conn_ids: Vec<(u64, u64)> = Vec::new();
+
+    for record in records {
+        if record.dir != Direction::In {
+            continue;
+        }
+
+        let conn_id = match conn_ids
+            .iter()
+            .find(|(recorded, _)| *recorded == record.conn_id)
+        {
+            Some((_, conn_id)) => *conn_id,
+            None => {
+                let conn_id = instance.new_connection(tx.clone())?;
+                debug!(
+                    "replaying connection {} as connection {}",
+                    record.conn_id, conn_id
+                );
+                conn_ids.push((record.conn_id, conn_id));
+                conn_id
+            }
+        };
+
+        instance.got_msg(MizeMessage::new(record.msg.value(), conn_id))?;
+    }
+
+    Ok(rx)
+}
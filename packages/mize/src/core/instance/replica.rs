@@ -0,0 +1,183 @@
+use ciborium::Value as CborValue;
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+use crate::config::config_flag;
+use crate::error::{MizeError, MizeResult};
+use crate::id::{MizeId, Namespace};
+use crate::item::ItemData;
+use crate::mize_err;
+use crate::proto::MizeMessage;
+use crate::util::now_ms;
+
+use super::Mize;
+
+// a replica mirrors all items of the namespace it joined into it's own store
+// it GetSubs every item of the owner (and Subs inst/new_item, to learn about new ones), then
+// keeps the mirror up to date with the Update msgs the owner sends
+// reads of mirrored items are served from the store, writes still go to the owner
+//
+// enabled with the config option replica, it's state is readable as inst/replica
+
+#[derive(Debug, Default)]
+pub struct ReplicaState {
+    owner: Option<Namespace>,
+    // the store_parts we have a copy of
+    items: HashSet<String>,
+    // unix time in ms
+    last_sync: Option<u64>,
+    // how long the last Update took from the owner to us
+    lag_ms: Option<u64>,
+}
+
+pub fn replica_enabled(instance: &Mize) -> MizeResult<bool> {
+    config_flag(instance, "self/config/replica")
+}
+
+// start mirroring the namespace of the peer on conn_id
+pub fn start(instance: &Mize, conn_id: u64) -> MizeResult<()> {
+    let owner = instance.get_connection(conn_id)?.ns.ok_or(mize_err!(
+        "can't replicate connection {}, it has no namespace",
+        conn_id
+    ))?;
+
+    info!("replicating namespace '{}'", owner.as_string());
+
+    // after a reconnect everything is fetched again, as we missed the updates in between
+    let mut replica_inner = instance.replica.lock()?;
+    replica_inner.owner = Some(owner.clone());
+    replica_inner.items.clear();
+    drop(replica_inner);
+
+    // waiting for replies can't happen on an updater thread
+    let instance_clone = instance.clone();
+    instance.clone().spawn_background("replica sync", move || {
+        let result = initial_sync(&instance_clone, &owner);
+        if let Err(err) = &result {
+            warn!("initial sync of replica failed: {:?}", err.messages);
+        }
+        result
+    })
+}
+
+fn initial_sync(instance: &Mize, owner: &Namespace) -> MizeResult<()> {
+    // sub to new items first, so that no new item is missed
+    let new_item_id = instance.new_id(format!("{}:inst/new_item", owner.as_string()))?;
+    let mut connection = instance.get_connection_by_ns(owner.clone())?;
+    connection.send(MizeMessage::new_sub(new_item_id, connection.id).with_ns(owner))?;
+
+    let ids_id = instance.new_id(format!("{}:inst/ids", owner.as_string()))?;
+    let ids = get_sub(instance, &ids_id)?;
+    sync_ids(instance, owner, &ids)?;
+
+    instance.replica.lock()?.last_sync = Some(now_ms());
+    info!("initial sync of '{}' done", owner.as_string());
+    Ok(())
+}
+
+// GetSub every id in ids, that we don't have yet
+fn sync_ids(instance: &Mize, owner: &Namespace, ids: &ItemData) -> MizeResult<()> {
+    let ids = match ids.cbor() {
+        CborValue::Array(ids) => ids.to_owned(),
+        other => {
+            return Err(mize_err!(
+                "inst/ids of the owner is not a list: {:?}",
+                other
+            ))
+        }
+    };
+
+    for id_val in ids {
+        let store_part = match id_val {
+            CborValue::Text(store_part) => store_part,
+            _ => continue,
+        };
+        // the instance item stays our own
+        if store_part == "0" || instance.replica.lock()?.items.contains(&store_part) {
+            continue;
+        }
+
+        let id = instance.new_id(format!("{}:{}", owner.as_string(), store_part))?;
+        let data = get_sub(instance, &id)?;
+        instance.store.lock()?.set(id, data)?;
+        instance.replica.lock()?.items.insert(store_part);
+    }
+
+    Ok(())
+}
+
+fn get_sub(instance: &Mize, id: &MizeId) -> MizeResult<ItemData> {
+    let connection = instance.get_connection_by_ns(id.namespace())?;
+    let msg = MizeMessage::new_get_sub(id.clone(), connection.id).with_ns(&id.namespace());
+    instance.give_msg_wait(id.clone(), connection.id, move || connection.send(msg))
+}
+
+// if we have a copy of id, that reads can be served from
+pub fn serves(instance: &Mize, id: &MizeId) -> MizeResult<bool> {
+    let replica_inner = instance.replica.lock()?;
+    Ok(replica_inner.owner.as_ref() == Some(&id.namespace())
+        && replica_inner.items.contains(id.store_part()))
+}
+
+// an Update from the owner
+pub fn saw_update(instance: &Mize, msg: &MizeMessage) -> MizeResult<()> {
+    let mut replica_inner = instance.replica.lock()?;
+    if replica_inner.owner.is_none() {
+        return Ok(());
+    }
+
+    let now = now_ms();
+    replica_inner.last_sync = Some(now);
+    if let Some(sent) = msg.time() {
+        replica_inner.lag_ms = Some(now.saturating_sub(sent));
+    }
+    Ok(())
+}
+
+// keep our copy up to date with what the owner sent (or what we sent to the owner)
+pub fn mirror(instance: &Mize, id: &MizeId, data: &ItemData) -> MizeResult<()> {
+    if serves(instance, id)? {
+        return instance.store.lock()?.set(id.clone(), data.to_owned());
+    }
+
+    // the owner has a new item
+    let owner = instance.replica.lock()?.owner.clone();
+    if let Some(owner) = owner {
+        let is_inst = id.namespace() == owner && id.store_part() == "inst";
+        // inst/ids changes, when the owner mounts a store (see mount.rs)
+        let ids = match id.nth_part(1) {
+            Ok("new_item") if is_inst => Some(ItemData::from_cbor(CborValue::Array(vec![data
+                .cbor()
+                .to_owned()]))),
+            Ok("ids") if is_inst => Some(data.to_owned()),
+            _ => None,
+        };
+        if let Some(ids) = ids {
+            let instance_clone = instance.clone();
+            instance.clone().spawn_background("replica sync", move || {
+                sync_ids(&instance_clone, &owner, &ids)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+// the inst/replica item
+pub fn inst_item(instance: &Mize, path: Vec<String>) -> MizeResult<ItemData> {
+    let replica_inner = instance.replica.lock()?;
+
+    let mut data = ItemData::new();
+    data.set_path("enabled", CborValue::Bool(replica_inner.owner.is_some()))?;
+    if let Some(owner) = &replica_inner.owner {
+        data.set_path("owner", owner.as_real_string())?;
+    }
+    data.set_path("items", CborValue::from(replica_inner.items.len() as u64))?;
+    if let Some(last_sync) = replica_inner.last_sync {
+        data.set_path("last_sync", CborValue::from(last_sync))?;
+    }
+    if let Some(lag_ms) = replica_inner.lag_ms {
+        data.set_path("lag_ms", CborValue::from(lag_ms))?;
+    }
+
+    data.get_path(path)
+}
@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use tracing::{debug, trace, warn};
+
+use crate::config::config_flag;
+use crate::error::MizeResult;
+use crate::id::Namespace;
+use crate::proto::{MessageCmd, MizeMessage};
+
+use super::connection::Connection;
+use super::Mize;
+
+// how many instances a msg may pass, also the max number of hops a route can have
+pub static DEFAULT_TTL: u64 = 16;
+
+// how to reach a namespace, that we are not directly connected to
+#[derive(Debug, Clone)]
+pub struct Route {
+    // the connection to the next hop
+    pub conn_id: u64,
+    pub hops: u64,
+}
+
+// the id path of the item a reply is for, Create and CreateReply have no id so they use an empty
+// path
+type ReplyKey = (u64, Vec<String>);
+
+#[derive(Debug, Default)]
+pub struct RoutingTable {
+    pub(crate) routes: HashMap<Namespace, Route>,
+
+    // the connections we forwarded a request for, keyed by the connection we forwarded it to
+    // a reply is forwarded once, updates for subs every time
+    replies: HashMap<ReplyKey, Vec<u64>>,
+    subs: HashMap<ReplyKey, Vec<u64>>,
+
+    // the seq of our last routes msg and of the last one handled per connection
+    seq: u64,
+    peer_seqs: HashMap<u64, u64>,
+}
+
+impl RoutingTable {
+    // forget everything, that goes over conn_id
+    pub(crate) fn remove_connection(&mut self, conn_id: u64) {
+        self.routes.retain(|_, route| route.conn_id != conn_id);
+        self.peer_seqs.remove(&conn_id);
+        for map in [&mut self.replies, &mut self.subs] {
+            map.retain(|(to, _), _| *to != conn_id);
+            for from in map.values_mut() {
+                from.retain(|id| *id != conn_id);
+            }
+        }
+    }
+}
+
+pub fn routing_enabled(instance: &Mize) -> MizeResult<bool> {
+    config_flag(instance, "self/config/routing")
+}
+
+// forwards msg to the next hop, if it is not for us
+// returns true if the msg was forwarded (or dropped) and should not be handled by us
+pub fn route_msg(msg: &mut MizeMessage, instance: &Mize) -> MizeResult<bool> {
+    match msg.cmd()? {
+        MessageCmd::Get
+        | MessageCmd::GetSub
+        | MessageCmd::Sub
+        | MessageCmd::Create
+        | MessageCmd::UpdateRequest => forward_request(msg, instance),
+
+        MessageCmd::Give | MessageCmd::CreateReply | MessageCmd::Denied => {
+            let key = (msg.conn_id, reply_path(msg)?);
+            let mut routing_inner = instance.routing.lock()?;
+            let from = match routing_inner.replies.remove(&key) {
+                Some(from) => from,
+                None => return Ok(false),
+            };
+            drop(routing_inner);
+
+            forward_to(msg, &from, instance)?;
+            Ok(true)
+        }
+
+        MessageCmd::Update => {
+            let key = (msg.conn_id, msg.id_str()?);
+            let routing_inner = instance.routing.lock()?;
+            let from = match routing_inner.subs.get(&key) {
+                Some(from) => from.clone(),
+                None => return Ok(false),
+            };
+            drop(routing_inner);
+
+            forward_to(msg, &from, instance)?;
+
+            // we might also be subscribed to that item ourselves
+            let id = msg.id_with_ns(instance)?;
+            let subs_inner = instance.subs.lock()?;
+            Ok(!subs_inner.contains_key(&id))
+        }
+
+        MessageCmd::Routes | MessageCmd::Auth => Ok(false),
+    }
+}
+
+fn forward_request(msg: &mut MizeMessage, instance: &Mize) -> MizeResult<bool> {
+    let ns = match msg.ns() {
+        Some(ns) => instance.namespace_from_string(ns)?,
+        None => return Ok(false),
+    };
+
+    if instance.is_own_namespace(&ns)? {
+        return Ok(false);
+    }
+
+    let next_hop = match instance.get_connection_by_ns(ns.clone()) {
+        Ok(conn) => conn,
+        Err(_) => {
+            // nowhere to send it, so try to answer it ourselves
+            debug!("no route to namespace '{}', handling msg", ns.as_string());
+            return Ok(false);
+        }
+    };
+
+    let ttl = msg.ttl().unwrap_or(DEFAULT_TTL);
+    let mut via = msg.via();
+    let route_name = instance.route_name()?;
+
+    if next_hop.id == msg.conn_id || ttl <= 1 || via.contains(&route_name) {
+        warn!(
+            "dropping msg for namespace '{}' (ttl: {}, via: {:?}), it is going in circles",
+            ns.as_string(),
+            ttl,
+            via
+        );
+        return Ok(true);
+    }
+
+    // remember where replies have to go
+    let mut routing_inner = instance.routing.lock()?;
+    let path = reply_path(msg)?;
+    match msg.cmd()? {
+        MessageCmd::Get | MessageCmd::Create => {
+            push_to(&mut routing_inner.replies, (next_hop.id, path), msg.conn_id);
+        }
+        MessageCmd::GetSub => {
+            push_to(
+                &mut routing_inner.replies,
+                (next_hop.id, path.clone()),
+                msg.conn_id,
+            );
+            push_to(&mut routing_inner.subs, (next_hop.id, path), msg.conn_id);
+        }
+        MessageCmd::Sub => {
+            push_to(&mut routing_inner.subs, (next_hop.id, path), msg.conn_id);
+        }
+        MessageCmd::UpdateRequest if msg.ack() => {
+            push_to(&mut routing_inner.replies, (next_hop.id, path), msg.conn_id);
+        }
+        _ => {}
+    }
+    drop(routing_inner);
+
+    via.push(route_name);
+    trace!(
+        "forwarding msg for '{}' to connection {}",
+        ns.as_string(),
+        next_hop.id
+    );
+
+    let forwarded = MizeMessage::new(msg.clone().value(), next_hop.id)
+        .with_ttl(ttl - 1)
+        .with_via(via);
+    next_hop.send(forwarded)?;
+
+    Ok(true)
+}
+
+fn forward_to(msg: &MizeMessage, conn_ids: &Vec<u64>, instance: &Mize) -> MizeResult<()> {
+    for conn_id in conn_ids {
+        let connection = instance.get_connection(*conn_id)?;
+        connection.send(MizeMessage::new(msg.clone().value(), *conn_id))?;
+    }
+    Ok(())
+}
+
+fn push_to(map: &mut HashMap<ReplyKey, Vec<u64>>, key: ReplyKey, conn_id: u64) {
+    let vec = map.entry(key).or_insert_with(Vec::new);
+    if !vec.contains(&conn_id) {
+        vec.push(conn_id);
+    }
+}
+
+fn reply_path(msg: &mut MizeMessage) -> MizeResult<Vec<String>> {
+    match msg.cmd()? {
+        MessageCmd::Create | MessageCmd::CreateReply => Ok(Vec::new()),
+        // a denied Create has no id
+        MessageCmd::Denied => Ok(msg.id_str().unwrap_or_default()),
+        _ => msg.id_str(),
+    }
+}
+
+// a reply is in the namespace the request was addressed to
+pub fn with_request_ns(
+    reply: MizeMessage,
+    request: &MizeMessage,
+    instance: &Mize,
+) -> MizeResult<MizeMessage> {
+    match request.ns() {
+        Some(ns) => Ok(reply.with_ns(&instance.namespace_from_string(ns)?)),
+        None => Ok(reply),
+    }
+}
+
+// updates carry the namespace of the owner, so that they can be routed back to the right item
+pub fn with_public_ns(msg: MizeMessage, instance: &Mize) -> MizeResult<MizeMessage> {
+    match instance.public_namespace()? {
+        Some(ns) => Ok(msg.with_ns(&ns)),
+        None => Ok(msg),
+    }
+}
+
+////// route advertisement
+
+// a peer tells us all namespaces it can reach, which replaces everything we knew to be reachable
+// over it
+pub fn handle_routes_msg(msg: &mut MizeMessage, instance: &Mize) -> MizeResult<()> {
+    let from = msg.conn_id;
+    let mut changed = false;
+    let mut advertised: Vec<(Namespace, u64)> = Vec::new();
+
+    for (ns_str, hops) in msg.routes()? {
+        let ns = instance.namespace_from_string(ns_str)?;
+        if instance.is_own_namespace(&ns)? {
+            continue;
+        }
+
+        if hops == 0 {
+            // the peer itself
+            let mut connection = instance.get_connection(from)?;
+            if connection.ns.is_none() {
+                instance.connection_set_namespace(from, ns)?;
+            }
+            continue;
+        }
+
+        advertised.push((ns, hops + 1));
+    }
+
+    let direct: Vec<Namespace> = instance
+        .connections
+        .lock()?
+        .iter()
+        .filter_map(|conn| conn.ns.clone())
+        .collect();
+
+    let mut routing_inner = instance.routing.lock()?;
+
+    // the updater threads can handle two routes msgs of a peer out of order
+    if let Some(seq) = msg.seq() {
+        if routing_inner
+            .peer_seqs
+            .get(&from)
+            .is_some_and(|last| *last >= seq)
+        {
+            trace!(
+                "ignoring outdated routes msg {} from connection {}",
+                seq,
+                from
+            );
+            return Ok(());
+        }
+        routing_inner.peer_seqs.insert(from, seq);
+    }
+
+    let routes_before = routing_inner.routes.len();
+    routing_inner
+        .routes
+        .retain(|ns, route| route.conn_id != from || advertised.iter().any(|(a, _)| a == ns));
+    changed |= routes_before != routing_inner.routes.len();
+
+    for (ns, hops) in advertised {
+        if direct.contains(&ns) || hops >= DEFAULT_TTL {
+            continue;
+        }
+
+        let better = match routing_inner.routes.get(&ns) {
+            None => true,
+            Some(route) if route.conn_id == from => route.hops != hops,
+            Some(route) => route.hops > hops,
+        };
+
+        if better {
+            debug!(
+                "route to '{}' over connection {} with {} hops",
+                ns.as_string(),
+                from,
+                hops
+            );
+            routing_inner.routes.insert(
+                ns,
+                Route {
+                    conn_id: from,
+                    hops,
+                },
+            );
+            changed = true;
+        }
+    }
+    drop(routing_inner);
+
+    if changed {
+        advertise_routes(instance)?;
+        super::sync::replay_in_background(instance)?;
+    }
+
+    Ok(())
+}
+
+pub fn advertise_routes(instance: &Mize) -> MizeResult<()> {
+    if !routing_enabled(instance)? {
+        return Ok(());
+    }
+
+    let connections = instance.connections.lock()?.clone();
+    for connection in connections.iter() {
+        advertise_routes_to(instance, connection)?;
+    }
+    Ok(())
+}
+
+pub fn advertise_routes_to(instance: &Mize, target: &Connection) -> MizeResult<()> {
+    let mut reachable: HashMap<String, u64> = HashMap::new();
+
+    if let Some(ns) = instance.public_namespace()? {
+        reachable.insert(ns.as_real_string(), 0);
+    }
+
+    // the table is read under the same lock, that hands out the seq, so that a higher seq never
+    // carries an older table
+    let mut routing_inner = instance.routing.lock()?;
+
+    // never tell a peer about what we reach over it
+    for connection in instance.connections.lock()?.iter() {
+        if let (Some(ns), true) = (&connection.ns, connection.id != target.id) {
+            reachable.entry(ns.as_real_string()).or_insert(1);
+        }
+    }
+
+    for (ns, route) in routing_inner.routes.iter() {
+        if route.conn_id != target.id {
+            let hops = reachable.entry(ns.as_real_string()).or_insert(route.hops);
+            *hops = (*hops).min(route.hops);
+        }
+    }
+
+    routing_inner.seq += 1;
+    let msg = MizeMessage::new_routes(reachable.into_iter().collect(), target.id)
+        .with_seq(routing_inner.seq);
+    // not while we hold the lock, sending can wait for space in the queue of the peer (see
+    // FullPolicy::Block), while route_msg() waits for the lock on every updater thread
+    // the peer drops an older advert, that arrives after a newer one, by it's seq
+    drop(routing_inner);
+    target.send(msg)
+}
@@ -0,0 +1,361 @@
+use ciborium::Value as CborValue;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::config_flag;
+use crate::error::{MizeError, MizeResult};
+use crate::id::MizeId;
+use crate::item::ItemData;
+use crate::mize_err;
+use crate::util::now_ms;
+
+use super::computed::is_cycle;
+use super::subscription::Subscription;
+use super::worker::{self, Worker};
+use super::Mize;
+
+// automation: when item X changes and matches a condition, run an action
+//
+// a rule is kept in 0/rules/<name> (readable as self/rules/<name>):
+// - when: the id of the item to watch
+// - if: a map of paths in that item to the value they must have (optional)
+// - one action:
+//   - set = "<id>" and value = <data>: merge value into the item
+//   - call = "<name>": an action added with Mize::add_rule_action() (eg: a method of a part)
+//   - run = ["<program>", "<arg>", ...]: run a command (only on os and only with the config
+//     option rules.run set to true, it is off by default)
+//
+// a rule can only be added with add_rule(), which activates it right away
+// a set() of self/rules is rejected (see add_validators()) and no connection may write it (see
+// auth::local_only()), so 0/rules only holds what add_rule() wrote, which load() activates again
+// at the next start
+//
+// the rule subs to it's item with a closure, that only hands it to the "rules" thread, because a
+// closure runs while the subs of the instance are locked
+// the last RUNS_KEPT runs of a rule are kept in 0/rule_runs/<name> (self/rule_runs/<name>), each
+// with the time, the changed id and an error, if it failed
+
+static RUNS_KEPT: usize = 100;
+
+pub type RuleAction = Arc<dyn Fn(&Mize, &MizeId) -> MizeResult<()> + Send + Sync>;
+
+#[derive(Clone, Debug)]
+enum Action {
+    Set(MizeId, ItemData),
+    Call(String),
+    Run(Vec<String>),
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    when: MizeId,
+    conditions: Vec<(Vec<String>, CborValue)>,
+    action: Action,
+    // a rule, that was replaced, still has it's subscription, which is then ignored
+    generation: u64,
+}
+
+#[derive(Default)]
+pub struct Rules {
+    rules: HashMap<String, Rule>,
+    actions: HashMap<String, RuleAction>,
+    next_generation: u64,
+    // (name, generation, the changed id) for the "rules" thread
+    pub(crate) triggered: Worker<(String, u64, MizeId)>,
+}
+
+impl Mize {
+    // add or replace the rule name, it is also written to 0/rules/<name>
+    pub fn add_rule(&self, name: &str, rule: ItemData) -> MizeResult<()> {
+        activate(self, name, &rule)?;
+        update_instance_item(self, vec!["rules".to_owned(), name.to_owned()], |_| {
+            rule.cbor().clone()
+        })
+    }
+
+    pub fn remove_rule(&self, name: &str) -> MizeResult<()> {
+        self.rules.lock()?.rules.remove(name);
+        update_instance_item(self, vec!["rules".to_owned()], |rules| match rules {
+            CborValue::Map(map) => CborValue::Map(
+                map.into_iter()
+                    .filter(|(key, _)| key != &CborValue::Text(name.to_owned()))
+                    .collect(),
+            ),
+            other => other,
+        })
+    }
+
+    // what the call action of a rule can call
+    pub fn add_rule_action(
+        &self,
+        name: &str,
+        action: impl Fn(&Mize, &MizeId) -> MizeResult<()> + Send + Sync + 'static,
+    ) -> MizeResult<()> {
+        self.rules
+            .lock()?
+            .actions
+            .insert(name.to_owned(), Arc::new(action));
+        Ok(())
+    }
+
+    // also used by others, that run actions (eg: the scheduler part)
+    pub fn call_rule_action(&self, name: &str, id: &MizeId) -> MizeResult<()> {
+        let action = self
+            .rules
+            .lock()?
+            .actions
+            .get(name)
+            .cloned()
+            .ok_or(mize_err!("there is no rule action '{}'", name))?;
+        action(self, id)
+    }
+}
+
+// called once, when the instance is created
+pub(crate) fn add_validators(instance: &Mize) -> MizeResult<()> {
+    for prefix in ["0/rules", "self/rules"] {
+        instance.add_validator(prefix, |_, id, _| {
+            Err(mize_err!(
+                "'{}' can't be set, a rule is added with Mize::add_rule()",
+                id
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+// activate the rules in 0/rules, when the store is opened
+pub fn load(instance: &Mize) -> MizeResult<()> {
+    let rules = match instance.get("0/rules")?.as_data_full() {
+        Ok(ItemData(CborValue::Map(rules))) => rules,
+        _ => return Ok(()),
+    };
+    for (name, rule) in rules {
+        let name = ItemData::from_cbor(name).value_string()?;
+        activate(instance, name.as_str(), &ItemData::from_cbor(rule))
+            .map_err(|err| err.msg(format!("could not load the rule '{}'", name)))?;
+    }
+    Ok(())
+}
+
+fn activate(instance: &Mize, name: &str, data: &ItemData) -> MizeResult<()> {
+    let mut rule = parse(instance, name, data)?;
+
+    let mut rules_inner = instance.rules.lock()?;
+    // A sets B and B sets A would trigger each other forever
+    if let Action::Set(target, _) = &rule.action {
+        let cycle = is_cycle(target, &[rule.when.clone()], |id| {
+            rules_inner
+                .rules
+                .iter()
+                .filter(|(other, _)| other.as_str() != name)
+                .filter_map(|(_, other)| match &other.action {
+                    Action::Set(other_target, _) if other_target == id => Some(other.when.clone()),
+                    _ => None,
+                })
+                .collect()
+        });
+        if cycle {
+            return Err(mize_err!(
+                "the rule '{}' would trigger itself over the items it sets",
+                name
+            ));
+        }
+    }
+    rule.generation = rules_inner.next_generation;
+    rules_inner.next_generation += 1;
+    let generation = rule.generation;
+    let when = rule.when.clone();
+    rules_inner.rules.insert(name.to_owned(), rule);
+
+    let triggered = rules_inner
+        .triggered
+        .sender(instance, "rules", handle_triggered)?;
+    drop(rules_inner);
+
+    let name = name.to_owned();
+    instance.sub(
+        when,
+        Subscription::from_closure(Box::new(move |update| {
+            worker::send(&triggered, (name.clone(), generation, update.id))
+        })),
+    )
+}
+
+fn parse(instance: &Mize, name: &str, data: &ItemData) -> MizeResult<Rule> {
+    let when = data
+        .get_path("when")?
+        .value_string()
+        .map_err(|err| err.msg(format!("the rule '{}' has no when", name)))?;
+    let when = instance.new_id(when.as_str())?;
+
+    let conditions = match data.get_path("if")?.cbor() {
+        CborValue::Map(map) => map
+            .iter()
+            .map(|(path, value)| {
+                let path = ItemData::from_cbor(path.clone()).value_string()?;
+                let path = path.split('/').map(|part| part.to_owned()).collect();
+                Ok((path, value.clone()))
+            })
+            .collect::<MizeResult<Vec<(Vec<String>, CborValue)>>>()?,
+        CborValue::Null => Vec::new(),
+        other => {
+            return Err(mize_err!(
+                "the if of the rule '{}' is not a map of paths to values: {:?}",
+                name,
+                other
+            ))
+        }
+    };
+
+    let action = match (
+        data.get_path("set")?.cbor(),
+        data.get_path("call")?.cbor(),
+        data.get_path("run")?.cbor(),
+    ) {
+        (CborValue::Text(id), CborValue::Null, CborValue::Null) => {
+            Action::Set(instance.new_id(id.as_str())?, data.get_path("value")?)
+        }
+        (CborValue::Null, CborValue::Text(call), CborValue::Null) => Action::Call(call.clone()),
+        (CborValue::Null, CborValue::Null, CborValue::Array(args)) if !args.is_empty() => {
+            let args = args
+                .iter()
+                .map(|arg| ItemData::from_cbor(arg.clone()).value_string())
+                .collect::<MizeResult<Vec<String>>>()?;
+            Action::Run(args)
+        }
+        _ => {
+            return Err(mize_err!(
+                "the rule '{}' needs exactly one action: set, call or run",
+                name
+            ))
+        }
+    };
+
+    Ok(Rule {
+        when,
+        conditions,
+        action,
+        generation: 0,
+    })
+}
+
+// on the "rules" thread
+fn handle_triggered(instance: &Mize, triggered: (String, u64, MizeId)) -> MizeResult<()> {
+    let (name, generation, id) = triggered;
+    let rule = match instance.rules.lock()?.rules.get(&name) {
+        Some(rule) if rule.generation == generation => rule.clone(),
+        _ => return Ok(()),
+    };
+
+    let result = match matches(instance, &rule) {
+        Ok(true) => run(instance, &rule, &id),
+        Ok(false) => return Ok(()),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = record_run(instance, &name, &id, &result) {
+        instance.report_err(err);
+    }
+    result.map_err(|err| err.msg(format!("the rule '{}' failed", name)))
+}
+
+fn matches(instance: &Mize, rule: &Rule) -> MizeResult<bool> {
+    if rule.conditions.is_empty() {
+        return Ok(true);
+    }
+    let data = instance.get(rule.when.clone())?.as_data_full()?;
+    for (path, expected) in rule.conditions.iter() {
+        if data.get_path(path.clone())?.cbor() != expected {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn run(instance: &Mize, rule: &Rule, id: &MizeId) -> MizeResult<()> {
+    match &rule.action {
+        Action::Set(target, value) => instance.set(target.clone(), value.clone()),
+        Action::Call(name) => instance.call_rule_action(name, id),
+        Action::Run(args) => {
+            if !config_flag(instance, "self/config/rules/run")? {
+                return Err(mize_err!(
+                    "not running '{}', running commands from rules is off (config option rules.run)",
+                    args.join(" ")
+                ));
+            }
+            run_command(args)
+        }
+    }
+}
+
+#[cfg(feature = "target-os")]
+fn run_command(args: &[String]) -> MizeResult<()> {
+    let status = std::process::Command::new(&args[0])
+        .args(&args[1..])
+        .status()
+        .map_err(|err| mize_err!("could not run '{}': {}", args[0], err))?;
+    if !status.success() {
+        return Err(mize_err!("'{}' exited with {}", args.join(" "), status));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "target-os"))]
+fn run_command(args: &[String]) -> MizeResult<()> {
+    Err(mize_err!("running a command is only possible on os"))
+}
+
+fn record_run(instance: &Mize, name: &str, id: &MizeId, result: &MizeResult<()>) -> MizeResult<()> {
+    let mut run = vec![
+        (
+            CborValue::Text("time".to_owned()),
+            CborValue::from(now_ms()),
+        ),
+        (
+            CborValue::Text("id".to_owned()),
+            CborValue::Text(format!("{}", id)),
+        ),
+    ];
+    if let Err(err) = result {
+        run.push((
+            CborValue::Text("error".to_owned()),
+            CborValue::Text(err.messages.join("\n")),
+        ));
+    }
+
+    update_instance_item(
+        instance,
+        vec!["rule_runs".to_owned(), name.to_owned()],
+        |runs| {
+            let mut runs = match runs {
+                CborValue::Array(runs) => runs,
+                _ => Vec::new(),
+            };
+            runs.push(CborValue::Map(run));
+            if runs.len() > RUNS_KEPT {
+                runs.drain(..runs.len() - RUNS_KEPT);
+            }
+            CborValue::Array(runs)
+        },
+    )?;
+
+    instance.changed(format!("self/rule_runs/{}", name))
+}
+
+// the whole instance item is read and written, while holding the store lock (like in sync.rs)
+fn update_instance_item(
+    instance: &Mize,
+    path: Vec<String>,
+    func: impl FnOnce(CborValue) -> CborValue,
+) -> MizeResult<()> {
+    let store_inner = instance.store.lock()?;
+    let id = instance.new_id("0")?;
+    let mut data = store_inner.get_value_data_full(id.clone())?;
+
+    let old = match data.get_path(path.clone()) {
+        Ok(old) => old.cbor().clone(),
+        Err(_) => CborValue::Null,
+    };
+    data.set_path(path, func(old))?;
+    store_inner.set(id, data)
+}
@@ -3,12 +3,13 @@ use std::fmt::Debug;
 use std::iter::Map;
 use std::option::Iter;
 
-use crate::error::MizeResult;
+use crate::error::{MizeError, MizeResult};
 use crate::id::MizeId;
 use crate::instance::Mize;
 use crate::item::get_raw_from_cbor;
 use crate::item::{Item, ItemData};
 use crate::memstore::MemStore;
+use crate::mize_err;
 
 dyn_clone::clone_trait_object!(Store);
 
@@ -19,8 +20,13 @@ pub trait Store: DynClone + Send + Sync + Debug {
     // is implemented, so that there can be a multithreaded implementation
     //fn get(self, id: MizeId) -> MizeResult<Item<Self>> where Self: Sized;
 
+    // every set increments the version of the item (of it's store_part)
     fn set(&self, id: MizeId, data: ItemData) -> MizeResult<()>;
 
+    // 0 for an item, that was never set
+    // used to find out, if an item changed, while we were offline
+    fn get_version(&self, id: MizeId) -> MizeResult<u64>;
+
     // in the future should implement transactions, ....
 
     // funcs to do with links, backlinks
@@ -30,6 +36,13 @@ pub trait Store: DynClone + Send + Sync + Debug {
 
     fn new_id(&self) -> MizeResult<String>;
 
+    // a new id with a store_part in from..to, None if there is none left
+    // a fresh range starts after the highest store_part, that is already used in it
+    // needed by a store, that is mounted (see mount.rs)
+    fn new_id_in(&self, from: u64, to: u64) -> MizeResult<Option<String>> {
+        Err(mize_err!("this store can't allocate ids in a range"))
+    }
+
     fn get_value_raw(&self, id: MizeId) -> MizeResult<Vec<u8>>;
 
     fn get_value_data_full(&self, id: MizeId) -> MizeResult<ItemData>;
@@ -39,6 +52,11 @@ pub trait Store: DynClone + Send + Sync + Debug {
     fn next_id(&self, prev_id: &str) -> MizeResult<Option<String>>;
 
     fn first_id(&self) -> MizeResult<String>;
+
+    // write out everything, that is not yet on disk, called when the instance shuts down
+    fn flush(&self) -> MizeResult<()> {
+        Ok(())
+    }
 }
 
 pub struct IdIter {
@@ -7,8 +7,11 @@ use tracing::trace;
 use crate::error::MizeResult;
 use crate::id::MizeId;
 use crate::instance::connection::Connection;
-use crate::item::Item;
+use crate::instance::routing::with_public_ns;
+use crate::instance::updater::with_version;
+use crate::item::{Item, ItemData};
 use crate::proto::MizeMessage;
+use crate::util::now_ms;
 
 use super::Mize;
 
@@ -16,6 +19,8 @@ use super::Mize;
 pub struct Update {
     pub instance: Arc<Mize>,
     pub id: MizeId,
+    // sent to connections instead of the whole item (eg: only the new id for inst/new_item)
+    pub data: Option<ItemData>,
 }
 
 impl Update {
@@ -41,15 +46,25 @@ impl Subscription {
         Subscription::Channel(tx)
     }
 
+    // a channel subscription, where the receiver was dropped, will never be handled again
+    pub fn is_closed(&self) -> bool {
+        match self {
+            Subscription::Channel(tx) => tx.is_disconnected(),
+            _ => false,
+        }
+    }
+
     pub fn handle(&mut self, update: Update) -> MizeResult<()> {
         trace!("handleing update");
         match &self {
             Subscription::Connection(conn) => {
-                let msg = MizeMessage::new_update(
-                    update.id.clone(),
-                    update.new_item()?.as_data_full()?,
-                    conn.id,
-                );
+                let data = match &update.data {
+                    Some(data) => data.clone(),
+                    None => update.new_item()?.as_data_full()?,
+                };
+                let msg = MizeMessage::new_update(update.id.clone(), data, conn.id);
+                let msg = with_version(msg, &update.id, &update.instance)?.with_time(now_ms());
+                let msg = with_public_ns(msg, &update.instance)?;
                 conn.send(msg)?;
             }
             Subscription::Closure(closure) => closure(update)?,
@@ -0,0 +1,445 @@
+use ciborium::Value as CborValue;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use tracing::{debug, info, warn};
+
+use crate::error::{MizeError, MizeResult};
+use crate::id::MizeId;
+use crate::item::{item_data_merge, ItemData};
+use crate::mize_err;
+use crate::proto::MizeMessage;
+use crate::types::crdt;
+
+use super::store::Store;
+use super::{provider, Mize};
+
+// writes to items of other namespaces, that we can't reach right now, wait in the outbox
+// (self/sync/outbox) and once the namespace is reachable again, they are replayed
+// if the item changed in the meantime (it's version is not the one we last saw), the write
+// conflicts and is resolved with the policy from the config option sync.conflict
+// a write, that is queued while the outbox is replayed, is sent in another round
+//
+// the outbox and the conflicts are kept in the SyncState, so that a write to another namespace
+// doesn't have to read the store to find out, whether it has to wait
+// every change of them is written to an item of their own, whose store_part is in 0/sync_item, so
+// they survive a restart, when using a FileStore (see stored())
+
+#[derive(Debug)]
+pub struct SyncState {
+    // the version of items of other namespaces, as we last saw them
+    pub(crate) versions: HashMap<MizeId, u64>,
+    replaying: bool,
+
+    // who we are in crdt edits, a new one on every start is fine, as the old one stays in the data
+    pub(crate) replica_id: String,
+
+    outbox: Vec<(CborValue, CborValue)>,
+    conflicts: Vec<(CborValue, CborValue)>,
+    // the store_part of the item, they are written to
+    item: Option<String>,
+}
+
+impl Default for SyncState {
+    fn default() -> SyncState {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        SyncState {
+            versions: HashMap::new(),
+            replaying: false,
+            replica_id: format!("{:016x}", hasher.finish()),
+            outbox: Vec::new(),
+            conflicts: Vec::new(),
+            item: None,
+        }
+    }
+}
+
+impl SyncState {
+    fn map_mut(&mut self, name: &str) -> MizeResult<&mut Vec<(CborValue, CborValue)>> {
+        match name {
+            "outbox" => Ok(&mut self.outbox),
+            "conflicts" => Ok(&mut self.conflicts),
+            other => Err(mize_err!("there is no sync map '{}'", other)),
+        }
+    }
+
+    fn as_data(&self) -> ItemData {
+        ItemData::from_cbor(CborValue::Map(vec![
+            (
+                CborValue::Text("outbox".to_owned()),
+                CborValue::Map(self.outbox.clone()),
+            ),
+            (
+                CborValue::Text("conflicts".to_owned()),
+                CborValue::Map(self.conflicts.clone()),
+            ),
+        ]))
+    }
+
+    // what data has in it's outbox and conflicts, replaces ours
+    fn set_maps(&mut self, data: &ItemData) -> MizeResult<()> {
+        for name in ["outbox", "conflicts"] {
+            *self.map_mut(name)? = match data.get_path(name) {
+                Ok(ItemData(CborValue::Map(map))) => map,
+                _ => Vec::new(),
+            };
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    // the queued write replaces what is there now (the UpdateRequest has replace set)
+    LastWriterWins,
+    // the queued write is merged into what is there now (with item_data_merge)
+    Merge,
+    // nothing is written, the conflict is put into self/sync/conflicts for the user to resolve
+    Manual,
+}
+
+impl ConflictPolicy {
+    pub fn from_config(instance: &Mize) -> MizeResult<ConflictPolicy> {
+        let policy = match instance.get("self/config/sync/conflict")?.value_string() {
+            Ok(policy) => policy,
+            Err(_) => return Ok(ConflictPolicy::LastWriterWins),
+        };
+
+        match policy.as_str() {
+            "last-writer-wins" => Ok(ConflictPolicy::LastWriterWins),
+            "merge" => Ok(ConflictPolicy::Merge),
+            "manual" => Ok(ConflictPolicy::Manual),
+            other => Err(mize_err!(
+                "unknown conflict policy '{}', expected one of: last-writer-wins, merge, manual",
+                other
+            )),
+        }
+    }
+}
+
+// the version of one of our own items, that we tell others about
+// provided items (eg: inst/*) are not in the store, so they have none
+pub fn own_version(instance: &Mize, id: &MizeId) -> MizeResult<Option<u64>> {
+    if id.namespace() != instance.get_self_namespace()?
+        || provider::provider_for(instance, id)?.is_some()
+    {
+        return Ok(None);
+    }
+    Ok(Some(instance.store.lock()?.get_version(id.clone())?))
+}
+
+pub fn saw_version(instance: &Mize, id: &MizeId, msg: &MizeMessage) -> MizeResult<()> {
+    if let Some(version) = msg.version() {
+        instance.sync.lock()?.versions.insert(id.clone(), version);
+    }
+    Ok(())
+}
+
+// a write to id has to wait, if we can't reach it's namespace or earlier writes to it still wait
+pub fn must_queue(instance: &Mize, id: &MizeId) -> MizeResult<bool> {
+    if id.store_part() == "self" || id.namespace() == instance.get_self_namespace()? {
+        return Ok(false);
+    }
+
+    if instance.get_connection_by_ns(id.namespace()).is_err() {
+        return Ok(true);
+    }
+
+    let key = outbox_key(id);
+    Ok(instance
+        .sync
+        .lock()?
+        .outbox
+        .iter()
+        .any(|(entry_key, _)| entry_key == &key))
+}
+
+pub fn queue_write(instance: &Mize, id: &MizeId, data: ItemData) -> MizeResult<()> {
+    debug!(
+        "queueing write to '{}' until it's namespace is reachable",
+        id
+    );
+
+    let version = instance.sync.lock()?.versions.get(id).copied();
+    let key = outbox_key(id);
+
+    update_sync_map(instance, "outbox", |outbox| {
+        // later writes to the same item are merged into the one waiting already, which keeps
+        // the version it was based on
+        if let Some((_, CborValue::Map(entry))) = outbox.iter_mut().find(|(k, _)| k == &key) {
+            if let Some((_, queued)) = entry
+                .iter_mut()
+                .find(|(k, _)| k == &CborValue::Text("data".to_owned()))
+            {
+                item_data_merge(queued, data.cbor());
+                return;
+            }
+        }
+
+        let mut entry = vec![(CborValue::Text("data".to_owned()), data.cbor().to_owned())];
+        if let Some(version) = version {
+            entry.push((CborValue::Text("version".to_owned()), version.into()));
+        }
+        outbox.retain(|(k, _)| k != &key);
+        outbox.push((key, CborValue::Map(entry)));
+    })?;
+
+    // maybe the namespace is reachable and only earlier writes were waiting
+    if instance.get_connection_by_ns(id.namespace()).is_ok() {
+        replay_in_background(instance)?;
+    }
+    Ok(())
+}
+
+// called, whenever a namespace might have become reachable
+pub fn replay_in_background(instance: &Mize) -> MizeResult<()> {
+    if read_sync_map(instance, "outbox")?.is_empty() {
+        return Ok(());
+    }
+
+    let mut sync_inner = instance.sync.lock()?;
+    if sync_inner.replaying {
+        return Ok(());
+    }
+    sync_inner.replaying = true;
+    drop(sync_inner);
+
+    // replaying waits for replies, so it can't happen on an updater thread
+    let instance_clone = instance.clone();
+    let spawned = instance.clone().spawn_background("sync replay", move || {
+        let result = replay_outbox(&instance_clone);
+        instance_clone.sync.lock()?.replaying = false;
+        result
+    });
+    if spawned.is_err() {
+        instance.sync.lock()?.replaying = false;
+    }
+    spawned
+}
+
+pub fn replay_outbox(instance: &Mize) -> MizeResult<()> {
+    loop {
+        let outbox = read_sync_map(instance, "outbox")?;
+        replay_entries(instance, &outbox)?;
+
+        // a new write, or one merged into an entry, that was sent already
+        let queued_meanwhile = read_sync_map(instance, "outbox")?
+            .iter()
+            .any(|entry| !outbox.contains(entry));
+        if !queued_meanwhile {
+            return Ok(());
+        }
+    }
+}
+
+fn replay_entries(instance: &Mize, outbox: &[(CborValue, CborValue)]) -> MizeResult<()> {
+    let policy = ConflictPolicy::from_config(instance)?;
+
+    for (key, entry) in outbox {
+        let id = match &key {
+            CborValue::Text(id_str) => instance.new_id(id_str.as_str())?,
+            _ => continue,
+        };
+
+        let namespace = id.namespace();
+        let mut connection = match instance.get_connection_by_ns(namespace.clone()) {
+            Ok(conn) => conn,
+            // still not reachable
+            Err(_) => continue,
+        };
+
+        let queued = ItemData::from_cbor(entry_field(entry, "data"));
+        let base_version = match entry_field(entry, "version") {
+            CborValue::Integer(version) => u64::try_from(version).ok(),
+            _ => None,
+        };
+
+        // this also tells us the current version of the item
+        let mut current = match instance.get(id.clone())?.as_data_full() {
+            Ok(data) => data,
+            Err(err) => {
+                warn!(
+                    "could not get '{}' to replay a write: {:?}",
+                    id, err.messages
+                );
+                continue;
+            }
+        };
+        let current_version = instance.sync.lock()?.versions.get(&id).copied();
+
+        // crdt values never conflict, they are always joined
+        let conflict = match (base_version, current_version) {
+            (Some(base), Some(current)) => base != current && !crdt::contains_crdt(queued.cbor()),
+            _ => false,
+        };
+
+        let (data, replace) = match (conflict, policy) {
+            (false, _) | (true, ConflictPolicy::Merge) => {
+                current.merge(queued);
+                (current, false)
+            }
+            (true, ConflictPolicy::LastWriterWins) => (queued, true),
+            (true, ConflictPolicy::Manual) => {
+                info!("write to '{}' conflicts, leaving it to the user", id);
+                let mut conflict = vec![
+                    (
+                        CborValue::Text("local".to_owned()),
+                        queued.cbor().to_owned(),
+                    ),
+                    (
+                        CborValue::Text("remote".to_owned()),
+                        current.cbor().to_owned(),
+                    ),
+                ];
+                if let (Some(base), Some(current)) = (base_version, current_version) {
+                    conflict.push((CborValue::Text("version".to_owned()), base.into()));
+                    conflict.push((CborValue::Text("remote_version".to_owned()), current.into()));
+                }
+                update_sync_map(instance, "conflicts", |conflicts| {
+                    conflicts.retain(|(k, _)| k != key);
+                    conflicts.push((key.clone(), CborValue::Map(conflict)));
+                })?;
+                remove_entry(instance, key, entry)?;
+                continue;
+            }
+        };
+
+        if conflict {
+            info!("write to '{}' conflicts, resolved with {:?}", id, policy);
+        }
+
+        let mut msg =
+            MizeMessage::new_update_request(id.clone(), data, connection.id).with_ns(&namespace);
+        if replace {
+            msg = msg.with_replace();
+        }
+        connection.send(msg)?;
+        remove_entry(instance, key, entry)?;
+    }
+
+    Ok(())
+}
+
+// only if it is still the entry, that was replayed, a write queued meanwhile was merged into it
+fn remove_entry(instance: &Mize, key: &CborValue, entry: &CborValue) -> MizeResult<()> {
+    update_sync_map(instance, "outbox", |outbox| {
+        outbox.retain(|(k, v)| k != key || v != entry)
+    })
+}
+
+fn outbox_key(id: &MizeId) -> CborValue {
+    CborValue::Text(format!("{}:{}", id.namespace_str(), id))
+}
+
+fn entry_field(entry: &CborValue, field: &str) -> CborValue {
+    match entry {
+        CborValue::Map(map) => map
+            .iter()
+            .find(|(k, _)| k == &CborValue::Text(field.to_owned()))
+            .map(|(_, v)| v.to_owned())
+            .unwrap_or(CborValue::Null),
+        _ => CborValue::Null,
+    }
+}
+
+fn read_sync_map(instance: &Mize, name: &str) -> MizeResult<Vec<(CborValue, CborValue)>> {
+    Ok(instance.sync.lock()?.map_mut(name)?.clone())
+}
+
+fn update_sync_map(
+    instance: &Mize,
+    name: &str,
+    func: impl FnOnce(&mut Vec<(CborValue, CborValue)>),
+) -> MizeResult<()> {
+    let mut sync_inner = instance.sync.lock()?;
+    func(sync_inner.map_mut(name)?);
+    persist(instance, &mut sync_inner)
+}
+
+// under the sync lock, so that the item never gets an older state than the one it has
+fn persist(instance: &Mize, sync_inner: &mut SyncState) -> MizeResult<()> {
+    let store_inner = instance.store.lock()?;
+    let item = match &sync_inner.item {
+        Some(item) => item.clone(),
+        None => {
+            let item = store_inner.new_id()?;
+            store_inner.set(
+                instance.new_id("0/sync_item")?,
+                ItemData::from_string(item.clone()),
+            )?;
+            sync_inner.item = Some(item.clone());
+            item
+        }
+    };
+    store_inner.set(instance.new_id(item.as_str())?, sync_inner.as_data())
+}
+
+// self/sync/<path>
+pub(crate) fn get(instance: &Mize, path: Vec<String>) -> MizeResult<ItemData> {
+    instance.sync.lock()?.as_data().get_path(path)
+}
+
+// merged into self/sync/<path> (eg: to clear the conflicts, that the user resolved)
+pub(crate) fn set(instance: &Mize, path: Vec<String>, data: ItemData) -> MizeResult<()> {
+    let mut sync_inner = instance.sync.lock()?;
+    let mut full = sync_inner.as_data();
+    let mut sub_item = full.get_path(path.clone())?;
+    sub_item.merge(data);
+    if path.is_empty() {
+        full = sub_item;
+    } else {
+        full.set_path(path, sub_item)?;
+    }
+    sync_inner.set_maps(&full)?;
+    persist(instance, &mut sync_inner)
+}
+
+// the outbox and the conflicts of the last run in store, migrate_to_store() reads them, before it
+// replaces item 0 of the store
+pub(crate) fn stored(instance: &Mize, store: &dyn Store) -> MizeResult<Option<(String, ItemData)>> {
+    let item = match store.get_value_data_full(instance.new_id("0/sync_item")?) {
+        Ok(ItemData(CborValue::Text(item))) => item,
+        _ => return Ok(None),
+    };
+    let data = store.get_value_data_full(instance.new_id(item.as_str())?)?;
+    Ok(Some((item, data)))
+}
+
+// once the new store is in place, what is left from the last run and what was queued in this one
+// are written to the item of the last run
+// our item in the old store was migrated under another store_part, so it is not used anymore
+pub(crate) fn store_replaced(
+    instance: &Mize,
+    stored: Option<(String, ItemData)>,
+) -> MizeResult<()> {
+    let mut sync_inner = instance.sync.lock()?;
+    sync_inner.item = None;
+
+    if let Some((item, data)) = stored {
+        let mut last_run = SyncState::default();
+        last_run.set_maps(&data)?;
+        for name in ["outbox", "conflicts"] {
+            let last_map = last_run.map_mut(name)?.clone();
+            let map = sync_inner.map_mut(name)?;
+            for (key, value) in last_map {
+                if !map.iter().any(|(k, _)| k == &key) {
+                    map.push((key, value));
+                }
+            }
+        }
+        sync_inner.item = Some(item);
+    }
+
+    if sync_inner.item.is_some()
+        || !sync_inner.outbox.is_empty()
+        || !sync_inner.conflicts.is_empty()
+    {
+        return persist(instance, &mut sync_inner);
+    }
+    // a store_part of the old store
+    instance.store.lock()?.set(
+        instance.new_id("0/sync_item")?,
+        ItemData::from_cbor(CborValue::Null),
+    )
+}
@@ -1,6 +1,10 @@
+use ciborium::Value as CborValue;
 use tracing_subscriber::registry::Data;
 
+use crate::instance::subscription::Update;
 use crate::item::IntoItemData;
+use crate::proto::MessageCmd;
+use crate::types::crdt::Counter;
 
 use super::*;
 
@@ -81,6 +85,123 @@ fn test_set_store_path() -> MizeResult<()> {
     Ok(())
 }
 
+#[cfg(feature = "target-os")]
+#[test]
+fn test_file_store_set_sub_path() -> MizeResult<()> {
+    use crate::platform::os::fsstore::FileStore;
+
+    let path = std::env::temp_dir().join(format!("mize-test-{}", std::process::id()));
+    let instance = Mize::empty()?;
+    instance.migrate_to_store(Box::new(FileStore::open_dir(path.to_str().unwrap())?))?;
+    instance.set_blocking("0/config/test", "before".to_owned().into_item_data())?;
+
+    // only the path is written, the rest of the item stays
+    instance.set_blocking("0/config/other", "hi".to_owned().into_item_data())?;
+    assert_eq!(
+        instance.get("0/config/other")?.value_string()?,
+        "hi".to_owned()
+    );
+    assert_eq!(
+        instance.get("0/config/test")?.value_string()?,
+        "before".to_owned()
+    );
+
+    // the socket of the instance next to the store is left alone
+    let _listener = std::os::unix::net::UnixListener::bind(path.join("sock"))?;
+    instance.store.lock()?.flush()?;
+
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_daemon_status() -> MizeResult<()> {
+    use crate::platform::os::daemon::{status, SocketState};
+
+    let path = std::env::temp_dir().join(format!("mize-test-status-{}", std::process::id()));
+    std::fs::create_dir_all(&path)?;
+
+    let status_now = status(&path)?;
+    assert_eq!(status_now.pid, None);
+    assert_eq!(status_now.socket, SocketState::Missing);
+    assert!(!status_now.is_running());
+
+    // above the largest pid linux hands out, so no process has it
+    std::fs::write(path.join("pid"), "4194304\n")?;
+    assert_eq!(status(&path)?.pid, None);
+
+    std::fs::write(path.join("pid"), "not a pid")?;
+    assert!(status(&path).is_err());
+
+    std::fs::write(path.join("pid"), format!("{}\n", std::process::id()))?;
+    let status_now = status(&path)?;
+    assert_eq!(status_now.pid, Some(std::process::id()));
+    assert!(status_now.uptime.is_some());
+    assert!(status_now.is_running());
+    std::fs::remove_file(path.join("pid"))?;
+
+    // the socket of an instance, that was killed
+    drop(std::os::unix::net::UnixListener::bind(path.join("sock"))?);
+    let status_now = status(&path)?;
+    assert_eq!(status_now.socket, SocketState::Stale);
+    assert!(!status_now.is_running());
+    std::fs::remove_file(path.join("sock"))?;
+
+    let listener = std::os::unix::net::UnixListener::bind(path.join("sock"))?;
+    let status_now = status(&path)?;
+    assert_eq!(status_now.socket, SocketState::Accepting);
+    assert!(status_now.is_running());
+    drop(listener);
+
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_format_duration() {
+    use crate::platform::os::daemon::format_duration;
+
+    assert_eq!(format_duration(0), "0s");
+    assert_eq!(format_duration(59), "59s");
+    assert_eq!(format_duration(60), "1m 0s");
+    assert_eq!(format_duration(3599), "59m 59s");
+    assert_eq!(format_duration(3600), "1h 0m 0s");
+    assert_eq!(format_duration(86399), "23h 59m 59s");
+    assert_eq!(format_duration(86400), "1d 0h 0m");
+    assert_eq!(format_duration(90061), "1d 1h 1m");
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_outbox_survives_a_restart() -> MizeResult<()> {
+    use crate::platform::os::fsstore::FileStore;
+
+    let path = std::env::temp_dir().join(format!("mize-test-outbox-{}", std::process::id()));
+    let outbox = ItemData::from_toml(
+        r#"["other.namespace:5"]
+data = "queued""#,
+    )?;
+
+    let instance = Mize::empty()?;
+    instance.migrate_to_store(Box::new(FileStore::open_dir(path.to_str().unwrap())?))?;
+    instance.set_blocking("self/sync/outbox", outbox.clone())?;
+    assert_eq!(instance.get("self/sync/outbox")?.as_data_full()?, outbox);
+    // not in the instance item
+    assert_eq!(
+        instance.get("0")?.as_data_full()?.get_path("sync")?.cbor(),
+        &CborValue::Null
+    );
+
+    let restarted = Mize::empty()?;
+    restarted.migrate_to_store(Box::new(FileStore::open_dir(path.to_str().unwrap())?))?;
+    assert_eq!(restarted.get("self/sync/outbox")?.as_data_full()?, outbox);
+
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
 #[test]
 fn test_set_sub_path() -> MizeResult<()> {
     let instance = Mize::empty()?;
@@ -253,6 +374,1966 @@ fn test_item_merge() -> MizeResult<()> {
     Ok(())
 }
 
+#[cfg(feature = "target-os")]
+#[test]
+fn test_stream_connection() -> MizeResult<()> {
+    use crate::platform::os::stdio::stream_connection;
+    use std::os::unix::net::UnixStream;
+
+    let mut client = Mize::empty()?;
+    let mut server = Mize::empty()?;
+
+    server.set_blocking(
+        "0/config/namespace",
+        "test.stdio.peer".to_owned().into_item_data(),
+    )?;
+
+    // two ends of a pipe, like the stdin and stdout of an ssh child process
+    let (client_sock, server_sock) = UnixStream::pair()?;
+    stream_connection(&mut server, server_sock.try_clone()?, server_sock)?;
+    let conn_id = stream_connection(&mut client, client_sock.try_clone()?, client_sock)?;
+
+    client.join_namespace_of_peer(conn_id)?;
+
+    assert_eq!(
+        client.get_namespace()?.as_real_string(),
+        "test.stdio.peer".to_owned()
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+fn in_process_pair() -> MizeResult<(Mize, Mize)> {
+    let server = Mize::empty()?;
+    server.set_blocking(
+        "0/config/namespace",
+        "test.in.process.server".to_owned().into_item_data(),
+    )?;
+
+    let mut client = Mize::empty()?;
+    client.connect_in_process(&server)?;
+
+    Ok((client, server))
+}
+
+// updates to the owner of an item are asynchronous, so poll a bit
+#[cfg(feature = "target-os")]
+fn eventually_eq(instance: &Mize, id: &str, expected: ItemData) -> MizeResult<()> {
+    for _ in 0..100 {
+        if instance.get(id)?.as_data_full()? == expected {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(instance.get(id)?.as_data_full()?, expected);
+    Ok(())
+}
+
+// polls until cond is true, for what happens on another thread
+#[cfg(feature = "target-os")]
+fn eventually(mut cond: impl FnMut() -> MizeResult<bool>) -> MizeResult<()> {
+    for _ in 0..100 {
+        if cond()? {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    Err(mize_err!("timed out waiting for a condition"))
+}
+
+// a sub of a peer reaches the instance asynchronously
+#[cfg(feature = "target-os")]
+fn wait_for_sub(instance: &Mize, id: &str) -> MizeResult<()> {
+    let id = instance.new_id(id)?;
+    eventually(|| Ok(instance.subs.lock()?.contains_key(&id)))
+        .map_err(|err| err.msg(format!("no one subscribed to '{}'", id)))
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_in_process_get_create_update() -> MizeResult<()> {
+    let (client, server) = in_process_pair()?;
+
+    assert_eq!(
+        client.get_namespace()?.as_real_string(),
+        "test.in.process.server".to_owned()
+    );
+
+    // get
+    server.set_blocking("0/config/test", "hello".to_owned().into_item_data())?;
+    assert_eq!(
+        client.get("0/config/test")?.value_string()?,
+        "hello".to_owned()
+    );
+
+    // create, the item lives in the store of the server
+    let item = client.new_item()?;
+    let id = item.id().store_part().to_owned();
+    assert_ne!(id, "0".to_owned());
+    assert_eq!(server.get(id.as_str())?.as_data_full()?, ItemData::new());
+
+    // update
+    let data = ItemData::from_toml(r#"hi = "from the client""#)?;
+    client.set_blocking(id.as_str(), data.clone())?;
+    eventually_eq(&server, id.as_str(), data.clone())?;
+    assert_eq!(client.get(id.as_str())?.as_data_full()?, data);
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_validators_reject_and_rewrite() -> MizeResult<()> {
+    let (client, server) = in_process_pair()?;
+    let id = client.new_item()?.id().store_part().to_owned();
+
+    // a log entry needs a count, and gets marked as checked
+    server.add_validator(id.as_str(), |_, _, mut data| {
+        if data.get_path("count")?.cbor() == &CborValue::Null {
+            return Err(mize_err!("a log entry needs a count"));
+        }
+        data.set_path("checked", CborValue::Bool(true))?;
+        Ok(data)
+    })?;
+
+    // local
+    assert!(server
+        .set_blocking(id.as_str(), ItemData::from_toml(r#"hi = "no count""#)?)
+        .is_err());
+    assert_eq!(server.get(id.as_str())?.as_data_full()?, ItemData::new());
+
+    // from a peer, which is told about the rejection
+    let err = client
+        .set_blocking(id.as_str(), ItemData::from_toml(r#"hi = "no count""#)?)
+        .unwrap_err();
+    assert!(err
+        .messages
+        .join(": ")
+        .contains("a log entry needs a count"));
+    client.set_blocking(id.as_str(), ItemData::from_toml("count = 3")?)?;
+    eventually_eq(
+        &server,
+        id.as_str(),
+        ItemData::from_toml("count = 3\nchecked = true")?,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_validators_see_a_set_of_a_parent() -> MizeResult<()> {
+    let (client, server) = in_process_pair()?;
+    let id = client.new_item()?.id().store_part().to_owned();
+
+    let log = format!("{}/log", id);
+    server.add_validator(log.as_str(), |_, _, mut data| {
+        if data.get_path("count")?.cbor() == &CborValue::Null {
+            return Err(mize_err!("a log entry needs a count"));
+        }
+        data.set_path("checked", CborValue::Bool(true))?;
+        Ok(data)
+    })?;
+
+    // local
+    assert!(server
+        .set_blocking(id.as_str(), ItemData::from_toml("[log]\nhi = 1")?)
+        .is_err());
+    assert_eq!(server.get(id.as_str())?.as_data_full()?, ItemData::new());
+
+    // from a peer
+    let err = client
+        .set_blocking(id.as_str(), ItemData::from_toml("[log]\nhi = 1")?)
+        .unwrap_err();
+    assert!(err
+        .messages
+        .join(": ")
+        .contains("a log entry needs a count"));
+
+    // what the validator returns is written under it's prefix
+    client.set_blocking(
+        id.as_str(),
+        ItemData::from_toml("name = \"run\"\n[log]\ncount = 3")?,
+    )?;
+    eventually_eq(
+        &server,
+        id.as_str(),
+        ItemData::from_toml("name = \"run\"\n[log]\ncount = 3\nchecked = true")?,
+    )?;
+
+    // a set, that does not write the log, is not checked
+    server.set_blocking(id.as_str(), ItemData::from_toml("name = \"walk\"")?)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_in_process_sub() -> MizeResult<()> {
+    let (client, server) = in_process_pair()?;
+
+    let id = server.new_item()?.id().store_part().to_owned();
+
+    let (tx, rx) = flume::unbounded::<Update>();
+    client.sub(id.as_str(), Subscription::from_sender(tx))?;
+
+    // wait until the server knows about the sub
+    wait_for_sub(&server, id.as_str())?;
+
+    let data = ItemData::from_toml(r#"hi = "from the server""#)?;
+    server.set_blocking(id.as_str(), data.clone())?;
+
+    let update = rx.recv_timeout(std::time::Duration::from_secs(5))?;
+    assert_eq!(update.new_item()?.as_data_full()?, data);
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_instance_async() -> MizeResult<()> {
+    use futures_util::StreamExt;
+
+    let (client, server) = in_process_pair()?;
+    let client = client.as_async();
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    runtime.block_on(async {
+        // created in and fetched from the store of the server
+        let id = client.new_item().await?.id().store_part().to_owned();
+        assert_eq!(client.get(id.as_str()).await?, ItemData::new());
+
+        let mut updates = client.sub(id.as_str())?;
+        wait_for_sub(&server, id.as_str())?;
+
+        let data = ItemData::from_toml(r#"hi = "from async""#)?;
+        client.set(id.as_str(), data.clone()).await?;
+        eventually_eq(&server, id.as_str(), data.clone())?;
+
+        let update = updates.next().await.ok_or(mize_err!("the stream ended"))?;
+        assert_eq!(update.new_item()?.as_data_full()?, data);
+        assert_eq!(client.get(id.as_str()).await?, data);
+
+        Ok(())
+    })
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_inst_items() -> MizeResult<()> {
+    let (client, mut server) = in_process_pair()?;
+
+    // read by the client over the connection
+    let connections = client.get("inst/connections")?.as_data_full()?;
+    assert!(format!("{}", connections).contains(r#""principal": "owner""#));
+
+    let (tx, rx) = flume::unbounded::<Update>();
+    client.sub("inst/threads", Subscription::from_sender(tx))?;
+    wait_for_sub(&server, "inst/threads")?;
+
+    let (stop_tx, stop_rx) = flume::unbounded::<()>();
+    server.spawn_background("test thread", move || {
+        stop_rx.recv()?;
+        Ok(())
+    })?;
+
+    let update = rx.recv_timeout(std::time::Duration::from_secs(5))?;
+    let threads = update.new_item()?.as_data_full()?;
+    assert!(format!("{}", threads).contains("test thread"));
+
+    // a stopped thread is gone again
+    stop_tx.send(())?;
+    rx.recv_timeout(std::time::Duration::from_secs(5))?;
+    let threads = server.get("inst/threads")?.as_data_full()?;
+    assert!(!format!("{}", threads).contains("test thread"));
+
+    let subs = server.get("inst/subs")?.as_data_full()?;
+    assert!(format!("{}", subs).contains("connection:"));
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+struct CounterProvider(Arc<Mutex<u64>>);
+
+#[cfg(feature = "target-os")]
+impl provider::Provider for CounterProvider {
+    fn get(&self, instance: &Mize, id: &MizeId) -> MizeResult<ItemData> {
+        Ok(ItemData::from_cbor(CborValue::from(*self.0.lock()?)))
+    }
+
+    fn set(&self, instance: &Mize, id: &MizeId, data: ItemData) -> MizeResult<()> {
+        let CborValue::Integer(value) = data.cbor() else {
+            return Err(mize_err!("the counter can only be set to a number"));
+        };
+        *self.0.lock()? = u64::try_from(*value)?;
+        instance.changed(id.clone())
+    }
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_provider() -> MizeResult<()> {
+    let (client, server) = in_process_pair()?;
+
+    let counter = Arc::new(Mutex::new(0));
+    server.add_provider("test/counter", CounterProvider(counter.clone()))?;
+
+    // a write of a peer goes to the provider
+    client.set("test/counter", CborValue::from(5u64).into_item_data())?;
+    eventually_eq(
+        &client,
+        "test/counter",
+        CborValue::from(5u64).into_item_data(),
+    )?;
+
+    assert!(server
+        .set_blocking("test/counter", "not a number".into_item_data())
+        .is_err());
+
+    let (tx, rx) = flume::unbounded::<Update>();
+    client.sub("test/counter", Subscription::from_sender(tx))?;
+    wait_for_sub(&server, "test/counter")?;
+
+    // the provider changed it on it's own
+    *counter.lock()? = 9;
+    server.changed("test/counter")?;
+
+    let update = rx.recv_timeout(std::time::Duration::from_secs(5))?;
+    assert_eq!(
+        update.new_item()?.as_data_full()?,
+        CborValue::from(9u64).into_item_data()
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_computed_items() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+    let monday = instance.new_item()?.id().store_part().to_owned();
+    let tuesday = instance.new_item()?.id().store_part().to_owned();
+    instance.set_blocking(monday.as_str(), ItemData::from_toml("done = 1")?)?;
+    instance.set_blocking(tuesday.as_str(), ItemData::from_toml("done = 0")?)?;
+
+    let sum = |values: Vec<ItemData>| -> MizeResult<ItemData> {
+        let mut sum: i128 = 0;
+        for value in values {
+            if let CborValue::Integer(int) = value.get_path("done")?.cbor() {
+                sum += i128::from(*int);
+            }
+        }
+        Ok(ItemData::from_toml(format!("done = {}", sum).as_str())?)
+    };
+    instance.compute("0/streak", vec![monday.as_str(), tuesday.as_str()], sum)?;
+    instance.compute("0/streak_twice", vec!["0/streak", "0/streak"], sum)?;
+    assert_eq!(
+        instance.get("0/streak")?.as_data_full()?,
+        ItemData::from_toml("done = 1")?
+    );
+
+    // an input changes, also the item computed from the computed one
+    let (tx, rx) = flume::unbounded::<Update>();
+    instance.sub("0/streak_twice", Subscription::from_sender(tx))?;
+    instance.set_blocking(tuesday.as_str(), ItemData::from_toml("done = 1")?)?;
+    eventually_eq(&instance, "0/streak", ItemData::from_toml("done = 2")?)?;
+    rx.recv_timeout(std::time::Duration::from_secs(5))?;
+    eventually_eq(
+        &instance,
+        "0/streak_twice",
+        ItemData::from_toml("done = 4")?,
+    )?;
+
+    // cycles
+    assert!(instance.compute("0/loop", vec!["0/loop"], sum).is_err());
+    instance.compute("0/a", vec!["0/b"], sum)?;
+    assert!(instance.compute("0/b", vec!["0/a"], sum).is_err());
+    assert!(instance.set_blocking("0/streak", ItemData::new()).is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_rules() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+    let today = instance.new_item()?.id().store_part().to_owned();
+    let streak = instance.new_item()?.id().store_part().to_owned();
+
+    let (tx, rx) = flume::unbounded::<MizeId>();
+    instance.add_rule_action("bed", move |_, id| {
+        tx.send(id.clone())?;
+        Ok(())
+    })?;
+    instance.add_rule(
+        "done_today",
+        ItemData::from_toml(&format!(
+            "when = \"{}\"\nset = \"{}\"\nvalue = {{ kept = true }}\n[if]\ndone = true",
+            today, streak
+        ))?,
+    )?;
+    instance.add_rule(
+        "bed",
+        ItemData::from_toml(&format!("when = \"{}\"\ncall = \"bed\"", today))?,
+    )?;
+    assert!(instance
+        .add_rule("bad", ItemData::from_toml(r#"when = "0/x""#)?)
+        .is_err());
+    // today sets streak, so streak can't set today
+    assert!(instance
+        .add_rule(
+            "cycle",
+            ItemData::from_toml(&format!(
+                "when = \"{}\"\nset = \"{}\"\nvalue = {{ done = false }}",
+                streak, today
+            ))?,
+        )
+        .is_err());
+
+    // the condition doesn't match, only the rule without one runs
+    instance.set_blocking(today.as_str(), ItemData::from_toml("done = false")?)?;
+    let id = rx.recv_timeout(std::time::Duration::from_secs(5))?;
+    assert_eq!(id.store_part(), today.as_str());
+
+    instance.set_blocking(today.as_str(), ItemData::from_toml("done = true")?)?;
+    eventually_eq(
+        &instance,
+        streak.as_str(),
+        ItemData::from_toml("kept = true")?,
+    )?;
+
+    // the history of a rule
+    rx.recv_timeout(std::time::Duration::from_secs(5))?;
+    eventually(|| {
+        let runs = instance.get("self/rule_runs/bed")?.as_data_full()?;
+        Ok(matches!(runs.cbor(), CborValue::Array(runs) if runs.len() == 2))
+    })?;
+    assert_eq!(
+        instance.get("self/rules/bed/call")?.value_string()?,
+        "bed".to_owned()
+    );
+
+    // a removed rule doesn't run anymore
+    instance.remove_rule("bed")?;
+    instance.set_blocking(today.as_str(), ItemData::from_toml("done = false")?)?;
+    assert!(rx
+        .recv_timeout(std::time::Duration::from_millis(300))
+        .is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_rules_are_only_added_locally() -> MizeResult<()> {
+    let (client, server) = in_process_pair()?;
+    let watched = server.new_item()?.id().store_part().to_owned();
+    let rule = format!("when = \"{}\"\nrun = [\"true\"]", watched);
+
+    // not by a peer, not even the owner, also not as a part of item 0, and not with a set
+    let mut item_0 = ItemData::new();
+    item_0.set_path(vec!["rules", "evil"], ItemData::from_toml(&rule)?)?;
+    assert!(client.set_blocking("0", item_0).is_err());
+    assert!(server
+        .set_blocking("self/rules/evil", ItemData::from_toml(&rule)?)
+        .is_err());
+    assert!(!format!("{}", server.get("0")?.as_data_full()?).contains("evil"));
+
+    // running a command is off by default
+    server.add_rule("cmd", ItemData::from_toml(&rule)?)?;
+    server.set_blocking(watched.as_str(), ItemData::from_toml("x = 1")?)?;
+    eventually(|| {
+        let runs = server.get("self/rule_runs/cmd")?.as_data_full();
+        Ok(runs.is_ok_and(|runs| format!("{}", runs).contains("rules.run")))
+    })?;
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_mounts() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+    instance.new_item()?;
+
+    // a read only mount, with an item in it already
+    let read_only = MemStore::new();
+    read_only.set(
+        instance.new_id("20")?,
+        ItemData::from_toml(r#"hi = "read only""#)?,
+    )?;
+    instance.mount("bulk", 10, 20, Box::new(MemStore::new()), false)?;
+    instance.mount("ro", 20, 30, Box::new(read_only), true)?;
+    assert!(instance
+        .mount("clash", 15, 25, Box::new(MemStore::new()), false)
+        .is_err());
+
+    let item = instance.new_item_in("bulk")?;
+    assert_eq!(item.id().store_part(), "10");
+    instance.set_blocking(item.id(), ItemData::from_toml(r#"hi = "bulk""#)?)?;
+    assert_eq!(
+        instance.get("10/hi")?.as_data_full()?,
+        ItemData::from_string("bulk")
+    );
+
+    assert_eq!(
+        instance.get("20/hi")?.as_data_full()?,
+        ItemData::from_string("read only")
+    );
+    assert!(instance
+        .set_blocking("20/hi", "changed".into_item_data())
+        .is_err());
+    assert!(instance.new_item_in("ro").is_err());
+
+    // the root store allocates around the mounts
+    for _ in 0..20 {
+        let id: u64 = instance.new_item()?.id().store_part().parse()?;
+        assert!(!(10..30).contains(&id));
+    }
+
+    let ids = format!("{}", instance.get("inst/ids")?.as_data_full()?);
+    assert!(ids.contains(r#""10""#) && ids.contains(r#""20""#) && ids.contains(r#""30""#));
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_mount_over_item_0() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+    instance.set_blocking("0/config/test", "kept".to_owned().into_item_data())?;
+
+    // item 0 in memory, the mount gets what the root had
+    let store = MemStore::new();
+    instance.mount("self", 0, 1, Box::new(store.clone()), false)?;
+    assert_eq!(instance.get("0/config/test")?.value_string()?, "kept");
+    assert_eq!(
+        store
+            .get_value_data_full(instance.new_id("0/config/test")?)?
+            .value_string()?,
+        "kept"
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+fn routing_instance(ns: &str) -> MizeResult<Mize> {
+    let instance = Mize::empty()?;
+    instance.set_blocking("0/config/namespace", ns.to_owned().into_item_data())?;
+    instance.set_blocking("0/config/routing", "true".to_owned().into_item_data())?;
+    Ok(instance)
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_routing_over_an_intermediate_instance() -> MizeResult<()> {
+    // laptop <-> home <-> phone
+    let mut laptop = routing_instance("test.laptop")?;
+    let mut home = routing_instance("test.home")?;
+    let phone = routing_instance("test.phone")?;
+
+    laptop.peer_in_process(&home)?;
+    home.peer_in_process(&phone)?;
+
+    let phone_ns = laptop.namespace_from_string("test.phone".to_owned())?;
+    eventually(|| Ok(laptop.get_connection_by_ns(phone_ns.clone()).is_ok()))?;
+    assert_eq!(
+        laptop.routing.lock()?.routes.get(&phone_ns).map(|r| r.hops),
+        Some(2)
+    );
+
+    // get
+    phone.set_blocking(
+        "0/config/test",
+        "from the phone".to_owned().into_item_data(),
+    )?;
+    assert_eq!(
+        laptop.get("test.phone:0/config/test")?.value_string()?,
+        "from the phone".to_owned()
+    );
+
+    // sub
+    let id = phone.new_item()?.id().store_part().to_owned();
+    let remote_id = format!("test.phone:{}", id);
+    let (tx, rx) = flume::unbounded::<Update>();
+    laptop.sub(remote_id.as_str(), Subscription::from_sender(tx))?;
+
+    wait_for_sub(&phone, id.as_str())?;
+
+    let data = ItemData::from_toml(r#"hi = "from the phone""#)?;
+    phone.set_blocking(id.as_str(), data.clone())?;
+    let update = rx.recv_timeout(std::time::Duration::from_secs(5))?;
+    assert_eq!(update.id.namespace(), phone_ns);
+
+    // update
+    let data = ItemData::from_toml(r#"hi = "from the laptop""#)?;
+    laptop.set_blocking(remote_id.as_str(), data.clone())?;
+    eventually_eq(&phone, id.as_str(), data)?;
+
+    // nothing of that ended up on the home instance
+    assert_eq!(home.get(id.as_str())?.as_data_full()?, ItemData::new());
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_auth_key_and_acl() -> MizeResult<()> {
+    let mut laptop = routing_instance("test.auth.laptop")?;
+    laptop.set_blocking(
+        "0/config/auth",
+        ItemData::from_toml(
+            r#"key = "laptop"
+secret = "s3cret""#,
+        )?,
+    )?;
+
+    let home = routing_instance("test.auth.home")?;
+    home.set_blocking(
+        "0/config/auth",
+        ItemData::from_toml(
+            r#"enabled = true
+keys = { laptop = "s3cret" }"#,
+        )?,
+    )?;
+    let readable = home.new_item()?.id().store_part().to_owned();
+    let secret = home.new_item()?.id().store_part().to_owned();
+    home.set_blocking(
+        readable.as_str(),
+        ItemData::from_toml(r#"hi = "readable""#)?,
+    )?;
+    home.set_blocking(secret.as_str(), ItemData::from_toml(r#"hi = "secret""#)?)?;
+    home.set_blocking(
+        "0/acl",
+        ItemData::from_toml(&format!(
+            r#"laptop = {{ who = "key:laptop", prefix = "{}", rights = ["read"] }}"#,
+            readable
+        ))?,
+    )?;
+
+    laptop.peer_in_process(&home)?;
+    let home_ns = laptop.namespace_from_string("test.auth.home".to_owned())?;
+    eventually(|| Ok(laptop.get_connection_by_ns(home_ns.clone()).is_ok()))?;
+
+    // as if the laptop came in over the network
+    let conn_id = home.connections.lock()?[0].id;
+    let mut connection = home.get_connection(conn_id)?;
+    connection.principal = None;
+    home.set_connection(conn_id, connection)?;
+    let readable_remote = format!("test.auth.home:{}", readable);
+    let secret_remote = format!("test.auth.home:{}", secret);
+    assert!(laptop
+        .get(readable_remote.as_str())?
+        .as_data_full()
+        .is_err());
+
+    auth::challenge(&home, conn_id)?;
+    eventually(|| Ok(home.get_connection(conn_id)?.principal.is_some()))?;
+    assert_eq!(
+        home.get_connection(conn_id)?.principal,
+        Some(auth::Principal::Key("laptop".to_owned()))
+    );
+
+    assert_eq!(
+        laptop.get(readable_remote.as_str())?.as_data_full()?,
+        ItemData::from_toml(r#"hi = "readable""#)?
+    );
+    assert!(laptop.get(secret_remote.as_str())?.as_data_full().is_err());
+
+    // the rule does not allow writing
+    assert!(laptop
+        .set_blocking(
+            readable_remote.as_str(),
+            ItemData::from_toml(r#"hi = "changed""#)?,
+        )
+        .is_err());
+    assert_eq!(
+        home.get(readable.as_str())?.as_data_full()?,
+        ItemData::from_toml(r#"hi = "readable""#)?
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_auth_drops_msgs_from_unexpected_peers() -> MizeResult<()> {
+    let laptop = routing_instance("test.expected.laptop")?;
+    let mut home = routing_instance("test.expected.home")?;
+    home.set_blocking("0/config/auth", ItemData::from_toml("enabled = true")?)?;
+    home.peer_in_process(&laptop)?;
+    let laptop_ns = home.namespace_from_string("test.expected.laptop".to_owned())?;
+    eventually(|| Ok(home.get_connection_by_ns(laptop_ns.clone()).is_ok()))?;
+
+    // as if the laptop came in over the network
+    let conn_id = home.connections.lock()?[0].id;
+    let mut connection = home.get_connection(conn_id)?;
+    connection.principal = None;
+    home.set_connection(conn_id, connection)?;
+
+    // an unauthenticated peer can't pull the msgs for a namespace to itself
+    let routes = vec![("test.expected.elsewhere".to_owned(), 1)];
+    home.queue_op(Operation::Msg(MizeMessage::new_routes(routes, conn_id)))?;
+
+    // nor speak for a namespace, that we don't reach over it
+    let elsewhere = home.new_id("test.expected.elsewhere:1")?;
+    let (tx, rx) = flume::unbounded::<Update>();
+    home.subs
+        .lock()?
+        .insert(elsewhere.clone(), vec![Subscription::from_sender(tx)]);
+    let update =
+        MizeMessage::new_update(elsewhere.clone(), ItemData::from_string("spoofed"), conn_id)
+            .with_ns(&elsewhere.namespace());
+    home.queue_op(Operation::Msg(update))?;
+
+    assert!(rx
+        .recv_timeout(std::time::Duration::from_millis(200))
+        .is_err());
+    assert!(home.get_connection_by_ns(elsewhere.namespace()).is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_reply_only_from_the_asked_connection() -> MizeResult<()> {
+    let (client, server) = in_process_pair()?;
+    let conn_id = client.connections.lock()?[0].id;
+    let id = client.new_id("test.asked:1")?;
+
+    // we asked some other connection
+    let rx = client.give_msg_receiver(id.clone(), conn_id + 1, || Ok(()))?;
+    let give = MizeMessage::new_give(id.clone(), ItemData::from_string("spoofed"), conn_id)
+        .with_ns(&id.namespace());
+    client.queue_op(Operation::Msg(give))?;
+    assert!(rx
+        .recv_timeout(std::time::Duration::from_millis(200))
+        .is_err());
+
+    let give = MizeMessage::new_give(id.clone(), ItemData::from_string("asked"), conn_id + 1)
+        .with_ns(&id.namespace());
+    client.queue_op(Operation::Msg(give))?;
+    assert_eq!(
+        rx.recv_timeout(std::time::Duration::from_secs(5))??,
+        ItemData::from_string("asked")
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_shutdown_asked_for_by_a_peer() -> MizeResult<()> {
+    let (client, server) = in_process_pair()?;
+
+    let (tx, rx) = flume::bounded(1);
+    let server_clone = server.clone();
+    std::thread::spawn(move || {
+        server_clone.wait();
+        tx.send(());
+    });
+
+    // like `mize stop`
+    client.set_blocking("inst/shutdown", CborValue::Bool(true).into_item_data())?;
+    rx.recv_timeout(std::time::Duration::from_secs(5))?;
+
+    assert!(server.is_stopping()?);
+    assert!(server.connections.lock()?.is_empty());
+    // the updater threads took their Stop
+    eventually(|| Ok(server.ops.is_empty()))?;
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_only_the_owner_may_shut_down_without_auth() -> MizeResult<()> {
+    let (client, server) = in_process_pair()?;
+
+    // as if the client came in over the network
+    let conn_id = server.connections.lock()?[0].id;
+    let mut connection = server.get_connection(conn_id)?;
+    connection.principal = None;
+    server.set_connection(conn_id, connection)?;
+
+    assert!(client
+        .set_blocking("inst/shutdown", CborValue::Bool(true).into_item_data())
+        .is_err());
+    assert!(!server.is_stopping()?);
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_shutdown_waits_for_the_updater_threads() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+
+    // runs on an updater thread, while shutdown() is called
+    let (started_tx, started_rx) = flume::bounded(1);
+    let done = Arc::new(Mutex::new(false));
+    let done_clone = done.clone();
+    instance.sub(
+        "0/slow",
+        Subscription::from_closure(Box::new(move |_| {
+            started_tx.send(())?;
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            *done_clone.lock()? = true;
+            Ok(())
+        })),
+    )?;
+
+    instance.set("0/slow", ItemData::from_string("yes"))?;
+    started_rx.recv_timeout(std::time::Duration::from_secs(5))?;
+    instance.shutdown()?;
+    assert!(*done.lock()?);
+    assert!(instance.updaters.lock()?.is_empty());
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_wait_joins_background_threads() -> MizeResult<()> {
+    let mut instance = Mize::empty()?;
+
+    let done = Arc::new(Mutex::new(false));
+    let done_clone = done.clone();
+    let instance_clone = instance.clone();
+    instance.spawn_background("test worker", move || {
+        while !instance_clone.is_stopping()? {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        *done_clone.lock()? = true;
+        Ok(())
+    })?;
+
+    instance.shutdown()?;
+    instance.wait();
+    assert!(*done.lock()?);
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_websocket_origin() -> MizeResult<()> {
+    use crate::platform::os::web::origin_allowed;
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    let instance = Mize::empty()?;
+    instance.set_blocking(
+        "0/config/web",
+        ItemData::from_toml(r#"origins = ["https://app.example"]"#)?,
+    )?;
+    let headers = |origin: &'static str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("localhost:3000"));
+        headers.insert(header::ORIGIN, HeaderValue::from_static(origin));
+        headers
+    };
+
+    // not a browser
+    assert!(origin_allowed(&instance, &HeaderMap::new())?);
+    assert!(origin_allowed(
+        &instance,
+        &headers("http://localhost:3000")
+    )?);
+    assert!(origin_allowed(&instance, &headers("https://app.example"))?);
+    assert!(!origin_allowed(
+        &instance,
+        &headers("https://evil.example")
+    )?);
+
+    Ok(())
+}
+
+// serves the websocket and the rest gateway on a free port of localhost
+#[cfg(all(feature = "target-os", feature = "web"))]
+fn serve_web(
+    instance: &Mize,
+    runtime: &tokio::runtime::Runtime,
+) -> MizeResult<std::net::SocketAddr> {
+    use crate::error::IntoMizeResult;
+    use crate::platform::os::web::web_router;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+
+    let _guard = runtime.enter();
+    let server = axum::Server::from_tcp(listener)
+        .mize_result_msg("could not serve on the test listener")?
+        .serve(web_router(instance.clone(), true).into_make_service());
+    runtime.spawn(server);
+
+    Ok(addr)
+}
+
+// a plain http/1.1 request, returns the status and the body
+#[cfg(all(feature = "target-os", feature = "web"))]
+fn http_request(
+    addr: std::net::SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[&str],
+    body: &str,
+) -> MizeResult<(u16, String)> {
+    use std::io::{Read, Write};
+
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        addr,
+        body.len()
+    );
+    for header in headers {
+        request += &format!("{}\r\n", header);
+    }
+    request += "\r\n";
+    request += body;
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or(mize_err!("not an http response: {}", response))?;
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_owned())
+        .unwrap_or_default();
+    Ok((status, body))
+}
+
+#[cfg(all(feature = "target-os", feature = "web"))]
+#[test]
+fn test_rest_get_and_put() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let addr = serve_web(&instance, &runtime)?;
+    let id = instance.new_item()?.id().store_part().to_owned();
+
+    let (status, _) = http_request(
+        addr,
+        "PUT",
+        &format!("/items/{}", id),
+        &[],
+        r#"{"hi": "over http"}"#,
+    )?;
+    assert_eq!(status, 204);
+    let expected = ItemData::from_toml(r#"hi = "over http""#)?;
+    assert_eq!(instance.get(id.as_str())?.as_data_full()?, expected);
+
+    let (status, body) = http_request(addr, "GET", &format!("/items/{}", id), &[], "")?;
+    assert_eq!(status, 200);
+    assert_eq!(ItemData::from_json(body)?, expected);
+
+    // the rules and item 0 as a whole can't be written over the web
+    let (status, _) = http_request(addr, "PUT", "/items/0/rules", &[], r#"{"x": "y"}"#)?;
+    assert_eq!(status, 403);
+    let (status, _) = http_request(addr, "PUT", "/items/0", &[], r#"{"x": "y"}"#)?;
+    assert_eq!(status, 403);
+
+    // without auth only a local peer may shut us down
+    let (status, _) = http_request(addr, "PUT", "/items/inst/shutdown", &[], "true")?;
+    assert_eq!(status, 403);
+
+    Ok(())
+}
+
+#[cfg(all(feature = "target-os", feature = "web"))]
+#[test]
+fn test_rest_auth() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+    instance.set_blocking(
+        "0/config/auth",
+        ItemData::from_toml(
+            r#"enabled = true
+keys = { laptop = "s3cret" }"#,
+        )?,
+    )?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let addr = serve_web(&instance, &runtime)?;
+    let id = instance.new_item()?.id().store_part().to_owned();
+    instance.set_blocking(id.as_str(), ItemData::from_toml(r#"hi = "readable""#)?)?;
+    let path = format!("/items/{}", id);
+
+    let (status, _) = http_request(addr, "GET", &path, &[], "")?;
+    assert_eq!(status, 401);
+    let (status, _) = http_request(addr, "GET", &path, &["Authorization: Bearer laptop"], "")?;
+    assert_eq!(status, 401);
+    let (status, _) = http_request(
+        addr,
+        "GET",
+        &path,
+        &["Authorization: Bearer laptop:wrong"],
+        "",
+    )?;
+    assert_eq!(status, 401);
+
+    // a known key, but no rule for it
+    let auth = "Authorization: Bearer laptop:s3cret";
+    let (status, _) = http_request(addr, "GET", &path, &[auth], "")?;
+    assert_eq!(status, 403);
+
+    instance.set_blocking(
+        "0/acl",
+        ItemData::from_toml(&format!(
+            r#"laptop = {{ who = "key:laptop", prefix = "{}", rights = ["read"] }}"#,
+            id
+        ))?,
+    )?;
+    let (status, body) = http_request(addr, "GET", &path, &[auth], "")?;
+    assert_eq!(status, 200);
+    assert_eq!(
+        ItemData::from_json(body)?,
+        ItemData::from_toml(r#"hi = "readable""#)?
+    );
+    let (status, _) = http_request(addr, "PUT", &path, &[auth], r#"{"hi": "written"}"#)?;
+    assert_eq!(status, 403);
+
+    Ok(())
+}
+
+#[cfg(all(feature = "target-os", feature = "web"))]
+#[test]
+fn test_rest_events() -> MizeResult<()> {
+    use std::io::{Read, Write};
+
+    let instance = Mize::empty()?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let addr = serve_web(&instance, &runtime)?;
+    let id = instance.new_item()?.id().store_part().to_owned();
+    instance.set_blocking(id.as_str(), ItemData::from_toml(r#"hi = "first""#)?)?;
+
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+    stream
+        .write_all(format!("GET /events/{} HTTP/1.1\r\nHost: {}\r\n\r\n", id, addr).as_bytes())?;
+
+    // the stream never ends, so read until what we wait for is there
+    let mut received = String::new();
+    let mut read_until = |expected: &str| -> MizeResult<()> {
+        let mut buf = [0u8; 1024];
+        while !received.contains(expected) {
+            let len = stream.read(&mut buf)?;
+            if len == 0 {
+                return Err(mize_err!("the event stream ended in: {}", received));
+            }
+            received += &String::from_utf8_lossy(&buf[..len]);
+        }
+        Ok(())
+    };
+
+    // the current data first, then one event for every update
+    read_until(r#"data:{"hi":"first"}"#)?;
+    wait_for_sub(&instance, id.as_str())?;
+    instance.set_blocking(id.as_str(), ItemData::from_toml(r#"hi = "second""#)?)?;
+    read_until(r#"data:{"hi":"second"}"#)?;
+
+    Ok(())
+}
+
+// a websocket frame from a client, which has to be masked
+#[cfg(all(feature = "target-os", feature = "web"))]
+fn ws_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [1u8, 2, 3, 4];
+    let mut frame = vec![0x80 | opcode];
+    if payload.len() < 126 {
+        frame.push(0x80 | payload.len() as u8);
+    } else {
+        frame.push(0x80 | 126);
+        frame.extend((payload.len() as u16).to_be_bytes());
+    }
+    frame.extend(mask);
+    frame.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4]),
+    );
+    frame
+}
+
+#[cfg(all(feature = "target-os", feature = "web"))]
+#[test]
+fn test_websocket_listener() -> MizeResult<()> {
+    use std::io::{Read, Write};
+
+    let instance = Mize::empty()?;
+    let runtime = tokio::runtime::Runtime::new()?;
+    let addr = serve_web(&instance, &runtime)?;
+    let id = instance.new_item()?.id();
+
+    let upgrade = |origin: &str| -> MizeResult<(std::net::TcpStream, String)> {
+        let mut stream = std::net::TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        stream.write_all(
+            format!(
+                "GET /@mize/socket HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n{}\r\n",
+                addr, origin
+            )
+            .as_bytes(),
+        )?;
+
+        // only the head, the frames come after it
+        let mut head = Vec::new();
+        let mut byte = [0u8; 1];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte)?;
+            head.push(byte[0]);
+        }
+        Ok((stream, String::from_utf8_lossy(&head).into_owned()))
+    };
+
+    let (_, head) = upgrade("Origin: https://evil.example\r\n")?;
+    assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+    assert!(instance.connections.lock()?.is_empty());
+
+    let (mut stream, head) = upgrade("")?;
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+
+    // a text frame is ignored, the connection stays and takes the next binary frame
+    stream.write_all(&ws_frame(0x1, b"{\"hi\": \"as text\"}"))?;
+    let msg = MizeMessage::new_update_request(id.clone(), ItemData::from_string("as cbor"), 0);
+    let mut buf = Vec::new();
+    ciborium::into_writer(&msg.value(), &mut buf)?;
+    stream.write_all(&ws_frame(0x2, &buf))?;
+    eventually_eq(&instance, id.store_part(), ItemData::from_string("as cbor"))?;
+    assert_eq!(instance.connections.lock()?.len(), 1);
+
+    stream.write_all(&ws_frame(0x8, &[]))?;
+    eventually(|| Ok(instance.connections.lock()?.is_empty()))?;
+
+    Ok(())
+}
+
+// as if the network went away
+#[cfg(feature = "target-os")]
+fn disconnect(instance: &Mize) -> MizeResult<()> {
+    let connections = instance.connections.lock()?.clone();
+    for connection in connections {
+        instance.remove_connection(connection.id)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_offline_writes_are_replayed() -> MizeResult<()> {
+    let (mut client, server) = in_process_pair()?;
+
+    let id = client.new_item()?.id().store_part().to_owned();
+    client.get(id.as_str())?.as_data_full()?;
+
+    disconnect(&client)?;
+
+    let data = ItemData::from_toml(r#"hi = "written offline""#)?;
+    client.set_blocking(id.as_str(), data.clone())?;
+    assert_ne!(
+        client.get("self/sync/outbox")?.as_data_full()?,
+        ItemData::from_cbor(CborValue::Map(Vec::new()))
+    );
+
+    client.connect_in_process(&server)?;
+    eventually_eq(&server, id.as_str(), data)?;
+    eventually_eq(
+        &client,
+        "self/sync/outbox",
+        ItemData::from_cbor(CborValue::Map(Vec::new())),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_offline_write_conflict_last_writer_wins() -> MizeResult<()> {
+    let (mut client, server) = in_process_pair()?;
+
+    let id = client.new_item()?.id().store_part().to_owned();
+    client.get(id.as_str())?.as_data_full()?;
+
+    disconnect(&client)?;
+
+    server.set_blocking(
+        id.as_str(),
+        ItemData::from_toml(
+            r#"hi = "from the server"
+other = "only on the server""#,
+        )?,
+    )?;
+    let client_data = ItemData::from_toml(r#"hi = "from the client""#)?;
+    client.set_blocking(id.as_str(), client_data.clone())?;
+
+    // the write of the client replaces the item, instead of being merged into it
+    client.connect_in_process(&server)?;
+    eventually_eq(&server, id.as_str(), client_data)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_offline_write_conflict_is_left_to_the_user() -> MizeResult<()> {
+    let (mut client, server) = in_process_pair()?;
+    client.set_blocking(
+        "self/config/sync",
+        ItemData::from_toml(r#"conflict = "manual""#)?,
+    )?;
+
+    let id = client.new_item()?.id().store_part().to_owned();
+    client.get(id.as_str())?.as_data_full()?;
+
+    disconnect(&client)?;
+
+    // both change the item, while they can't reach each other
+    let server_data = ItemData::from_toml(r#"hi = "from the server""#)?;
+    server.set_blocking(id.as_str(), server_data.clone())?;
+    let client_data = ItemData::from_toml(r#"hi = "from the client""#)?;
+    client.set_blocking(id.as_str(), client_data.clone())?;
+
+    client.connect_in_process(&server)?;
+
+    let key = format!("test.in.process.server:{}", id);
+    let mut conflict = ItemData::new();
+    eventually(|| {
+        conflict = client.get("self/sync/conflicts")?.as_data_full()?;
+        Ok(conflict != ItemData::from_cbor(CborValue::Map(Vec::new())))
+    })?;
+
+    assert_eq!(
+        conflict.get_path(vec![key.clone(), "local".to_owned()])?,
+        client_data
+    );
+    assert_eq!(
+        conflict.get_path(vec![key, "remote".to_owned()])?,
+        server_data
+    );
+    assert_eq!(server.get(id.as_str())?.as_data_full()?, server_data);
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_crdt_counter_edited_from_two_instances() -> MizeResult<()> {
+    let (laptop, server) = in_process_pair()?;
+    let mut phone = Mize::empty()?;
+    phone.connect_in_process(&server)?;
+
+    let id = laptop.new_item()?.id().store_part().to_owned();
+
+    // both increment the counter, before they know of the other's edit
+    let mut laptop_counter = Counter::from_data(&laptop.get(id.as_str())?.as_data_full()?)?;
+    let mut phone_counter = Counter::from_data(&phone.get(id.as_str())?.as_data_full()?)?;
+    laptop_counter.increment(&laptop.replica_id()?, 2);
+    phone_counter.increment(&phone.replica_id()?, 3);
+
+    phone.set_blocking(id.as_str(), phone_counter)?;
+    laptop.set_blocking(id.as_str(), laptop_counter)?;
+
+    let mut value = 0;
+    eventually(|| {
+        value = Counter::from_data(&server.get(id.as_str())?.as_data_full()?)?.value();
+        Ok(value == 5)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_replica_mirrors_the_namespace() -> MizeResult<()> {
+    let server = Mize::empty()?;
+    server.set_blocking(
+        "0/config/namespace",
+        "test.replica.owner".to_owned().into_item_data(),
+    )?;
+    let old_id = server.new_item()?.id().store_part().to_owned();
+    server.set_blocking(old_id.as_str(), ItemData::from_toml(r#"hi = "old""#)?)?;
+
+    let mut replica = Mize::empty()?;
+    replica.set_blocking("self/config/replica", "true".to_owned().into_item_data())?;
+    replica.connect_in_process(&server)?;
+
+    eventually(|| {
+        Ok(replica.get("inst/replica/items")?.as_data_full()?.cbor() != &CborValue::from(0u64))
+    })?;
+    let id = replica.new_id(old_id.as_str())?;
+    assert!(replica::serves(&replica, &id)?);
+    assert_eq!(
+        replica.get(old_id.as_str())?.as_data_full()?,
+        ItemData::from_toml(r#"hi = "old""#)?
+    );
+
+    // changes on the owner reach the mirror
+    server.set_blocking(old_id.as_str(), ItemData::from_toml(r#"hi = "changed""#)?)?;
+    eventually_eq(
+        &replica,
+        old_id.as_str(),
+        ItemData::from_toml(r#"hi = "changed""#)?,
+    )?;
+
+    // and so do new items
+    let new_id = server.new_item()?.id().store_part().to_owned();
+    let new_id = replica.new_id(new_id.as_str())?;
+    eventually(|| replica::serves(&replica, &new_id))?;
+
+    assert!(replica
+        .get("inst/replica/last_sync")?
+        .as_data_full()
+        .is_ok());
+
+    Ok(())
+}
+
+// a writer, that can still be read after it was given to the recorder
+#[derive(Clone, Default)]
+struct SharedBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_record_and_replay() -> MizeResult<()> {
+    use super::recorder::{self, Direction};
+
+    let server = Mize::empty()?;
+    let log = SharedBuf::default();
+    server.record_to(Box::new(log.clone()))?;
+
+    let mut client = Mize::empty()?;
+    client.connect_in_process(&server)?;
+    let id = client.new_item()?.id().store_part().to_owned();
+    client.set_blocking(id.as_str(), ItemData::from_toml(r#"hi = "recorded""#)?)?;
+    eventually_eq(
+        &server,
+        id.as_str(),
+        ItemData::from_toml(r#"hi = "recorded""#)?,
+    )?;
+    server.stop_recording()?;
+
+    let records = recorder::read_records(log.0.lock()?.as_slice())?;
+    let has = |dir: Direction, cmd: fn(&MessageCmd) -> bool| {
+        records
+            .iter()
+            .any(|record| record.dir == dir && record.msg.cmd().is_ok_and(|c| cmd(&c)))
+    };
+    assert!(has(Direction::In, |cmd| matches!(cmd, MessageCmd::Create)));
+    assert!(has(Direction::In, |cmd| matches!(
+        cmd,
+        MessageCmd::UpdateRequest
+    )));
+    assert!(has(Direction::Out, |cmd| matches!(
+        cmd,
+        MessageCmd::CreateReply
+    )));
+
+    // a fresh instance does the same with the recorded msgs
+    let fresh = Mize::empty()?;
+    let rx = recorder::replay(&fresh, records)?;
+    let mut replies = Vec::new();
+    while let Ok(msg) = rx.recv_timeout(std::time::Duration::from_millis(200)) {
+        replies.push(msg.cmd()?);
+    }
+    assert!(replies
+        .iter()
+        .any(|cmd| matches!(cmd, MessageCmd::CreateReply)));
+    eventually_eq(
+        &fresh,
+        id.as_str(),
+        ItemData::from_toml(r#"hi = "recorded""#)?,
+    )?;
+
+    Ok(())
+}
+
+// a connection, that never reads what is sent to it, subscribed to a new item
+#[cfg(feature = "target-os")]
+fn stalled_subscriber(instance: &Mize) -> MizeResult<(u64, String, QueueReceiver<MizeMessage>)> {
+    let (queue, rx) = instance.new_conn_queue()?;
+    let conn_id = instance.new_connection(queue)?;
+    let id = instance.new_item()?.id().store_part().to_owned();
+    instance.sub(
+        id.as_str(),
+        Subscription::from_conn(instance.get_connection(conn_id)?),
+    )?;
+    Ok((conn_id, id, rx))
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_full_send_queue_drops_oldest_update() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+    instance.set_blocking(
+        "self/config/queue",
+        ItemData::from_toml(
+            r#"size = 3
+full = "drop-oldest""#,
+        )?,
+    )?;
+    let (conn_id, id, rx) = stalled_subscriber(&instance)?;
+
+    for i in 0..10u64 {
+        instance.set_blocking(id.as_str(), ItemData::from_cbor(CborValue::from(i)))?;
+    }
+
+    // the queue never grew past it's size, the oldest updates made space for the newer ones
+    let depth = instance.get(format!("inst/queues/connections/{}", conn_id))?;
+    assert_eq!(depth.as_data_full()?.cbor(), &CborValue::from(3u64));
+    let queued: Vec<CborValue> = rx
+        .drain()
+        .map(|mut msg| msg.data().map(|data| data.cbor().to_owned()))
+        .collect::<MizeResult<_>>()?;
+    assert_eq!(
+        queued,
+        vec![
+            CborValue::from(7u64),
+            CborValue::from(8u64),
+            CborValue::from(9u64)
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_full_queue_waits_for_space() -> MizeResult<()> {
+    let queue = Queue::new(1, FullPolicy::Block);
+    let rx = queue.receiver();
+    queue.push_blocking(1)?;
+
+    let pusher = queue.clone();
+    let handle = std::thread::spawn(move || pusher.push_blocking(2));
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    // waiting does not keep others from pushing, where there is space
+    queue.set_limits(1, FullPolicy::Disconnect)?;
+    assert!(!queue.push(3, |_, _| false)?);
+    queue.set_limits(1, FullPolicy::Block)?;
+
+    assert_eq!(rx.recv()?, 1);
+    handle.join().unwrap()?;
+    assert_eq!(rx.recv()?, 2);
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_route_advert_waits_without_the_routing_lock() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+    let queue = Queue::new(1, FullPolicy::Block);
+    let rx = queue.receiver();
+    let conn_id = instance.new_connection(queue.clone())?;
+    rx.drain();
+    queue.push_blocking(MizeMessage::new_routes(Vec::new(), conn_id))?;
+
+    // waits for space in the full queue of the peer
+    let instance_clone = instance.clone();
+    let handle = std::thread::spawn(move || {
+        let connection = instance_clone.get_connection(conn_id)?;
+        routing::advertise_routes_to(&instance_clone, &connection)
+    });
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert!(!handle.is_finished());
+    assert!(instance.routing.try_lock().is_ok());
+
+    rx.recv()?;
+    handle.join().unwrap()?;
+    assert!(rx.recv()?.seq().is_some());
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_full_queue_push_async() -> MizeResult<()> {
+    let queue = Queue::new(1, FullPolicy::Block);
+    let rx = queue.receiver();
+    queue.push_blocking(1)?;
+
+    // with one thread, a push, that blocked it, would never let the receiver run
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    runtime.block_on(async {
+        let pusher = queue.clone();
+        let push = tokio::spawn(async move { pusher.push_async(2).await });
+        tokio::task::yield_now().await;
+        assert!(!push.is_finished());
+
+        assert_eq!(rx.recv_async().await?, 1);
+        push.await??;
+        assert_eq!(rx.recv_async().await?, 2);
+        Ok(())
+    })
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_full_send_queue_disconnects() -> MizeResult<()> {
+    let instance = Mize::empty()?;
+    instance.set_blocking(
+        "self/config/queue",
+        ItemData::from_toml(
+            r#"size = 3
+full = "disconnect""#,
+        )?,
+    )?;
+    let (conn_id, id, _rx) = stalled_subscriber(&instance)?;
+
+    for i in 0..10u64 {
+        instance.set_blocking(id.as_str(), ItemData::from_cbor(CborValue::from(i)))?;
+    }
+
+    eventually(|| Ok(instance.get_connection(conn_id).is_err()))?;
+
+    Ok(())
+}
+
+// records every call of the lifecycle into a log
+#[derive(Default)]
+struct LifecyclePart {
+    mize: Mize,
+    name: &'static str,
+    deps: &'static [&'static str],
+    run_blocks: bool,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl LifecyclePart {
+    fn add(
+        instance: &mut Mize,
+        name: &'static str,
+        deps: &'static [&'static str],
+        log: &Arc<Mutex<Vec<String>>>,
+    ) -> MizeResult<()> {
+        instance.add_part(Box::new(LifecyclePart {
+            mize: instance.clone(),
+            name,
+            deps,
+            log: log.clone(),
+            ..Default::default()
+        }))
+    }
+
+    fn log(&self, what: &str) {
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", what, self.name));
+    }
+}
+
+#[async_trait]
+impl MizePart for LifecyclePart {
+    fn deps(&self) -> &'static [&'static str] {
+        self.deps
+    }
+    fn run_blocks(&self) -> bool {
+        self.run_blocks
+    }
+    fn take_blocking_run(&mut self) -> Option<BlockingRun> {
+        if !self.run_blocks {
+            return None;
+        }
+        let name = self.name;
+        let log = self.log.clone();
+        Some(Box::new(move |mize: &mut Mize| {
+            // the part is not held, while it blocks
+            mize.get_part(name)?;
+            log.lock().unwrap().push(format!("blocking run {}", name));
+            Ok(())
+        }))
+    }
+    fn opts(&self, mize: &mut Mize) {
+        self.log("opts");
+    }
+    fn init(&mut self, mize: &mut Mize) -> MizeResult<()> {
+        self.log("init");
+        Ok(())
+    }
+    async fn async_init(&mut self, mize: &mut Mize) -> MizeResult<()> {
+        self.log("async_init");
+        Ok(())
+    }
+    fn run(&mut self, mize: &mut Mize) -> MizeResult<()> {
+        // a part can use the parts it depends on
+        for dep in self.deps {
+            mize.get_part(dep)?;
+        }
+        self.log("run");
+        Ok(())
+    }
+    fn shutdown(&mut self, mize: &mut Mize) -> MizeResult<()> {
+        self.log("shutdown");
+        Ok(())
+    }
+}
+
+impl MizePartGenerated for LifecyclePart {
+    fn name_generated(&self) -> &'static str {
+        self.name
+    }
+    fn get_mize_generated(&mut self) -> &mut Mize {
+        &mut self.mize
+    }
+    fn as_any_generated(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut_generated(&mut self) -> &mut dyn Any {
+        self
+    }
+    fn into_any_generated(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_part_lifecycle() -> MizeResult<()> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut instance = Mize::empty()?;
+    LifecyclePart::add(&mut instance, "web", &["store", "auth"], &log)?;
+    LifecyclePart::add(&mut instance, "auth", &["store"], &log)?;
+    LifecyclePart::add(&mut instance, "store", &[], &log)?;
+    assert_eq!(instance.part_order()?, vec!["store", "auth", "web"]);
+
+    instance.init_parts()?;
+    // inited parts are not inited again
+    instance.init_parts()?;
+    // there are no threads to wait for, so it shuts the parts down right away
+    instance.run()?;
+    // they are not shut down twice
+    instance.shutdown()?;
+
+    let expected: Vec<String> = [
+        "opts store",
+        "opts auth",
+        "opts web",
+        "init store",
+        "async_init store",
+        "init auth",
+        "async_init auth",
+        "init web",
+        "async_init web",
+        "run store",
+        "run auth",
+        "run web",
+        "shutdown web",
+        "shutdown auth",
+        "shutdown store",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    assert_eq!(*log.lock().unwrap(), expected);
+
+    // a missing dependency and a cycle
+    let mut instance = Mize::empty()?;
+    LifecyclePart::add(&mut instance, "web", &["auth"], &log)?;
+    assert!(instance.init_parts().is_err());
+    LifecyclePart::add(&mut instance, "auth", &["web"], &log)?;
+    let err = instance.part_order().unwrap_err();
+    assert!(err.messages.join("").contains("web -> auth -> web"));
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_blocking_run_comes_last() -> MizeResult<()> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut instance = Mize::empty()?;
+    instance.add_part(Box::new(LifecyclePart {
+        mize: instance.clone(),
+        name: "cli",
+        run_blocks: true,
+        log: log.clone(),
+        ..Default::default()
+    }))?;
+    LifecyclePart::add(&mut instance, "c2vi", &["cli"], &log)?;
+    LifecyclePart::add(&mut instance, "scheduler", &[], &log)?;
+
+    assert_eq!(instance.part_order()?, vec!["cli", "c2vi", "scheduler"]);
+    assert_eq!(instance.run_order()?, vec!["scheduler", "cli", "c2vi"]);
+
+    instance.run_parts()?;
+    let log = log.lock().unwrap().clone();
+    let ran: Vec<&str> = log
+        .iter()
+        .filter(|line| line.contains("run"))
+        .map(|line| line.as_str())
+        .collect();
+    assert_eq!(
+        ran,
+        vec!["run scheduler", "run cli", "blocking run cli", "run c2vi"]
+    );
+
+    Ok(())
+}
+
+#[cfg(all(feature = "target-os", feature = "async"))]
+#[test]
+fn test_parts_inited_on_a_runtime_thread() -> MizeResult<()> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut instance = Mize::empty()?;
+    LifecyclePart::add(&mut instance, "store", &[], &log)?;
+
+    let runtime = tokio::runtime::Builder::new_multi_thread().build()?;
+    let mut instance_clone = instance.clone();
+    runtime.block_on(async move { instance_clone.init_parts() })?;
+    assert!(log.lock().unwrap().contains(&"async_init store".to_owned()));
+
+    // a current thread runtime would be blocked, that is an error instead of a panic
+    LifecyclePart::add(&mut instance, "web", &[], &log)?;
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    let mut instance_clone = instance.clone();
+    assert!(runtime
+        .block_on(async move { instance_clone.init_parts() })
+        .is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_shared_part_access() -> MizeResult<()> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut instance = Mize::empty()?;
+    LifecyclePart::add(&mut instance, "store", &[], &log)?;
+
+    // readers on two threads at the same time
+    let reading = instance.read_part_native::<LifecyclePart>("store")?;
+    let instance_clone = instance.clone();
+    let other_reader = thread::spawn(move || -> MizeResult<&'static str> {
+        Ok(instance_clone.read_part("store")?.name())
+    });
+    assert_eq!(other_reader.join().unwrap()?, "store");
+    assert_eq!(
+        instance
+            .get("inst/parts/store")?
+            .as_data_full()?
+            .value_string()?,
+        "read"
+    );
+
+    // reading it again on the same thread is fine, writing it would wait for ourselves
+    assert_eq!(instance.read_part("store")?.name(), "store");
+    let err = instance.get_part("store").err().unwrap();
+    assert!(err.messages.join("").contains("deadlock"));
+
+    // a writer waits for the reader
+    let instance_clone = instance.clone();
+    let writer = thread::spawn(move || -> MizeResult<()> {
+        instance_clone.get_part_native::<LifecyclePart>("store")?;
+        Ok(())
+    });
+    thread::sleep(std::time::Duration::from_millis(100));
+    assert!(!writer.is_finished());
+    drop(reading);
+    writer.join().unwrap()?;
+
+    assert!(instance.get_part_native::<NameOnlyPart>("store").is_err());
+    assert!(instance.read_part("nothing").is_err());
+    assert_eq!(
+        instance
+            .get("inst/parts/store")?
+            .as_data_full()?
+            .value_string()?,
+        "available"
+    );
+
+    Ok(())
+}
+
+#[cfg(feature = "target-os")]
+#[test]
+fn test_inst_parts_only_changes_with_the_state() -> MizeResult<()> {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut instance = Mize::empty()?;
+    LifecyclePart::add(&mut instance, "store", &[], &log)?;
+    let (tx, rx) = flume::unbounded();
+    instance.sub("inst/parts", Subscription::from_sender(tx))?;
+    let next = || rx.recv_timeout(std::time::Duration::from_millis(300));
+
+    // available -> read, the second reader changes nothing
+    let reading = instance.read_part("store")?;
+    next()?;
+    let instance_clone = instance.clone();
+    thread::spawn(move || -> MizeResult<()> {
+        instance_clone.read_part("store")?;
+        Ok(())
+    })
+    .join()
+    .unwrap()?;
+    assert!(next().is_err());
+
+    // read -> available, told by an updater thread
+    drop(reading);
+    next()?;
+    assert!(next().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_config_opt_defaults_and_types() -> MizeResult<()> {
+    let mut instance = Mize::empty()?;
+    // like read from a config file, before the option was declared
+    instance.config_opts.lock()?.insert(
+        "hab.retries".to_owned(),
+        ConfigOpt {
+            name: "hab.retries".to_owned(),
+            val: Some(ItemData::from_string("5")),
+            ..Default::default()
+        },
+    );
+    instance
+        .new_opt("hab.retries")
+        .default_val(ItemData::from_string("3"));
+    instance
+        .new_opt("hab.verbose")
+        .default_val(ItemData::from_string("true"))
+        .doc("log every request");
+
+    assert_eq!(instance.get_config("hab.retries")?.value_as::<u64>()?, 5);
+    assert!(instance.get_config("hab.verbose")?.value_as::<bool>()?);
+    assert_eq!(
+        instance.get_config("hab.verbose")?.value_as::<String>()?,
+        "true"
+    );
+    assert!(instance
+        .get_config("hab.retries")?
+        .value_as::<bool>()
+        .is_err());
+
+    Ok(())
+}
+
+// like a part of marts, with options
+#[crate::mize_part("hab")]
+#[derive(Default)]
+struct OptPart {
+    mize: Mize,
+    other: u8,
+    #[opt(default = "https://x/api", doc = "the url of the api")]
+    api_url: String,
+    #[opt(name = "hab.retries", default = 3)]
+    retry_count: u64,
+    #[opt]
+    verbose: bool,
+}
+
+#[async_trait]
+impl MizePart for OptPart {}
+
+#[test]
+fn test_mize_part_opts() -> MizeResult<()> {
+    let mut instance = Mize::empty()?;
+    instance.config_opts.lock()?.insert(
+        "hab.retries".to_owned(),
+        ConfigOpt {
+            name: "hab.retries".to_owned(),
+            val: Some(ItemData::from_string("5")),
+            ..Default::default()
+        },
+    );
+    instance.add_part(Box::new(OptPart {
+        mize: instance.clone(),
+        other: 1,
+    }))?;
+    instance.init_parts()?;
+
+    let part = instance.read_part_native::<OptPart>("hab")?;
+    assert_eq!(part.api_url()?, "https://x/api");
+    assert_eq!(part.retry_count()?, 5);
+    assert!(part.verbose().is_err());
+    assert_eq!(part.other, 1);
+    assert!(instance.config_opts.lock()?.contains_key("hab.api_url"));
+
+    Ok(())
+}
+
+#[test]
+fn test_config_opt_validation_and_missing() -> MizeResult<()> {
+    let mut instance = Mize::empty()?;
+    instance
+        .config_files
+        .lock()?
+        .push("/etc/mize/hab.toml".to_owned());
+    instance
+        .new_opt("hab.port")
+        .typed::<u16>()
+        .validate(|port: u16| match port {
+            0..=1023 => Err(mize_err!("port {} is reserved", port)),
+            _ => Ok(()),
+        })
+        .default_val(ItemData::from_string("80"));
+    instance
+        .new_opt("hab.api_token")
+        .typed::<String>()
+        .doc("the token of the habitica api");
+
+    let err = instance.get_config_as::<u16>("hab.port").unwrap_err();
+    assert!(err.messages.join("").contains("port 80 is reserved"));
+    // a wrong default is found at the start too
+    let err = crate::config::check_all(&instance).unwrap_err();
+    assert!(err
+        .messages
+        .join("")
+        .contains("the option 'hab.port' (from the default)"));
+    instance
+        .config_opts
+        .lock()?
+        .get_mut("hab.port")
+        .unwrap()
+        .val = Some(ItemData::from_string("8080"));
+    assert_eq!(instance.get_config_as::<u16>("hab.port")?, 8080);
+
+    let err = instance
+        .get_config_as::<String>("hab.api_token")
+        .unwrap_err();
+    let msg = err.messages.join("");
+    assert!(msg.contains("'hab.api_token' is not set"));
+    assert!(msg.contains("the token of the habitica api"));
+    assert!(msg.contains("MIZE_OPT_HAB_API_TOKEN"));
+    assert!(msg.contains("/etc/mize/hab.toml"));
+    assert!(msg.contains("MIZE_CONFIG and MIZE_CONFIG_FILE"));
+    assert!(msg.contains("as api_token = ... in [hab]"));
+
+    // a top-level option is not in a table
+    instance.new_opt("verbose").typed::<bool>();
+    let msg = instance
+        .get_config_as::<bool>("verbose")
+        .unwrap_err()
+        .messages
+        .join("");
+    assert!(msg.contains("as verbose = ... at the top of one of the config files"));
+    assert!(!msg.contains("[]"));
+    assert_eq!(
+        instance.get_config_as::<Option<String>>("hab.api_token")?,
+        None
+    );
+
+    Ok(())
+}
+
 /*
 #[test]
 #[should_panic(expected = "correct panic")]
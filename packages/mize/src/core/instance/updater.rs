@@ -1,7 +1,9 @@
-use flume::Receiver;
+use ciborium::Value as CborValue;
+use flume::Sender;
 use std::borrow::BorrowMut;
+use std::cell::Cell;
 use std::sync::Arc;
-use tracing::{error, trace, warn};
+use tracing::{debug, error, trace, warn};
 
 use crate::error::{MizeError, MizeResult, MizeResultTrait};
 use crate::id::MizeId;
@@ -9,13 +11,37 @@ use crate::mize_err;
 use crate::proto::{MessageCmd, MizeMessage};
 use crate::{instance::Mize, item::ItemData};
 
+use super::auth;
 use super::connection::{self, Connection};
+use super::introspect;
+use super::provider;
+use super::queue::QueueReceiver;
+use super::replica;
+use super::routing;
 use super::subscription::{Subscription, Update};
+use super::sync;
+use super::validator;
 
 #[derive(Debug)]
 pub enum Operation {
     Set(MizeId, ItemData, Option<Connection>), // bool: is_from_update_msg
+    // like Set, but the data replaces the item (an UpdateRequest with replace, see sync.rs)
+    Replace(MizeId, ItemData, Connection),
     Msg(MizeMessage),
+    // the send queue of the connection is full
+    Disconnect(u64),
+    // inst/<name> changed, where the subscribers can't be told right away (see part.rs)
+    Changed(&'static str),
+    // the updater thread, that gets it, ends (see Mize::shutdown)
+    Stop,
+}
+
+thread_local! {
+    static ON_UPDATER_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn on_updater_thread() -> bool {
+    ON_UPDATER_THREAD.with(|on| on.get())
 }
 
 // console_log macro
@@ -45,7 +71,7 @@ macro_rules! console_log {
 }
 //end of console_log macro
 
-pub async fn updater_thread_async(operation_rx: Receiver<Operation>, instance: Mize) -> () {
+pub async fn updater_thread_async(operation_rx: QueueReceiver<Operation>, instance: Mize) -> () {
     let mut count = 0;
     console_log!("inside an updater thread");
 
@@ -60,7 +86,11 @@ pub async fn updater_thread_async(operation_rx: Receiver<Operation>, instance: M
 
         let op_str = match operation {
             Operation::Set(_, _, _) => "SET",
+            Operation::Replace(_, _, _) => "REPLACE",
             Operation::Msg(_) => "MSG",
+            Operation::Disconnect(_) => "DISCONNECT",
+            Operation::Changed(_) => "CHANGED",
+            Operation::Stop => break,
         };
 
         trace!("OPERATION {} - {}", count, op_str);
@@ -78,14 +108,22 @@ pub async fn updater_thread_async(operation_rx: Receiver<Operation>, instance: M
     }
 }
 
-pub fn updater_thread(operation_rx: Receiver<Operation>, instance: &Mize) -> MizeResult<()> {
+pub fn updater_thread(operation_rx: QueueReceiver<Operation>, instance: &Mize) -> MizeResult<()> {
     let mut count = 0;
+    ON_UPDATER_THREAD.with(|on| on.set(true));
 
     loop {
         let mut operation = operation_rx.recv()?;
         let op_str = match operation {
             Operation::Set(_, _, _) => "SET",
+            Operation::Replace(_, _, _) => "REPLACE",
             Operation::Msg(_) => "MSG",
+            Operation::Disconnect(_) => "DISCONNECT",
+            Operation::Changed(_) => "CHANGED",
+            Operation::Stop => {
+                debug!("updater thread stopping");
+                return Ok(());
+            }
         };
 
         trace!("OPERATION {} - {}", count, op_str);
@@ -106,53 +144,125 @@ pub fn updater_thread(operation_rx: Receiver<Operation>, instance: &Mize) -> Miz
 pub fn handle_operation(operation: &mut Operation, instance: &Mize) -> MizeResult<()> {
     match operation {
         Operation::Set(id, value, maybe_conn) => {
-            let item_data: ItemData = value.to_owned();
-            let mut item = instance.get(id.clone())?;
-            item.merge(item_data)?;
-
-            //check subs and handle them
-            let mut subs_inner = instance.subs.lock()?;
-            if let Some(vec) = subs_inner.get_mut(&id) {
-                let update = Update {
-                    instance: Arc::new(instance.to_owned()),
-                    id: id.clone(),
-                };
-                for sub in vec.iter_mut() {
-                    // don't handle sub of type connection, in case the update comes from this
-                    // connection
-                    if let Some(conn) = maybe_conn {
-                        if let Subscription::Connection(conn2) = sub {
-                            if conn.id == conn2.id {
-                                continue;
-                            }
-                        }
-                    }
+            // an update from the owner of an item, that is not ours, only has to reach our
+            // subscribers, writing it back would change it's version again
+            let from_owner =
+                maybe_conn.is_some() && id.namespace() != instance.get_self_namespace()?;
+            if from_owner {
+                replica::mirror(instance, id, value)?;
+            } else {
+                let item_data: ItemData = value.to_owned();
+                let mut item = instance.get(id.clone())?;
+                item.merge(item_data)?;
+            }
+
+            notify_subs(instance, id, maybe_conn)?;
+        }
+        Operation::Replace(id, value, conn) => {
+            match provider::provider_for(instance, id)? {
+                Some(provider) => provider.set(instance, id, value.to_owned())?,
+                None => instance.store.lock()?.set(id.clone(), value.to_owned())?,
+            }
+            notify_subs(instance, id, &Some(conn.clone()))?;
+        }
+        Operation::Msg(msg) => handle_msg(msg, instance)?,
+        Operation::Disconnect(conn_id) => instance.remove_connection(*conn_id)?,
+        Operation::Changed(name) => introspect::changed(instance, name)?,
+        // the updater threads end on it, before it gets here
+        Operation::Stop => {}
+    }
+    Ok(())
+}
 
+// tell everyone subscribed to id, that it changed
+pub(crate) fn notify_subs(
+    instance: &Mize,
+    id: &MizeId,
+    maybe_conn: &Option<Connection>,
+) -> MizeResult<()> {
+    notify_subs_with(instance, id, maybe_conn, None)
+}
+
+// connections get data instead of the item
+pub(crate) fn notify_subs_with(
+    instance: &Mize,
+    id: &MizeId,
+    maybe_conn: &Option<Connection>,
+    data: Option<ItemData>,
+) -> MizeResult<()> {
+    let update = Update {
+        instance: Arc::new(instance.to_owned()),
+        id: id.clone(),
+        data,
+    };
+
+    // sending to a connection reads the item, which must not happen while we hold the lock
+    // (inst/subs reads it)
+    let mut to_connections = Vec::new();
+
+    let mut subs_inner = instance.subs.lock()?;
+    if let Some(vec) = subs_inner.get_mut(id) {
+        for sub in vec.iter_mut() {
+            match sub {
+                // don't handle sub of type connection, in case the update comes from this
+                // connection
+                Subscription::Connection(conn2) => {
+                    if maybe_conn.as_ref().is_some_and(|conn| conn.id == conn2.id) {
+                        continue;
+                    }
+                    to_connections.push(conn2.clone());
+                }
+                _ => {
                     sub.handle(update.clone());
                 }
             }
         }
-        Operation::Msg(msg) => handle_msg(msg, instance)?,
+
+        // forget about subscribers, that went away (eg: a closed http event stream)
+        vec.retain(|sub| !sub.is_closed());
+    }
+    drop(subs_inner);
+
+    for conn in to_connections {
+        Subscription::Connection(conn).handle(update.clone());
     }
     Ok(())
 }
 
 fn handle_msg(msg: &mut MizeMessage, instance: &Mize) -> MizeResult<()> {
+    // a peer may only do, what the acl allows, even for other namespaces
+    if !auth::check_msg(msg, instance)? {
+        return Ok(());
+    }
+
+    // msgs for other namespaces and replies to them are passed on
+    if routing::route_msg(msg, instance)? {
+        return Ok(());
+    }
+
     match msg.cmd()? {
         MessageCmd::Get => {
             let id = msg.id(instance)?;
             let mut connection = instance.get_connection(msg.conn_id)?.clone();
             let item = instance.get(id.clone())?;
-            let msg = MizeMessage::new_give(id, item.as_data_full()?, msg.conn_id);
-            connection.send(msg)?;
+            let reply = with_version(
+                MizeMessage::new_give(id.clone(), item.as_data_full()?, msg.conn_id),
+                &id,
+                instance,
+            )?;
+            connection.send(routing::with_request_ns(reply, msg, instance)?)?;
         }
 
         MessageCmd::GetSub => {
             let id = msg.id(instance)?;
             let mut connection = instance.get_connection(msg.conn_id)?.clone();
             let item = instance.get(id.clone())?;
-            let msg = MizeMessage::new_give(id.clone(), item.as_data_full()?, msg.conn_id);
-            connection.send(msg)?;
+            let reply = with_version(
+                MizeMessage::new_give(id.clone(), item.as_data_full()?, msg.conn_id),
+                &id,
+                instance,
+            )?;
+            connection.send(routing::with_request_ns(reply, msg, instance)?)?;
             let sub = Subscription::from_conn(connection.clone());
             instance.sub(id, sub)?;
         }
@@ -166,58 +276,170 @@ fn handle_msg(msg: &mut MizeMessage, instance: &Mize) -> MizeResult<()> {
 
         MessageCmd::Update => {
             let data = msg.data()?;
-            let id = msg.id(instance)?;
+            let id = msg.id_with_ns(instance)?;
+            // only a change to one of our own items is validated, see needed_rights in auth.rs
+            let data = match validated(msg, instance, &id, data, false)? {
+                Some(data) => data,
+                None => return Ok(()),
+            };
+            sync::saw_version(instance, &id, msg)?;
+            replica::saw_update(instance, msg)?;
             let connection = instance.get_connection(msg.conn_id)?;
-            instance
-                .op_tx
-                .send(Operation::Set(id.clone(), data, Some(connection)));
+            instance.queue_op(Operation::Set(id.clone(), data, Some(connection)))?;
         }
 
-        // this should check, if the update is valid
-        // but for now, we do just always accept it
+        // the acl was checked in auth::check_msg
         MessageCmd::UpdateRequest => {
             let data = msg.data()?;
             let id = msg.id(instance)?;
+            let data = match validated(msg, instance, &id, data, msg.replace())? {
+                Some(data) => data,
+                None => return Ok(()),
+            };
             let connection = instance.get_connection(msg.conn_id)?;
-            instance
-                .op_tx
-                .send(Operation::Set(id.clone(), data, Some(connection)));
+            let mut operation = if msg.replace() {
+                Operation::Replace(id.clone(), data, connection.clone())
+            } else {
+                Operation::Set(id.clone(), data, Some(connection.clone()))
+            };
+            if !msg.ack() {
+                return instance.queue_op(operation);
+            }
+
+            // the peer waits, so the change has to be done, before we answer
+            handle_operation(&mut operation, instance)?;
+            let item = instance.get(id.clone())?;
+            let reply = with_version(
+                MizeMessage::new_give(id.clone(), item.as_data_full()?, msg.conn_id),
+                &id,
+                instance,
+            )?;
+            connection.send(routing::with_request_ns(reply, msg, instance)?)?;
         }
 
         MessageCmd::Give => {
-            let id = msg.id(instance)?;
+            let id = msg.id_with_ns(instance)?;
             let data = msg.data()?;
-            let give_msg_wait_inner = instance.give_msg_wait.lock()?;
-            if let Some(vec) = give_msg_wait_inner.get(&id) {
-                for tx in vec {
-                    tx.send(data.clone());
-                }
-            } else {
+            // every waiter gets exactly one Give, the next get asks again
+            let waiters = take_waiters(instance, &id, msg.conn_id)?;
+            if waiters.is_empty() {
                 warn!(
-                    "got give msg for id '{}', that has no one waiting for it",
-                    id
+                    "got give msg for id '{}' from connection {}, that has no one waiting for it",
+                    id, msg.conn_id
                 );
+                return Ok(());
+            }
+            sync::saw_version(instance, &id, msg)?;
+            for tx in waiters {
+                tx.send(Ok(data.clone()));
             }
         }
 
         MessageCmd::Create => {
-            println!("instance.store: {:?}", instance.clone().store);
+            debug!("instance.store: {:?}", instance.clone().store);
             let item = instance.new_item()?;
             let reply_msg = MizeMessage::new_create_reply(item.id(), msg.conn_id);
             let mut connection = instance.get_connection(msg.conn_id)?;
-            connection.send(reply_msg)?;
+            connection.send(routing::with_request_ns(reply_msg, msg, instance)?)?;
         }
 
         MessageCmd::CreateReply => {
             let create_msg_wait_inner = instance.create_msg_wait.lock()?;
-            if let Some(sender) = create_msg_wait_inner.as_ref() {
-                sender.send(msg.id(&mut instance.clone())?)?;
+            match create_msg_wait_inner.as_ref() {
+                Some((conn_id, sender)) if *conn_id == msg.conn_id => {
+                    sender.send(Ok(msg.id_with_ns(instance)?))?;
+                }
+                _ => warn!(
+                    "got a create reply from connection {}, that we did not ask",
+                    msg.conn_id
+                ),
             }
             return Ok(());
         }
+
+        MessageCmd::Routes => routing::handle_routes_msg(msg, instance)?,
+
+        MessageCmd::Auth => auth::handle_auth_msg(msg, instance)?,
+
+        // the peer refused a request of ours
+        MessageCmd::Denied => {
+            let data = msg.data()?;
+            let reason = data.get_path("reason")?.value_string()?;
+            let err = || mize_err!("the peer denied the request: {}", reason);
+            let write = data.get_path("write")?.cbor() == &CborValue::Bool(true);
+            match msg.id_with_ns(instance) {
+                Ok(id) => {
+                    let waiters = take_waiters(instance, &id, msg.conn_id)?;
+                    // only a change, that was sent with ack, is waited for
+                    if write && waiters.is_empty() {
+                        err().log();
+                    }
+                    for tx in waiters {
+                        tx.send(Err(err()));
+                    }
+                }
+                Err(_) => match instance.create_msg_wait.lock()?.as_ref() {
+                    Some((conn_id, sender)) if *conn_id == msg.conn_id => {
+                        sender.send(Err(err()))?;
+                    }
+                    _ => {}
+                },
+            }
+        }
         _ => {
             return Err(mize_err!("got a message, that is not handeled"));
         }
     }
     Ok(())
 }
+
+// the ones waiting for a reply for id from conn_id, waiters for other connections stay
+fn take_waiters(
+    instance: &Mize,
+    id: &MizeId,
+    conn_id: u64,
+) -> MizeResult<Vec<Sender<MizeResult<ItemData>>>> {
+    let mut give_msg_wait_inner = instance.give_msg_wait.lock()?;
+    let (ours, others): (Vec<_>, Vec<_>) = give_msg_wait_inner
+        .remove(id)
+        .unwrap_or_default()
+        .into_iter()
+        .partition(|(waiting_for, _)| *waiting_for == conn_id);
+    if !others.is_empty() {
+        give_msg_wait_inner.insert(id.clone(), others);
+    }
+    Ok(ours.into_iter().map(|(_, tx)| tx).collect())
+}
+
+// None, if a validator rejected the change, the peer is then told with a Denied msg
+fn validated(
+    msg: &mut MizeMessage,
+    instance: &Mize,
+    id: &MizeId,
+    data: ItemData,
+    replace: bool,
+) -> MizeResult<Option<ItemData>> {
+    match validator::validate(instance, id, data, replace) {
+        Ok(data) => Ok(Some(data)),
+        Err(err) => {
+            let reason = err.messages.join(": ");
+            warn!("connection {}: {}", msg.conn_id, reason);
+            let reply = MizeMessage::new_denied(Some(msg.id_str()?), reason, true, msg.conn_id);
+            let connection = instance.get_connection(msg.conn_id)?;
+            connection.send(routing::with_request_ns(reply, msg, instance)?)?;
+            Ok(None)
+        }
+    }
+}
+
+// tell the asking instance, which version of our item it got
+pub(crate) fn with_version(
+    msg: MizeMessage,
+    id: &MizeId,
+    instance: &Mize,
+) -> MizeResult<MizeMessage> {
+    match sync::own_version(instance, id)? {
+        Some(version) => Ok(msg.with_version(version)),
+        None => Ok(msg),
+    }
+}
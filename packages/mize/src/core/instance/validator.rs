@@ -0,0 +1,113 @@
+use ciborium::Value as CborValue;
+use std::sync::Arc;
+
+use crate::error::{MizeError, MizeResult};
+use crate::id::{IntoMizeId, MizeId};
+use crate::item::ItemData;
+
+use super::Mize;
+
+// parts can register validators for a prefix of ids in our namespace
+// every change to an item under the prefix, or to an item above it, that writes something under
+// it (a local set or an UpdateRequest of a peer), goes through them before it is merged into the
+// store
+//
+// a validator gets the item at it's prefix (or at the changed id, if that is under the prefix) as
+// it is after the change, and returns the item to write instead, which can be rewritten, or an
+// error to reject the change
+// so it gets the same for a local set of a part of the item and for a peer, that sends the whole
+// item, and a set of a parent can't sneak something past it
+// a rejected change of a peer is answered with a Denied msg, which set_blocking() of the peer
+// returns as an error
+//
+// validators for overlapping prefixes all run, in the order they were added
+
+pub type Validator = Arc<dyn Fn(&Mize, &MizeId, ItemData) -> MizeResult<ItemData> + Send + Sync>;
+
+#[derive(Default)]
+pub struct Validators {
+    entries: Vec<(MizeId, Validator)>,
+}
+
+impl Mize {
+    pub fn add_validator<I: IntoMizeId>(
+        &self,
+        prefix: I,
+        validator: impl Fn(&Mize, &MizeId, ItemData) -> MizeResult<ItemData> + Send + Sync + 'static,
+    ) -> MizeResult<()> {
+        let prefix = prefix.to_mize_id(self)?;
+        self.validators
+            .lock()?
+            .entries
+            .push((prefix, Arc::new(validator)));
+        Ok(())
+    }
+}
+
+// the data to write to id instead of data, when a validator is under or above id, that is the
+// whole item after the change
+pub fn validate(
+    instance: &Mize,
+    id: &MizeId,
+    data: ItemData,
+    replace: bool,
+) -> MizeResult<ItemData> {
+    // a validator may use the instance, so it can't run while we hold the lock
+    let entries: Vec<(MizeId, Validator)> = instance.validators.lock()?.entries.clone();
+
+    let mut validators = Vec::new();
+    for (prefix, validator) in entries {
+        if let Some(sub_path) = sub_path(instance, &prefix, id)? {
+            // a set of a parent, that does not touch the prefix, is not it's business
+            let touched = data
+                .get_path(sub_path.clone())
+                .is_ok_and(|sub_item| sub_item.cbor() != &CborValue::Null);
+            if sub_path.is_empty() || touched {
+                validators.push((prefix, sub_path, validator));
+            }
+        }
+    }
+    if validators.is_empty() {
+        return Ok(data);
+    }
+
+    let mut merged = if replace {
+        data
+    } else {
+        let mut merged = instance
+            .get(id.clone())?
+            .as_data_full()
+            .unwrap_or_else(|_| ItemData::new());
+        merged.merge(data);
+        merged
+    };
+
+    for (prefix, sub_path, validator) in validators {
+        let rejected = |err: MizeError| err.msg(format!("the change to '{}' was rejected", id));
+        if sub_path.is_empty() {
+            merged = validator(instance, id, merged).map_err(rejected)?;
+        } else {
+            let sub_item = merged.get_path(sub_path.clone())?;
+            let sub_item = validator(instance, &prefix, sub_item).map_err(rejected)?;
+            merged.set_path(sub_path, sub_item)?;
+        }
+    }
+    Ok(merged)
+}
+
+// where the prefix is in the item id, Some(vec![]) if id is the prefix or under it, None if the
+// validator of prefix has nothing to do with id
+fn sub_path(instance: &Mize, prefix: &MizeId, id: &MizeId) -> MizeResult<Option<Vec<String>>> {
+    if !instance.is_own_namespace(&id.namespace())?
+        || !instance.is_own_namespace(&prefix.namespace())?
+    {
+        return Ok(None);
+    }
+
+    let prefix_path: Vec<String> = prefix.path().into_iter().map(|p| p.to_owned()).collect();
+    let path: Vec<String> = id.path().into_iter().map(|p| p.to_owned()).collect();
+    if !prefix_path.iter().zip(path.iter()).all(|(a, b)| a == b) {
+        return Ok(None);
+    }
+    Ok(Some(prefix_path.into_iter().skip(path.len()).collect()))
+}
@@ -0,0 +1,69 @@
+use flume::{unbounded, Sender, WeakSender};
+
+use crate::error::{MizeError, MizeResult};
+use crate::mize_err;
+
+use super::Mize;
+
+// a background thread, that handles everything sent to it one after the other (eg: the
+// "computed" and the "rules" thread)
+//
+// it is started with the first sender() and ends, when stop() drops the only Sender, which
+// shutdown() does
+// everyone else (eg: a subscription) only gets a WeakSender, so that they don't keep it running
+
+pub struct Worker<T> {
+    sender: Option<Sender<T>>,
+}
+
+impl<T> Default for Worker<T> {
+    fn default() -> Self {
+        Worker { sender: None }
+    }
+}
+
+impl<T: Send + 'static> Worker<T> {
+    pub fn sender(
+        &mut self,
+        instance: &Mize,
+        name: &str,
+        func: impl Fn(&Mize, T) -> MizeResult<()> + Send + 'static,
+    ) -> MizeResult<WeakSender<T>> {
+        if let Some(sender) = &self.sender {
+            return Ok(sender.downgrade());
+        }
+        if instance.is_stopping()? {
+            return Err(mize_err!(
+                "not starting the {} thread, the instance is stopping",
+                name
+            ));
+        }
+
+        let (tx, rx) = unbounded::<T>();
+        let weak = tx.downgrade();
+        self.sender = Some(tx);
+
+        let instance_clone = instance.clone();
+        instance.clone().spawn_background(name, move || {
+            for item in rx.iter() {
+                if let Err(err) = func(&instance_clone, item) {
+                    instance_clone.report_err(err);
+                }
+            }
+            Ok(())
+        })?;
+        Ok(weak)
+    }
+
+    pub fn stop(&mut self) {
+        self.sender = None;
+    }
+}
+
+// send to a worker, what it gets, after it stopped, is dropped
+pub fn send<T>(sender: &WeakSender<T>, item: T) -> MizeResult<()> {
+    match sender.upgrade() {
+        Some(sender) => Ok(sender.send(item)?),
+        None => Ok(()),
+    }
+}
@@ -1,5 +1,6 @@
 use colored::Colorize;
 use core::fmt;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 use std::fmt::Debug;
@@ -13,12 +14,13 @@ use tracing::{span, Level};
 
 use crate::error::{IntoMizeResult, MizeError, MizeResult};
 use crate::id::MizeId;
-use crate::instance::connection::value_raw_con_by_id;
 use crate::instance::store::Store;
-use crate::instance::{connection, Mize};
+use crate::instance::{provider, replica, sync, Mize};
 use crate::mize_err;
 use crate::proto::MizeMessage;
+use crate::types::crdt;
 use ciborium::Value as CborValue;
+use flume::Receiver;
 
 // a item always has to do with a Instance, which takes care of how it is updated
 #[derive(Debug, Clone)]
@@ -54,72 +56,46 @@ impl Item<'_> {
     }
 
     pub fn as_data_full(&self) -> MizeResult<ItemData> {
-        let id = self.id();
+        match self.data_or_request()? {
+            Ok(data) => Ok(data),
+            Err(rx) => rx.recv()?,
+        }
+    }
 
-        // the instance we are
-        if id.store_part() == "self" {
-            debug!("getting item '{}' from self", self.id());
+    // the same, but waiting for the owner doesn't block the thread
+    pub async fn as_data_full_async(&self) -> MizeResult<ItemData> {
+        match self.data_or_request()? {
+            Ok(data) => Ok(data),
+            Err(rx) => rx.recv_async().await?,
+        }
+    }
 
-            match id.nth_part(1)? {
-                "con_by_id" => {
-                    return value_raw_con_by_id(&mut self.clone());
-                }
-                "namespace" => {
-                    let namespace_inner = self.instance.namespace.lock()?;
-                    return Ok(ItemData::from_string(namespace_inner.as_real_string()));
-                }
-                "self_namespace" => {
-                    let namespace_inner = self.instance.self_namespace.lock()?;
-                    return Ok(ItemData::from_string(namespace_inner.as_real_string()));
-                }
-                "config" => {
-                    let rest_path = id
-                        .path
-                        .into_iter()
-                        .skip(2)
-                        .map(|v| v.to_owned())
-                        .collect::<Vec<String>>()
-                        .join("/");
-                    let id_for_store = self
-                        .instance
-                        .new_id("0/config".to_owned() + "/" + rest_path.as_str())?;
-                    let store = self.instance.store.lock()?;
-                    let data = store.get_value_data_full(id_for_store)?;
-                    return Ok(data);
-                }
-                _ => {}
-            }
-            return Err(mize_err!(
-                "a /self path, but the next element in the path '{:?}' is not valid",
-                id.nth_part(1)
-            ));
+    // the data, if we have it, otherwise a Get msg is sent to the owner and the receiver gets the
+    // data from their Give msg
+    fn data_or_request(&self) -> MizeResult<Result<ItemData, Receiver<MizeResult<ItemData>>>> {
+        let id = self.id();
+
+        // made up by code, eg: inst/* and self/* (see provider.rs)
+        if let Some(provider) = provider::provider_for(self.instance, &id)? {
+            debug!("getting item '{}' from a provider", id);
+            return Ok(Ok(provider.get(self.instance, &id)?));
         }
 
-        let self_namespace_inner = self.instance.self_namespace.lock()?;
+        // don't hold the lock, while we might wait for a peer
+        let self_namespace = self.instance.get_self_namespace()?;
 
-        if self.id().namespace() == self_namespace_inner.to_owned() {
+        if self.id().namespace() == self_namespace {
             debug!("getting item '{}' from store", self.id());
 
-            if id.store_part() == "inst" {
-                match id.nth_part(1)? {
-                    "con_by_id" => {
-                        return value_raw_con_by_id(&mut self.clone());
-                    }
-                    "namespace" => {
-                        let namespace_inner = self.instance.namespace.lock()?;
-                        return Ok(ItemData::from_string(namespace_inner.as_real_string()));
-                    }
-                    "self_namespace" => {
-                        let namespace_inner = self.instance.self_namespace.lock()?;
-                        return Ok(ItemData::from_string(namespace_inner.as_real_string()));
-                    }
-                    _ => {}
-                }
-            }
-
             let store_inner = self.instance.store.lock()?;
-            return store_inner.get_value_data_full(self.id());
+            return Ok(Ok(store_inner.get_value_data_full(self.id())?));
         } else {
+            if replica::serves(self.instance, &id)? {
+                debug!("getting item '{}' from our replica", self.id());
+                let store_inner = self.instance.store.lock()?;
+                return Ok(Ok(store_inner.get_value_data_full(self.id())?));
+            }
+
             let mut connection = self.instance.get_connection_by_ns(self.id().namespace())?;
 
             debug!(
@@ -128,49 +104,67 @@ impl Item<'_> {
                 connection.id
             );
 
-            let msg = MizeMessage::new_get(self.id(), connection.id);
-            connection.send(msg);
-            let data = self.instance.give_msg_wait(self.id())?;
-            return Ok(data);
+            let msg =
+                MizeMessage::new_get(self.id(), connection.id).with_ns(&self.id().namespace());
+            let rx = self
+                .instance
+                .give_msg_receiver(self.id(), connection.id, move || connection.send(msg))?;
+            return Ok(Err(rx));
         }
     }
 
     #[instrument(name = "fn.ItemData::merge")]
-    pub fn merge<V: Into<ItemData> + Debug>(&mut self, mut value: V) -> MizeResult<()> {
+    pub fn merge<V: Into<ItemData> + Debug>(&mut self, value: V) -> MizeResult<()> {
+        self.merge_inner(value.into(), false)
+    }
+
+    // like merge(), but a change to an item of another namespace waits for it's owner, so that a
+    // rejection of the change is returned
+    pub fn merge_acked<V: Into<ItemData> + Debug>(&mut self, value: V) -> MizeResult<()> {
+        self.merge_inner(value.into(), true)
+    }
+
+    fn merge_inner(&mut self, value: ItemData, ack: bool) -> MizeResult<()> {
+        let id = self.id();
+        if let Some(provider) = provider::provider_for(self.instance, &id)? {
+            return provider.set(self.instance, &id, value.into());
+        }
+
+        // we can't reach the owner of the item, so the write waits in the outbox
+        if sync::must_queue(self.instance, &self.id())? {
+            return sync::queue_write(self.instance, &self.id(), value.into());
+        }
+
         let mut data = self.as_data_full()?;
         //let mut data = data_full.get_path(id_path_without_store_part)?;
         trace!("item::merge data: {:?}", data);
         trace!("item::merge id: {:?}", self.id());
 
-        let new_data = value.into();
-
-        data.merge(new_data);
+        data.merge(value);
         trace!("item::merge new_data: {:?}", data);
 
-        // handle the case of /self/*
-        if self.id().store_part() == "self" {
-            let rest_path = self
-                .id()
-                .path
-                .into_iter()
-                .skip(1)
-                .map(|v| v.to_owned())
-                .collect::<Vec<String>>()
-                .join("/");
-            let id_for_store = self
-                .instance
-                .new_id("0".to_owned() + "/" + rest_path.as_str())?;
-
-            let store_inner = self.instance.store.lock()?;
-            store_inner.set(id_for_store, data)?;
-        } else if self.instance.we_are_namespace()? {
+        if self.id().namespace() == self.instance.get_self_namespace()? {
             let store_inner = self.instance.store.lock()?;
             store_inner.set(self.id(), data)?;
         } else {
             let namespace = self.id().namespace();
-            let mut connection = self.instance.get_connection_by_ns(namespace)?;
-            let msg = MizeMessage::new_update_request(self.id(), data, connection.id);
-            connection.send(msg)?;
+            let mut connection = self.instance.get_connection_by_ns(namespace.clone())?;
+            let msg = MizeMessage::new_update_request(self.id(), data.clone(), connection.id)
+                .with_ns(&namespace);
+
+            // the owner answers with the changed item or a Denied msg
+            if ack {
+                let conn_id = connection.id;
+                let msg = msg.with_ack();
+                data = self
+                    .instance
+                    .give_msg_wait(self.id(), conn_id, move || connection.send(msg))?;
+            } else {
+                connection.send(msg)?;
+            }
+
+            // the owner does not send our own write back to us
+            replica::mirror(self.instance, &self.id(), &data)?;
         }
 
         Ok(())
@@ -212,6 +206,26 @@ impl ItemData {
         }
     }
 
+    // a text value is also parsed (eg: "10" for a number), because values from the command line or
+    // env vars are text
+    pub fn value_as<T: DeserializeOwned>(&self) -> MizeResult<T> {
+        let err = match self.0.deserialized::<T>() {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        if let CborValue::Text(text) = &self.0 {
+            if let Ok(value) = ItemData::parse(text.as_str()).0.deserialized::<T>() {
+                return Ok(value);
+            }
+        }
+        Err(mize_err!(
+            "{} is not a {}: {}",
+            self,
+            std::any::type_name::<T>(),
+            err
+        ))
+    }
+
     pub fn from_string<S: Into<String>>(into_string: S) -> ItemData {
         let string = into_string.into();
         let data = CborValue::Text(string);
@@ -263,7 +277,7 @@ impl ItemData {
         if value_str == "false" {
             return CborValue::Bool(false).into_item_data();
         }
-        if value_str == "false" {
+        if value_str == "true" {
             return CborValue::Bool(true).into_item_data();
         }
         if let Ok(int) = value_str.parse::<i128>() {
@@ -517,6 +531,11 @@ pub fn item_data_set_path(
 }
 
 pub fn item_data_merge(merge_into: &mut CborValue, other: &CborValue) {
+    // crdt values are joined, which gives the same result in any order
+    if crdt::merge_cbor(merge_into, other) {
+        return;
+    }
+
     // needs to be recursive
 
     match (merge_into, other) {
@@ -19,13 +19,13 @@ macro_rules! console_log {
 
 #[macro_export]
 macro_rules! add_parts {
-    ($mize:expr, $($part:expr),+) => {
+    ($mize:expr, $($part:ty),+) => {
         $(
-          let part = $part.create($mize.clone());
+          let part = <$part as $crate::MizePartCreateGenerated>::create_generated($mize.clone());
           $mize.add_part(
-                part
-            );
+                Box::new(part)
+            )?;
         )+
-        mize.init_parts();
+        $mize.init_parts()?;
     };
 }
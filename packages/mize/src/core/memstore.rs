@@ -1,8 +1,9 @@
 use ciborium::Value as CborValue;
 use colored::Colorize;
 use std::collections::binary_heap::Iter;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::iter::Map;
+use std::ops::Bound::{Excluded, Unbounded};
 use std::sync::{Arc, Mutex};
 use tracing::{instrument, trace};
 
@@ -23,8 +24,11 @@ pub type MemStoreId = u64;
 
 #[derive(Debug)]
 struct MemStoreInner {
-    map: HashMap<u64, ItemData>,
-    next_id: u64,
+    // ordered, so that next_id() does not have to look at every key
+    map: BTreeMap<u64, ItemData>,
+    versions: HashMap<u64, u64>,
+    // the next id of every range, the ids of the range 0.. are handed out by new_id()
+    next_ids: HashMap<u64, u64>,
 }
 
 impl Store for MemStore {
@@ -36,14 +40,20 @@ impl Store for MemStore {
             let mut old_data = old_data.to_owned();
             let path = id.after_store_part();
             old_data.set_path(path, data)?;
-            inner.map.insert(id_to_u64(id)?, old_data);
+            inner.map.insert(id_to_u64(id.clone())?, old_data);
         } else {
             // if no data exists for that store_part, just insert
-            inner.map.insert(id_to_u64(id)?, data);
+            inner.map.insert(id_to_u64(id.clone())?, data);
         }
 
+        *inner.versions.entry(id_to_u64(id)?).or_insert(0) += 1;
+
         return Ok(());
     }
+    fn get_version(&self, id: MizeId) -> MizeResult<u64> {
+        let inner = self.inner.lock()?;
+        Ok(inner.versions.get(&id_to_u64(id)?).copied().unwrap_or(0))
+    }
     fn get_links(&self, item: Item) -> MizeResult<Vec<MizeId>> {
         let inner = self.inner.lock()?;
 
@@ -55,11 +65,33 @@ impl Store for MemStore {
         Ok(Vec::new())
     }
     fn new_id(&self) -> MizeResult<String> {
+        self.new_id_in(0, u64::MAX)?
+            .ok_or(mize_err!("the MemStore has no ids left"))
+    }
+
+    fn new_id_in(&self, from: u64, to: u64) -> MizeResult<Option<String>> {
+        if from >= to {
+            return Ok(None);
+        }
         let mut inner = self.inner.lock()?;
 
-        let id_string = format!("{}", inner.next_id);
-        inner.next_id += 1;
-        return Ok(id_string);
+        let id = match inner.next_ids.get(&from) {
+            Some(id) => *id,
+            None => inner
+                .map
+                .range(from..to)
+                .next_back()
+                .map_or(from, |(id, _)| id + 1),
+        };
+        if id >= to {
+            return Ok(None);
+        }
+
+        // a new item exists, with empty data
+        inner.map.insert(id, ItemData::new());
+
+        inner.next_ids.insert(from, id + 1);
+        return Ok(Some(format!("{}", id)));
     }
 
     #[instrument(name="fn.MemStore::get_value_raw" skip(self))]
@@ -104,23 +136,16 @@ impl Store for MemStore {
     fn next_id(&self, prev_id_str: &str) -> MizeResult<Option<String>> {
         let inner = self.inner.lock()?;
 
-        let mut id = str_to_u64(prev_id_str)?;
-
-        loop {
-            id += 1;
+        let prev_id = str_to_u64(prev_id_str)?;
 
-            if id > inner.next_id {
-                return Ok(None);
-            }
-
-            if inner.map.contains_key(&id) {
-                println!("returning: {}", id);
-                return Ok(Some(format!("{}", id)));
-            } else {
-                // try id +1
-                continue;
-            }
-        }
+        // ids can be anywhere, when the store is mounted for a range
+        let next = inner
+            .map
+            .range((Excluded(prev_id), Unbounded))
+            .next()
+            .map(|(id, _)| id);
+        trace!("returning: {:?}", next);
+        Ok(next.map(|id| format!("{}", id)))
     }
 
     fn first_id(&self) -> MizeResult<String> {
@@ -131,8 +156,10 @@ impl Store for MemStore {
 impl MemStore {
     pub fn new() -> MemStore {
         let inner = MemStoreInner {
-            map: HashMap::new(),
-            next_id: 0,
+            map: BTreeMap::new(),
+            versions: HashMap::new(),
+            // 0 is the instance item itself, just like in the FileStore
+            next_ids: HashMap::from([(0, 1)]),
         };
         return MemStore {
             inner: Arc::new(Mutex::new(inner)),
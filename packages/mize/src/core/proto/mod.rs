@@ -6,7 +6,7 @@ use ciborium::{value::Integer, Value as CborValue};
 
 use crate::{
     error::{IntoMizeResult, MizeError, MizeResult},
-    id::MizeId,
+    id::{MizeId, Namespace},
     instance::{self, connection::Connection, Mize},
     item::{IntoItemData, ItemData},
 };
@@ -21,6 +21,20 @@ pub struct MizeMessage {
 static MSG_CMD: u16 = 1;
 static MSG_ID: u16 = 2;
 static MSG_DATA: u16 = 3;
+// optional, for routing through other instances
+static MSG_NS: u16 = 4;
+static MSG_TTL: u16 = 5;
+static MSG_VIA: u16 = 6;
+static MSG_SEQ: u16 = 7;
+// the version of the item, a Give or Update carries
+static MSG_VERSION: u16 = 8;
+// when an Update was sent (unix time in ms)
+static MSG_TIME: u16 = 9;
+// an UpdateRequest, whose data replaces the item instead of being merged into it
+static MSG_REPLACE: u16 = 10;
+// an UpdateRequest, that the sender waits for, it is answered with a Give of the changed item or
+// a Denied msg
+static MSG_ACK: u16 = 11;
 
 // cmds
 static CMD_GET: u16 = 1;
@@ -31,6 +45,9 @@ static CMD_CREATE_REPLY: u16 = 5;
 static CMD_UPDATE_REQUEST: u16 = 6;
 static CMD_GET_SUB: u16 = 7;
 static CMD_SUB: u16 = 8;
+static CMD_ROUTES: u16 = 9;
+static CMD_AUTH: u16 = 10;
+static CMD_DENIED: u16 = 11;
 
 #[derive(Debug)]
 pub enum MessageCmd {
@@ -42,6 +59,9 @@ pub enum MessageCmd {
     UpdateRequest,
     GetSub,
     Sub,
+    Routes,
+    Auth,
+    Denied,
 }
 
 impl MizeMessage {
@@ -181,10 +201,232 @@ impl MizeMessage {
         MizeMessage::new(value, conn_id)
     }
 
+    // data is a map of the namespaces the sender can reach to the number of hops it needs
+    pub fn new_routes(routes: Vec<(String, u64)>, conn_id: u64) -> MizeMessage {
+        let routes_map = routes
+            .into_iter()
+            .map(|(ns, hops)| (CborValue::Text(ns), CborValue::Integer(hops.into())))
+            .collect();
+
+        let cmd = (
+            CborValue::Integer(MSG_CMD.into()),
+            CborValue::Integer(CMD_ROUTES.into()),
+        );
+        let data = (
+            CborValue::Integer(MSG_DATA.into()),
+            CborValue::Map(routes_map),
+        );
+        let value = CborValue::Map(vec![cmd, data]);
+
+        MizeMessage::new(value, conn_id)
+    }
+
+    // the auth handshake, data is a map with either the nonce of a challenge or the key and the
+    // proof of the answer
+    pub fn new_auth(data: CborValue, conn_id: u64) -> MizeMessage {
+        let cmd = (
+            CborValue::Integer(MSG_CMD.into()),
+            CborValue::Integer(CMD_AUTH.into()),
+        );
+        let data = (CborValue::Integer(MSG_DATA.into()), data);
+        let value = CborValue::Map(vec![cmd, data]);
+
+        MizeMessage::new(value, conn_id)
+    }
+
+    // the reply to a request, that the acl or a validator does not allow, id is None for a Create
+    // write tells, if the request was a change, no one waits for a reply to those
+    pub fn new_denied(
+        id: Option<Vec<String>>,
+        reason: String,
+        write: bool,
+        conn_id: u64,
+    ) -> MizeMessage {
+        let cmd = (
+            CborValue::Integer(MSG_CMD.into()),
+            CborValue::Integer(CMD_DENIED.into()),
+        );
+        let data = (
+            CborValue::Integer(MSG_DATA.into()),
+            CborValue::Map(vec![
+                (
+                    CborValue::Text("reason".to_owned()),
+                    CborValue::Text(reason),
+                ),
+                (CborValue::Text("write".to_owned()), CborValue::Bool(write)),
+            ]),
+        );
+        let mut fields = vec![cmd, data];
+        if let Some(id_path) = id {
+            let id_path = id_path.into_iter().map(CborValue::Text).collect();
+            fields.push((CborValue::Integer(MSG_ID.into()), CborValue::Array(id_path)));
+        }
+
+        MizeMessage::new(CborValue::Map(fields), conn_id)
+    }
+
     pub fn value(self) -> CborValue {
         self.value
     }
 
+    fn field(&self, key: u16) -> Option<&CborValue> {
+        let msg_as_map = match &self.value {
+            CborValue::Map(val) => val,
+            _ => return None,
+        };
+
+        let key: Integer = key.into();
+        for (map_key, val) in msg_as_map {
+            if let CborValue::Integer(key_int) = map_key {
+                if key_int == &key {
+                    return Some(val);
+                }
+            }
+        }
+        None
+    }
+
+    fn set_field(&mut self, key: u16, new_val: CborValue) {
+        let msg_as_map = match &mut self.value {
+            CborValue::Map(val) => val,
+            _ => return,
+        };
+
+        let key_int: Integer = key.into();
+        for (map_key, val) in msg_as_map.iter_mut() {
+            if let CborValue::Integer(map_key_int) = map_key {
+                if map_key_int == &key_int {
+                    *val = new_val;
+                    return;
+                }
+            }
+        }
+        msg_as_map.push((CborValue::Integer(key.into()), new_val));
+    }
+
+    // the namespace the msg is addressed to (for requests) or comes from (for replies)
+    pub fn ns(&self) -> Option<String> {
+        match self.field(MSG_NS) {
+            Some(CborValue::Text(ns)) => Some(ns.to_owned()),
+            _ => None,
+        }
+    }
+
+    pub fn with_ns(mut self, ns: &Namespace) -> MizeMessage {
+        self.set_field(MSG_NS, CborValue::Text(ns.as_real_string()));
+        self
+    }
+
+    pub fn ttl(&self) -> Option<u64> {
+        match self.field(MSG_TTL) {
+            Some(CborValue::Integer(ttl)) => u64::try_from(*ttl).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: u64) -> MizeMessage {
+        self.set_field(MSG_TTL, CborValue::Integer(ttl.into()));
+        self
+    }
+
+    // the instances, that already forwarded this msg
+    pub fn via(&self) -> Vec<String> {
+        match self.field(MSG_VIA) {
+            Some(CborValue::Array(vec)) => vec
+                .iter()
+                .filter_map(|val| match val {
+                    CborValue::Text(text) => Some(text.to_owned()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn with_via(mut self, via: Vec<String>) -> MizeMessage {
+        let via = via.into_iter().map(CborValue::Text).collect();
+        self.set_field(MSG_VIA, CborValue::Array(via));
+        self
+    }
+
+    // routes msgs are numbered, so that an older table handled after a newer one can be ignored
+    pub fn seq(&self) -> Option<u64> {
+        match self.field(MSG_SEQ) {
+            Some(CborValue::Integer(seq)) => u64::try_from(*seq).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn with_seq(mut self, seq: u64) -> MizeMessage {
+        self.set_field(MSG_SEQ, CborValue::Integer(seq.into()));
+        self
+    }
+
+    pub fn version(&self) -> Option<u64> {
+        match self.field(MSG_VERSION) {
+            Some(CborValue::Integer(version)) => u64::try_from(*version).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn with_version(mut self, version: u64) -> MizeMessage {
+        self.set_field(MSG_VERSION, CborValue::Integer(version.into()));
+        self
+    }
+
+    pub fn replace(&self) -> bool {
+        self.field(MSG_REPLACE) == Some(&CborValue::Bool(true))
+    }
+
+    pub fn with_replace(mut self) -> MizeMessage {
+        self.set_field(MSG_REPLACE, CborValue::Bool(true));
+        self
+    }
+
+    pub fn ack(&self) -> bool {
+        self.field(MSG_ACK) == Some(&CborValue::Bool(true))
+    }
+
+    pub fn with_ack(mut self) -> MizeMessage {
+        self.set_field(MSG_ACK, CborValue::Bool(true));
+        self
+    }
+
+    pub fn time(&self) -> Option<u64> {
+        match self.field(MSG_TIME) {
+            Some(CborValue::Integer(time)) => u64::try_from(*time).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn with_time(mut self, time: u64) -> MizeMessage {
+        self.set_field(MSG_TIME, CborValue::Integer(time.into()));
+        self
+    }
+
+    pub fn routes(&self) -> MizeResult<Vec<(String, u64)>> {
+        let routes_map = match self.field(MSG_DATA) {
+            Some(CborValue::Map(map)) => map,
+            _ => {
+                return Err(MizeError::new().msg("routes msg has no map as data"));
+            }
+        };
+
+        let mut routes = Vec::new();
+        for (key, val) in routes_map {
+            match (key, val) {
+                (CborValue::Text(ns), CborValue::Integer(hops)) => {
+                    routes.push((ns.to_owned(), u64::try_from(*hops)?));
+                }
+                _ => {
+                    return Err(MizeError::new().msg("routes msg has an invalid entry"));
+                }
+            }
+        }
+
+        Ok(routes)
+    }
+
     pub fn cmd(&self) -> MizeResult<MessageCmd> {
         // return err, if msg is not a map
         let msg_as_map = match &self.value {
@@ -224,6 +466,9 @@ impl MizeMessage {
             6 => MessageCmd::UpdateRequest,
             7 => MessageCmd::GetSub,
             8 => MessageCmd::Sub,
+            9 => MessageCmd::Routes,
+            10 => MessageCmd::Auth,
+            11 => MessageCmd::Denied,
             _ => {
                 return Err(MizeError::new().msg("error cmd of msg was not a valid command"));
             }
@@ -282,6 +527,15 @@ impl MizeMessage {
         return instance.new_id(id_str);
     }
 
+    // for replies, the id is in the namespace the reply came from (if the msg tells us)
+    pub fn id_with_ns(&mut self, instance: &Mize) -> MizeResult<MizeId> {
+        let mut id_str = self.id_str()?;
+        if let (Some(ns), Some(first)) = (self.ns(), id_str.first_mut()) {
+            *first = format!("{}:{}", ns, first);
+        }
+        return instance.new_id(id_str);
+    }
+
     pub fn data(&mut self) -> MizeResult<ItemData> {
         // return err, if msg is not a map
         let msg_as_map = match &self.value {
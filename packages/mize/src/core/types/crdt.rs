@@ -0,0 +1,657 @@
+use ciborium::Value as CborValue;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use tracing::warn;
+
+use crate::error::{MizeError, MizeResult};
+use crate::item::ItemData;
+use crate::mize_err;
+
+// crdt values for item data, that is edited from many instances at once
+//
+// they are cbor values with one of the tags below, so they can be anywhere in the data of an item
+// item_data_merge joins two crdt values of the same kind instead of overwriting one with the other
+// and as a join is commutative, associative and idempotent, every instance ends up with the same
+// value, no matter in which order the Update and UpdateRequest msgs arrive
+//
+// every edit needs the id of the replica (the instance) making it, see Mize::replica_id()
+
+// from the first come first served range of cbor tags
+pub static TAG_COUNTER: u64 = 48001;
+pub static TAG_MAP: u64 = 48002;
+pub static TAG_TEXT: u64 = 48003;
+
+// orders edits, the lamport clock first and the replica to break ties
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Stamp {
+    pub clock: u64,
+    pub replica: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Crdt {
+    Counter(Counter),
+    Map(CrdtMap),
+    Text(Text),
+}
+
+impl Crdt {
+    pub fn from_cbor(value: &CborValue) -> MizeResult<Option<Crdt>> {
+        let (tag, inner) = match value {
+            CborValue::Tag(tag, inner) => (*tag, inner.as_ref()),
+            _ => return Ok(None),
+        };
+
+        if tag == TAG_COUNTER {
+            Ok(Some(Crdt::Counter(Counter::from_inner(inner)?)))
+        } else if tag == TAG_MAP {
+            Ok(Some(Crdt::Map(CrdtMap::from_inner(inner)?)))
+        } else if tag == TAG_TEXT {
+            Ok(Some(Crdt::Text(Text::from_inner(inner)?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn to_cbor(&self) -> CborValue {
+        match self {
+            Crdt::Counter(counter) => counter.to_cbor(),
+            Crdt::Map(map) => map.to_cbor(),
+            Crdt::Text(text) => text.to_cbor(),
+        }
+    }
+
+    // false, if they are not of the same kind
+    pub fn merge(&mut self, other: &Crdt) -> bool {
+        match (self, other) {
+            (Crdt::Counter(a), Crdt::Counter(b)) => a.merge(b),
+            (Crdt::Map(a), Crdt::Map(b)) => a.merge(b),
+            (Crdt::Text(a), Crdt::Text(b)) => a.merge(b),
+            _ => return false,
+        }
+        true
+    }
+
+    // the plain value, without any of the crdt bookkeeping
+    pub fn value(&self) -> ItemData {
+        match self {
+            Crdt::Counter(counter) => ItemData::from_cbor(counter.value().into()),
+            Crdt::Map(map) => map.value(),
+            Crdt::Text(text) => ItemData::from_string(text.value()),
+        }
+    }
+}
+
+// called by item_data_merge
+// returns false, if the two values are not crdts of the same kind and have to be merged normally
+pub fn merge_cbor(merge_into: &mut CborValue, other: &CborValue) -> bool {
+    let (mut into_crdt, other_crdt) = match (Crdt::from_cbor(merge_into), Crdt::from_cbor(other)) {
+        (Ok(Some(a)), Ok(Some(b))) => (a, b),
+        (Err(err), _) | (_, Err(err)) => {
+            warn!(
+                "invalid crdt value, merging it as plain data: {:?}",
+                err.messages
+            );
+            return false;
+        }
+        _ => return false,
+    };
+
+    if !into_crdt.merge(&other_crdt) {
+        return false;
+    }
+    *merge_into = into_crdt.to_cbor();
+    true
+}
+
+pub fn contains_crdt(value: &CborValue) -> bool {
+    match value {
+        CborValue::Tag(tag, _) => [TAG_COUNTER, TAG_MAP, TAG_TEXT].contains(tag),
+        CborValue::Map(map) => map.iter().any(|(_, val)| contains_crdt(val)),
+        CborValue::Array(vec) => vec.iter().any(contains_crdt),
+        _ => false,
+    }
+}
+
+// the plain value of data, where every crdt (also nested ones) is replaced by it's value
+pub fn plain_value(data: &ItemData) -> ItemData {
+    ItemData::from_cbor(plain_cbor(data.cbor()))
+}
+
+fn plain_cbor(value: &CborValue) -> CborValue {
+    if let Ok(Some(crdt)) = Crdt::from_cbor(value) {
+        return plain_cbor(crdt.value().cbor());
+    }
+    match value {
+        CborValue::Map(map) => CborValue::Map(
+            map.iter()
+                .map(|(key, val)| (key.to_owned(), plain_cbor(val)))
+                .collect(),
+        ),
+        CborValue::Array(vec) => CborValue::Array(vec.iter().map(plain_cbor).collect()),
+        other => other.to_owned(),
+    }
+}
+
+////// counter
+
+// a counter, that can be incremented and decremented
+// every replica only ever grows it's own two numbers, the value is the difference of the sums
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Counter {
+    counts: BTreeMap<String, (u64, u64)>,
+}
+
+impl Counter {
+    pub fn new() -> Counter {
+        Counter::default()
+    }
+
+    pub fn from_data(data: &ItemData) -> MizeResult<Counter> {
+        match Crdt::from_cbor(data.cbor())? {
+            Some(Crdt::Counter(counter)) => Ok(counter),
+            None if data.cbor().is_null() => Ok(Counter::new()),
+            _ => Err(mize_err!("item data is not a crdt counter: {}", data)),
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        self.counts
+            .values()
+            .map(|(inc, dec)| *inc as i64 - *dec as i64)
+            .sum()
+    }
+
+    pub fn increment(&mut self, replica: &str, by: u64) {
+        self.counts.entry(replica.to_owned()).or_insert((0, 0)).0 += by;
+    }
+
+    pub fn decrement(&mut self, replica: &str, by: u64) {
+        self.counts.entry(replica.to_owned()).or_insert((0, 0)).1 += by;
+    }
+
+    pub fn merge(&mut self, other: &Counter) {
+        for (replica, (inc, dec)) in other.counts.iter() {
+            let counts = self.counts.entry(replica.to_owned()).or_insert((0, 0));
+            counts.0 = counts.0.max(*inc);
+            counts.1 = counts.1.max(*dec);
+        }
+    }
+
+    fn from_inner(inner: &CborValue) -> MizeResult<Counter> {
+        let mut counter = Counter::new();
+        for (replica, counts) in cbor_map(inner, "counter")? {
+            let counts = cbor_array(counts, "counter entry")?;
+            counter.counts.insert(
+                cbor_text(replica)?,
+                (cbor_u64(counts.first())?, cbor_u64(counts.get(1))?),
+            );
+        }
+        Ok(counter)
+    }
+
+    fn to_cbor(&self) -> CborValue {
+        let map = self
+            .counts
+            .iter()
+            .map(|(replica, (inc, dec))| {
+                (
+                    CborValue::Text(replica.to_owned()),
+                    CborValue::Array(vec![(*inc).into(), (*dec).into()]),
+                )
+            })
+            .collect();
+        CborValue::Tag(TAG_COUNTER, Box::new(CborValue::Map(map)))
+    }
+}
+
+////// map
+
+// a map, where every key is set by the last writer (by Stamp)
+// if both sides have a crdt of the same kind at a key, they are merged instead
+// removed keys stay as a Null value, so that an older set can't bring them back
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CrdtMap {
+    entries: BTreeMap<String, (Stamp, CborValue)>,
+}
+
+impl CrdtMap {
+    pub fn new() -> CrdtMap {
+        CrdtMap::default()
+    }
+
+    pub fn from_data(data: &ItemData) -> MizeResult<CrdtMap> {
+        match Crdt::from_cbor(data.cbor())? {
+            Some(Crdt::Map(map)) => Ok(map),
+            None if data.cbor().is_null() => Ok(CrdtMap::new()),
+            _ => Err(mize_err!("item data is not a crdt map: {}", data)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<ItemData> {
+        match self.entries.get(key) {
+            Some((_, CborValue::Null)) | None => None,
+            Some((_, value)) => Some(ItemData::from_cbor(value.to_owned())),
+        }
+    }
+
+    pub fn set<V: Into<ItemData>>(&mut self, replica: &str, key: &str, value: V) {
+        let stamp = self.next_stamp(replica);
+        self.entries
+            .insert(key.to_owned(), (stamp, value.into().cbor().to_owned()));
+    }
+
+    pub fn remove(&mut self, replica: &str, key: &str) {
+        let stamp = self.next_stamp(replica);
+        self.entries
+            .insert(key.to_owned(), (stamp, CborValue::Null));
+    }
+
+    pub fn value(&self) -> ItemData {
+        let map = self
+            .entries
+            .iter()
+            .filter(|(_, (_, value))| !value.is_null())
+            .map(|(key, (_, value))| (CborValue::Text(key.to_owned()), plain_cbor(value)))
+            .collect();
+        ItemData::from_cbor(CborValue::Map(map))
+    }
+
+    pub fn merge(&mut self, other: &CrdtMap) {
+        for (key, (other_stamp, other_value)) in other.entries.iter() {
+            let (stamp, value) = match self.entries.get_mut(key) {
+                Some(entry) => entry,
+                None => {
+                    self.entries
+                        .insert(key.to_owned(), (other_stamp.clone(), other_value.clone()));
+                    continue;
+                }
+            };
+
+            // nested crdts keep the edits of both sides
+            if !value.is_null() && !other_value.is_null() && merge_cbor(value, other_value) {
+                if other_stamp > stamp {
+                    *stamp = other_stamp.clone();
+                }
+                continue;
+            }
+
+            if other_stamp > stamp {
+                *stamp = other_stamp.clone();
+                *value = other_value.clone();
+            }
+        }
+    }
+
+    fn next_stamp(&self, replica: &str) -> Stamp {
+        let clock = self
+            .entries
+            .values()
+            .map(|(stamp, _)| stamp.clock)
+            .max()
+            .unwrap_or(0);
+        Stamp {
+            clock: clock + 1,
+            replica: replica.to_owned(),
+        }
+    }
+
+    fn from_inner(inner: &CborValue) -> MizeResult<CrdtMap> {
+        let mut map = CrdtMap::new();
+        for (key, entry) in cbor_map(inner, "map")? {
+            let entry = cbor_array(entry, "map entry")?;
+            let stamp = Stamp {
+                clock: cbor_u64(entry.first())?,
+                replica: cbor_text(entry.get(1).unwrap_or(&CborValue::Null))?,
+            };
+            let value = entry.get(2).cloned().unwrap_or(CborValue::Null);
+            map.entries.insert(cbor_text(key)?, (stamp, value));
+        }
+        Ok(map)
+    }
+
+    fn to_cbor(&self) -> CborValue {
+        let map = self
+            .entries
+            .iter()
+            .map(|(key, (stamp, value))| {
+                (
+                    CborValue::Text(key.to_owned()),
+                    CborValue::Array(vec![
+                        stamp.clock.into(),
+                        CborValue::Text(stamp.replica.to_owned()),
+                        value.to_owned(),
+                    ]),
+                )
+            })
+            .collect();
+        CborValue::Tag(TAG_MAP, Box::new(CborValue::Map(map)))
+    }
+}
+
+////// text
+
+// a replicated growable array of chars (RGA)
+// every char knows the char it was inserted after, chars inserted after the same one are ordered
+// newest first, deleted chars stay as tombstones
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Text {
+    chars: BTreeMap<Stamp, TextChar>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct TextChar {
+    after: Option<Stamp>,
+    ch: char,
+    deleted: bool,
+}
+
+impl Text {
+    pub fn new() -> Text {
+        Text::default()
+    }
+
+    pub fn from_data(data: &ItemData) -> MizeResult<Text> {
+        match Crdt::from_cbor(data.cbor())? {
+            Some(Crdt::Text(text)) => Ok(text),
+            None if data.cbor().is_null() => Ok(Text::new()),
+            _ => Err(mize_err!("item data is not a crdt text: {}", data)),
+        }
+    }
+
+    pub fn value(&self) -> String {
+        self.visible().iter().map(|(_, ch)| *ch).collect()
+    }
+
+    // index and len count chars, not bytes
+    pub fn insert(&mut self, replica: &str, index: usize, string: &str) {
+        let visible = self.visible();
+        let mut after = match index {
+            0 => None,
+            _ => visible
+                .get(index.min(visible.len()) - 1)
+                .map(|(stamp, _)| stamp.clone()),
+        };
+
+        let mut clock = self.chars.keys().map(|s| s.clock).max().unwrap_or(0);
+        for ch in string.chars() {
+            clock += 1;
+            let stamp = Stamp {
+                clock,
+                replica: replica.to_owned(),
+            };
+            self.chars.insert(
+                stamp.clone(),
+                TextChar {
+                    after,
+                    ch,
+                    deleted: false,
+                },
+            );
+            after = Some(stamp);
+        }
+    }
+
+    pub fn delete(&mut self, index: usize, len: usize) {
+        let to_delete: Vec<Stamp> = self
+            .visible()
+            .into_iter()
+            .skip(index)
+            .take(len)
+            .map(|(stamp, _)| stamp)
+            .collect();
+        for stamp in to_delete {
+            if let Some(text_char) = self.chars.get_mut(&stamp) {
+                text_char.deleted = true;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Text) {
+        for (stamp, other_char) in other.chars.iter() {
+            match self.chars.get_mut(stamp) {
+                Some(text_char) => text_char.deleted |= other_char.deleted,
+                None => {
+                    self.chars.insert(stamp.clone(), other_char.clone());
+                }
+            }
+        }
+    }
+
+    fn visible(&self) -> Vec<(Stamp, char)> {
+        let mut children: HashMap<Option<&Stamp>, Vec<&Stamp>> = HashMap::new();
+        for (stamp, text_char) in self.chars.iter() {
+            children
+                .entry(text_char.after.as_ref())
+                .or_default()
+                .push(stamp);
+        }
+
+        let mut visible = Vec::new();
+        let mut seen = BTreeSet::new();
+        // depth first, newest child first
+        let mut stack: Vec<&Stamp> = children.get(&None).cloned().unwrap_or_default();
+        stack.sort();
+        while let Some(stamp) = stack.pop() {
+            if !seen.insert(stamp) {
+                continue;
+            }
+            let text_char = &self.chars[stamp];
+            if !text_char.deleted {
+                visible.push((stamp.clone(), text_char.ch));
+            }
+            if let Some(next) = children.get(&Some(stamp)) {
+                let mut next = next.clone();
+                next.sort();
+                stack.extend(next);
+            }
+        }
+        visible
+    }
+
+    fn from_inner(inner: &CborValue) -> MizeResult<Text> {
+        let mut text = Text::new();
+        for entry in cbor_array(inner, "text")? {
+            let entry = cbor_array(entry, "text entry")?;
+            let stamp = Stamp {
+                clock: cbor_u64(entry.first())?,
+                replica: cbor_text(entry.get(1).unwrap_or(&CborValue::Null))?,
+            };
+            let after = match entry.get(2) {
+                Some(CborValue::Null) | None => None,
+                Some(_) => Some(Stamp {
+                    clock: cbor_u64(entry.get(2))?,
+                    replica: cbor_text(entry.get(3).unwrap_or(&CborValue::Null))?,
+                }),
+            };
+            let ch = cbor_text(entry.get(4).unwrap_or(&CborValue::Null))?
+                .chars()
+                .next()
+                .ok_or(mize_err!("crdt text entry without a char"))?;
+            let deleted = matches!(entry.get(5), Some(CborValue::Bool(true)));
+            text.chars.insert(stamp, TextChar { after, ch, deleted });
+        }
+        Ok(text)
+    }
+
+    fn to_cbor(&self) -> CborValue {
+        let entries = self
+            .chars
+            .iter()
+            .map(|(stamp, text_char)| {
+                let (after_clock, after_replica) = match &text_char.after {
+                    Some(after) => (
+                        after.clock.into(),
+                        CborValue::Text(after.replica.to_owned()),
+                    ),
+                    None => (CborValue::Null, CborValue::Null),
+                };
+                CborValue::Array(vec![
+                    stamp.clock.into(),
+                    CborValue::Text(stamp.replica.to_owned()),
+                    after_clock,
+                    after_replica,
+                    CborValue::Text(text_char.ch.to_string()),
+                    CborValue::Bool(text_char.deleted),
+                ])
+            })
+            .collect();
+        CborValue::Tag(TAG_TEXT, Box::new(CborValue::Array(entries)))
+    }
+}
+
+impl From<Counter> for ItemData {
+    fn from(value: Counter) -> ItemData {
+        ItemData::from_cbor(value.to_cbor())
+    }
+}
+
+impl From<CrdtMap> for ItemData {
+    fn from(value: CrdtMap) -> ItemData {
+        ItemData::from_cbor(value.to_cbor())
+    }
+}
+
+impl From<Text> for ItemData {
+    fn from(value: Text) -> ItemData {
+        ItemData::from_cbor(value.to_cbor())
+    }
+}
+
+////// cbor helpers
+
+fn cbor_map<'a>(value: &'a CborValue, what: &str) -> MizeResult<&'a Vec<(CborValue, CborValue)>> {
+    match value {
+        CborValue::Map(map) => Ok(map),
+        other => Err(mize_err!("crdt {} is not a map: {:?}", what, other)),
+    }
+}
+
+fn cbor_array<'a>(value: &'a CborValue, what: &str) -> MizeResult<&'a Vec<CborValue>> {
+    match value {
+        CborValue::Array(vec) => Ok(vec),
+        other => Err(mize_err!("crdt {} is not an array: {:?}", what, other)),
+    }
+}
+
+fn cbor_text(value: &CborValue) -> MizeResult<String> {
+    match value {
+        CborValue::Text(text) => Ok(text.to_owned()),
+        other => Err(mize_err!("expected text in crdt, got: {:?}", other)),
+    }
+}
+
+fn cbor_u64(value: Option<&CborValue>) -> MizeResult<u64> {
+    match value {
+        Some(CborValue::Integer(int)) => {
+            u64::try_from(*int).map_err(|_| mize_err!("negative number in crdt"))
+        }
+        other => Err(mize_err!("expected a number in crdt, got: {:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // every order of merging has to give the same result
+    fn assert_converges(a: ItemData, b: ItemData, c: ItemData) {
+        let mut abc = a.clone();
+        abc.merge(b.clone());
+        abc.merge(c.clone());
+
+        let mut cba = c.clone();
+        cba.merge(b.clone());
+        cba.merge(a.clone());
+
+        let mut bac = b.clone();
+        bac.merge(a.clone());
+        bac.merge(c.clone());
+        // merging twice changes nothing
+        bac.merge(c);
+
+        assert_eq!(abc, cba);
+        assert_eq!(abc, bac);
+    }
+
+    #[test]
+    fn test_counter() -> MizeResult<()> {
+        let base = Counter::new();
+
+        let mut a = base.clone();
+        a.increment("a", 3);
+        let mut b = base.clone();
+        b.increment("b", 2);
+        b.decrement("b", 4);
+        let mut c = base.clone();
+        c.increment("c", 1);
+
+        assert_converges(a.clone().into(), b.clone().into(), c.clone().into());
+
+        let mut data: ItemData = a.into();
+        data.merge(b.into());
+        data.merge(c.into());
+        assert_eq!(Counter::from_data(&data)?.value(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_map() -> MizeResult<()> {
+        let mut base = CrdtMap::new();
+        base.set("base", "name", ItemData::from_string("old"));
+        base.set("base", "count", Counter::new());
+
+        let mut a = base.clone();
+        a.set("a", "name", ItemData::from_string("from a"));
+        let mut counter = Counter::from_data(&a.get("count").unwrap())?;
+        counter.increment("a", 1);
+        a.set("a", "count", counter);
+
+        let mut b = base.clone();
+        b.remove("b", "name");
+        let mut counter = Counter::from_data(&b.get("count").unwrap())?;
+        counter.increment("b", 1);
+        b.set("b", "count", counter);
+
+        let c = base.clone();
+
+        assert_converges(a.clone().into(), b.clone().into(), c.into());
+
+        let mut data: ItemData = a.into();
+        data.merge(b.into());
+        let map = CrdtMap::from_data(&data)?;
+        // both set name with the same clock, the replica decides
+        assert_eq!(map.get("name"), None);
+        // but the counter keeps both increments
+        assert_eq!(Counter::from_data(&map.get("count").unwrap())?.value(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_text() -> MizeResult<()> {
+        let mut base = Text::new();
+        base.insert("base", 0, "hello world");
+
+        let mut a = base.clone();
+        a.insert("a", 5, " there");
+        let mut b = base.clone();
+        b.delete(0, 1);
+        b.insert("b", 0, "H");
+        let mut c = base.clone();
+        c.insert("c", 11, "!");
+
+        assert_converges(a.clone().into(), b.clone().into(), c.clone().into());
+
+        let mut data: ItemData = a.into();
+        data.merge(b.into());
+        data.merge(c.into());
+        assert_eq!(Text::from_data(&data)?.value(), "Hello there world!");
+        assert_eq!(
+            plain_value(&data),
+            ItemData::from_string("Hello there world!")
+        );
+
+        Ok(())
+    }
+}
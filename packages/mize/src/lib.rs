@@ -1,5 +1,9 @@
 #![allow(warnings)]
 
+// so that the code #[mize_part] generates (which uses mize::...) also works in here (eg: in the
+// tests)
+extern crate self as mize;
+
 static PROTO_VERSION: u8 = 1;
 
 #[macro_export]
@@ -40,16 +44,19 @@ mod core {
     pub mod memstore;
     pub mod proto;
     pub mod types;
+    pub mod util;
 }
 
 pub use async_trait::async_trait;
 pub use core::error::MizeError;
 pub use core::error::MizeResult;
 pub use core::instance::module::Module;
+pub use core::instance::part::{
+    DynMizePartGuard, DynMizePartReadGuard, MizePartGuard, MizePartReadGuard,
+};
 pub use core::instance::Mize;
 pub use core::instance::{
-    DynMizePartGuard, MizePart, MizePartCreate, MizePartCreateGenerated, MizePartGenerated,
-    MizePartGuard,
+    BlockingRun, MizePart, MizePartCreate, MizePartCreateGenerated, MizePartGenerated,
 };
 pub use core::*;
 pub use mize_macros::*;
@@ -74,6 +81,13 @@ pub mod platform {
         #[cfg(not(any(feature = "target-os", feature = "target-wasm ")))]
         pub use super::super::instance_init;
 
+        //////////// instance_shutdown
+        #[cfg(feature = "target-os")]
+        pub use super::os::os_instance_shutdown as instance_shutdown;
+
+        #[cfg(not(feature = "target-os"))]
+        pub use super::super::instance_shutdown;
+
         //////////// load_module
         #[cfg(feature = "target-os")]
         pub use super::os::load_module;
@@ -100,6 +114,10 @@ pub fn instance_init(instance: &mut core::instance::Mize) -> MizeResult<()> {
     Ok(())
 }
 
+pub fn instance_shutdown(instance: &core::instance::Mize) -> MizeResult<()> {
+    Ok(())
+}
+
 pub fn load_module(
     instance: &mut core::instance::Mize,
     name: &str,
@@ -6,17 +6,24 @@ use home::home_dir;
 use mize::mize_err;
 use std::ffi::OsString;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 use tracing::debug;
 
 use mize::error::{IntoMizeResult, MizeError, MizeResult, MizeResultTrait};
 use mize::instance::subscription::Subscription;
 use mize::instance::subscription::Update;
+use mize::instance::recorder::{self, Direction, Record};
+use mize::util::now_ms;
 use mize::instance::Mize;
 use mize::item::{IntoItemData, ItemData};
-use mize::platform::os::config_from_cli_args;
+use mize::platform::os::{daemon, unix_socket};
+use mize::platform::os::{
+    block_stop_signals, config_before_init, config_from_cli_args, shutdown_on_signals, store_path,
+};
 use mize::platform::os::fsstore::FileStore;
+use mize::platform::os::stdio;
 
 pub fn get(sub_matches: &ArgMatches) -> MizeResult<()> {
     let instance = Mize::with_config(config_from_cli_args(sub_matches)?)?;
@@ -58,8 +65,15 @@ pub fn create(sub_matches: &ArgMatches) -> MizeResult<()> {
 }
 
 pub fn is_running(sub_matches: &ArgMatches) -> MizeResult<()> {
-    println!("not implemented");
-    Err(mize_err!("not implemented"))
+    let store_path = store_path(&config_before_init(config_from_cli_args(sub_matches)?)?)?;
+    let status = daemon::status(Path::new(&store_path))?;
+    println!("{}", status);
+
+    // for scripts, without logging an error
+    if !status.is_running() {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
 pub fn mount(sub_matches: &ArgMatches) -> MizeResult<()> {
@@ -67,7 +81,14 @@ pub fn mount(sub_matches: &ArgMatches) -> MizeResult<()> {
 }
 
 pub fn run(sub_matches: &ArgMatches) -> MizeResult<()> {
-    let instance = Mize::with_config(config_from_cli_args(sub_matches)?)?;
+    if sub_matches.get_flag("daemon") {
+        let store_path = store_path(&config_before_init(config_from_cli_args(sub_matches)?)?)?;
+        daemon::start(Path::new(&store_path))?;
+    }
+
+    let signals = block_stop_signals()?;
+    let mut instance = Mize::with_config(config_from_cli_args(sub_matches)?)?;
+    shutdown_on_signals(&mut instance, signals)?;
 
     instance.wait();
 
@@ -114,9 +135,42 @@ pub fn show(sub_matches: &ArgMatches) -> MizeResult<()> {
 }
 
 pub fn stop(sub_matches: &ArgMatches) -> MizeResult<()> {
+    // only to read the config, it does not open the store or connect to the running instance
+    let config = config_before_init(config_from_cli_args(sub_matches)?)?;
+    let store_path = store_path(&config)?;
+    let instance = Mize::empty()?;
+    instance.set_blocking("0", config)?;
+
+    unix_socket::request_shutdown(&instance, Path::new(&store_path))?;
+    println!("stopped the instance at '{}'", store_path);
+
     Ok(())
 }
 
+pub fn serve_stdio(sub_matches: &ArgMatches) -> MizeResult<()> {
+    let mut instance = Mize::with_config(config_from_cli_args(sub_matches)?)?;
+
+    stdio::serve(&mut instance)
+}
+
+pub fn connect(sub_matches: &ArgMatches) -> MizeResult<()> {
+    let mut instance = Mize::with_config(config_from_cli_args(sub_matches)?)?;
+
+    if !sub_matches.get_flag("stdio") {
+        return Err(mize_err!(
+            "only connecting with --stdio is supported, local instances are connected to automatically"
+        ));
+    }
+
+    let cmd: Vec<OsString> = sub_matches
+        .get_many::<OsString>("cmd")
+        .ok_or(mize_err!("No command to connect to given, use: mize connect --stdio -- <cmd>..."))?
+        .map(|e| e.to_owned())
+        .collect();
+
+    stdio::connect_command(&mut instance, cmd)
+}
+
 pub fn gui(sub_matches: &ArgMatches) -> MizeResult<()> {
     let mut instance = Mize::with_config(config_from_cli_args(sub_matches)?)?;
 
@@ -127,16 +181,45 @@ pub fn gui(sub_matches: &ArgMatches) -> MizeResult<()> {
     Ok(())
 }
 
+// prints cbor values from stdin, a log of the recorder is printed msg by msg
 pub fn format_cbor(sub_matches: &ArgMatches) -> MizeResult<()> {
-    let mut input: Vec<u8> = Vec::new();
     let stdin = std::io::stdin();
-    let mut handle = stdin.lock();
-    //handle.read_to_end(&mut input);
+    let handle = stdin.lock();
+
+    for value in recorder::read_values(handle)? {
+        if Record::is_record(&value) {
+            println!("{}", Record::from_cbor(value)?);
+        } else {
+            println!("{}", ItemData::from_cbor(value));
+        }
+    }
 
-    let cbor_data = ciborium::from_reader(handle)?;
-    let item_data = ItemData::from_cbor(cbor_data);
+    Ok(())
+}
 
-    println!("{}", item_data);
+pub fn replay(sub_matches: &ArgMatches) -> MizeResult<()> {
+    let path = sub_matches
+        .get_one::<String>("file")
+        .ok_or(MizeError::new().msg("No file Argument specified"))?;
+    let file = std::fs::File::open(path).mize_result_msg(format!("could not open '{}'", path))?;
+    let records = recorder::read_records(file)?;
+
+    // a fresh instance, that does not open the store or connect to a running one
+    let instance = Mize::empty()?;
+    instance.set_blocking("0", config_from_cli_args(sub_matches)?)?;
+
+    let rx = recorder::replay(&instance, records)?;
+
+    // print what the instance answers, until it is quiet for a bit
+    while let Ok(msg) = rx.recv_timeout(Duration::from_secs(1)) {
+        let record = Record {
+            dir: Direction::Out,
+            conn_id: msg.conn_id,
+            time: now_ms(),
+            msg,
+        };
+        println!("{}", record);
+    }
 
     Ok(())
 }
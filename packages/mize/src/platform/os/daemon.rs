@@ -0,0 +1,148 @@
+use daemonize::Daemonize;
+use std::fmt;
+use std::fs;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use sysinfo::{Pid, System};
+
+use crate::error::{IntoMizeResult, MizeError, MizeResult};
+use crate::mize_err;
+
+use super::fsstore::valid_pid_file;
+
+// `mize run --daemon` and `mize is-running`
+//
+// the instance, that owns a store, writes it's pid to <store_path>/pid and listens on
+// <store_path>/sock, a daemon also logs to <store_path>/mize.log
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SocketState {
+    Missing,
+    // the file exists, but no one accepts connections on it (eg: the instance was killed)
+    Stale,
+    Accepting,
+}
+
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub store_path: PathBuf,
+    pub pid: Option<u32>,
+    // in seconds
+    pub uptime: Option<u64>,
+    pub socket: SocketState,
+}
+
+impl Status {
+    pub fn is_running(&self) -> bool {
+        self.pid.is_some() || self.socket == SocketState::Accepting
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let running = if self.is_running() { "yes" } else { "no" };
+        writeln!(f, "running: {}", running)?;
+        writeln!(f, "store: {}", self.store_path.display())?;
+
+        match (self.pid, self.uptime) {
+            (Some(pid), Some(uptime)) => {
+                writeln!(f, "pid: {} (up {})", pid, format_duration(uptime))?
+            }
+            (Some(pid), None) => writeln!(f, "pid: {}", pid)?,
+            (None, _) => writeln!(f, "pid: none")?,
+        }
+
+        let socket = match self.socket {
+            SocketState::Missing => "missing",
+            SocketState::Stale => "exists, but no one accepts connections",
+            SocketState::Accepting => "accepting connections",
+        };
+        write!(
+            f,
+            "socket: {} ({})",
+            self.store_path.join("sock").display(),
+            socket
+        )
+    }
+}
+
+pub fn status(store_path: &Path) -> MizeResult<Status> {
+    let pid = valid_pid_file(store_path)?;
+
+    let uptime = pid.and_then(|pid| {
+        let mut system = System::new();
+        let pid = Pid::from_u32(pid);
+        system.refresh_process(pid);
+        system.process(pid).map(|process| process.run_time())
+    });
+
+    let sock_path = store_path.join("sock");
+    let socket = if !sock_path.exists() {
+        SocketState::Missing
+    } else if UnixStream::connect(&sock_path).is_ok() {
+        SocketState::Accepting
+    } else {
+        SocketState::Stale
+    };
+
+    Ok(Status {
+        store_path: store_path.to_owned(),
+        pid,
+        uptime,
+        socket,
+    })
+}
+
+// detach from the terminal, the calling process exits and only the daemon returns from this
+// has to be called, before any thread is started, they would not survive the fork
+pub fn start(store_path: &Path) -> MizeResult<()> {
+    let status = status(store_path)?;
+    if status.is_running() {
+        return Err(mize_err!(
+            "an instance is already running at '{}' (pid: {:?})",
+            store_path.display(),
+            status.pid
+        ));
+    }
+
+    fs::create_dir_all(store_path)
+        .mize_result_msg(format!("could not create '{}'", store_path.display()))?;
+
+    let log_path = store_path.join("mize.log");
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .mize_result_msg(format!("could not open '{}'", log_path.display()))?;
+
+    println!(
+        "starting the instance at '{}' as a daemon, it logs to '{}'",
+        store_path.display(),
+        log_path.display()
+    );
+
+    Daemonize::new()
+        .pid_file(store_path.join("pid"))
+        .working_directory(store_path)
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        .start()
+        .mize_result_msg("could not start the daemon")?;
+
+    Ok(())
+}
+
+pub(crate) fn format_duration(secs: u64) -> String {
+    let (days, hours, mins, secs) = (
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    );
+    match (days, hours, mins) {
+        (0, 0, 0) => format!("{}s", secs),
+        (0, 0, _) => format!("{}m {}s", mins, secs),
+        (0, _, _) => format!("{}h {}m {}s", hours, mins, secs),
+        _ => format!("{}d {}h {}m", days, hours, mins),
+    }
+}
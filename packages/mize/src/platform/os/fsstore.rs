@@ -30,19 +30,29 @@ impl FileStore {
         fs::create_dir_all(Path::new(&path).join("store"))?;
 
         // check for valid pid file
-        /*
-        if let Some(pid) = valid_pid_file(path)? {
-            // store already opened
-            return Err(mize_err!("MizeStore at path {} is already opened by process with pid {}", path.display(), pid));
-
-        } else {
-            // write our own pid file
-            fs::remove_file(path.join("pid"));
-            let pid = std::process::id();
-            let mut file = OpenOptions::new().write(true).create(true).open(path.join("pid"))?;
-            write!(file, "{}", pid)?;
+        // a daemon already wrote it's pid file (see daemon.rs)
+        match valid_pid_file(path)? {
+            Some(pid) if pid == std::process::id() => {}
+            Some(pid) => {
+                // store already opened
+                return Err(mize_err!(
+                    "MizeStore at path {} is already opened by process with pid {}",
+                    path.display(),
+                    pid
+                ));
+            }
+            None => {
+                // write our own pid file
+                fs::remove_file(path.join("pid"));
+                let pid = std::process::id();
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path.join("pid"))?;
+                write!(file, "{}", pid)?;
+            }
         }
-        */
 
         // init the store
         if !path.join("next_id").exists() {
@@ -54,6 +64,50 @@ impl FileStore {
         })
     }
 
+    // a folder of items, that is not the store of an instance (eg: a mount), so there is no pid
+    // file and nothing is created, until something is written
+    pub fn open_dir(path_str: &str) -> MizeResult<FileStore> {
+        Ok(FileStore {
+            path: Path::new(path_str).to_owned(),
+        })
+    }
+
+    // the ids of the range 0.. are counted in next_id, like they always were
+    fn next_id_path(&self, from: u64) -> PathBuf {
+        if from == 0 {
+            self.path.join("next_id")
+        } else {
+            self.path.join(format!("next_id_{}", from))
+        }
+    }
+
+    // the store_parts of all items, in any namespace folder
+    fn store_parts(&self) -> MizeResult<Vec<u64>> {
+        let mut ids = Vec::new();
+        if !self.path.join("store").exists() {
+            return Ok(ids);
+        }
+        for ns_dir in fs::read_dir(self.path.join("store"))? {
+            let ns_dir = ns_dir?.path();
+            if !ns_dir.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(ns_dir)? {
+                if let Some(Ok(id)) = file?.file_name().to_str().map(|name| name.parse::<u64>()) {
+                    ids.push(id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    fn version_path(&self, id: &MizeId) -> PathBuf {
+        self.path
+            .join("versions")
+            .join(id.namespace_str())
+            .join(id.store_part())
+    }
+
     pub fn store_is_opened(store_path: String, instance: &mut Mize) -> MizeResult<bool> {
         // this was the old method....
         //let valid_pid_file = valid_pid_file(Path::new(&store_path))?.is_some();
@@ -81,24 +135,45 @@ async fn try_connect_to_sock(store_path: String) -> MizeResult<()> {
 
 impl Store for FileStore {
     fn new_id(&self) -> MizeResult<String> {
-        let mut next_id: u64 =
-            String::from_utf8(fs::read(self.path.join("next_id")).mize_result_msg(format!(
-                "could not read next_id at '{}'",
-                self.path.display()
-            ))?)?
+        self.new_id_in(0, u64::MAX)?.ok_or(mize_err!(
+            "the FileStore at '{}' has no ids left",
+            self.path.display()
+        ))
+    }
+
+    fn new_id_in(&self, from: u64, to: u64) -> MizeResult<Option<String>> {
+        let next_id_path = self.next_id_path(from);
+
+        let mut next_id: u64 = if next_id_path.exists() {
+            String::from_utf8(
+                fs::read(&next_id_path)
+                    .mize_result_msg(format!("could not read '{}'", next_id_path.display()))?,
+            )?
             .parse()
             .mize_result_msg(format!(
-                "could not parse next_id at '{}' to u64",
-                self.path.display()
-            ))?;
+                "could not parse '{}' to u64",
+                next_id_path.display()
+            ))?
+        } else {
+            self.store_parts()?
+                .into_iter()
+                .filter(|id| (from..to).contains(id))
+                .max()
+                .map_or(from, |id| id + 1)
+        };
+
+        if next_id >= to {
+            return Ok(None);
+        }
 
         let id_string = format!("{}", next_id);
 
         next_id += 1;
 
-        fs::write(self.path.join("next_id"), format!("{}", next_id))?;
+        fs::create_dir_all(&self.path)?;
+        fs::write(&next_id_path, format!("{}", next_id))?;
 
-        return Ok(id_string);
+        return Ok(Some(id_string));
     }
 
     fn set(&self, id: MizeId, data: ItemData) -> MizeResult<()> {
@@ -109,12 +184,50 @@ impl Store for FileStore {
             .join(id.store_part());
         fs::create_dir_all(self.path.join("store").join(id.namespace_str()))?;
 
-        let file = OpenOptions::new().write(true).create(true).open(path)?;
+        // like in the MemStore, the data of an id with a path goes to that path of the item
+        let sub_path = id.after_store_part();
+        let data = if !sub_path.is_empty() && path.exists() {
+            let file = OpenOptions::new().read(true).open(&path)?;
+            let cbor_value: CborValue = ciborium::from_reader(file).mize_result_msg(format!(
+                "could not read file '{}' from FileStore",
+                path.display()
+            ))?;
+            let mut full = cbor_value.into_item_data();
+            full.set_path(sub_path, data)?;
+            full
+        } else {
+            data
+        };
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
 
         ciborium::into_writer(data.cbor(), file)?;
+
+        let version = self.get_version(id.clone())? + 1;
+        fs::create_dir_all(self.path.join("versions").join(id.namespace_str()))?;
+        fs::write(self.version_path(&id), format!("{}", version))?;
+
         Ok(())
     }
 
+    fn get_version(&self, id: MizeId) -> MizeResult<u64> {
+        let path = self.version_path(&id);
+        if !path.exists() {
+            return Ok(0);
+        }
+
+        String::from_utf8(fs::read(&path)?)?
+            .parse()
+            .mize_result_msg(format!(
+                "could not parse version at '{}' to u64",
+                path.display()
+            ))
+    }
+
     fn get_links(&self, item: Item) -> MizeResult<Vec<MizeId>> {
         Ok(Vec::new())
     }
@@ -178,17 +291,51 @@ impl Store for FileStore {
         return Ok(ret_data);
     }
     fn id_iter(&self) -> MizeResult<IdIter> {
-        todo!()
+        IdIter::new(Box::new(self.to_owned()))
     }
     fn next_id(&self, prev_id: &str) -> MizeResult<Option<String>> {
-        todo!()
+        let prev: u64 = prev_id.parse()?;
+
+        // the smallest store_part after prev, in any namespace folder
+        let next = self
+            .store_parts()?
+            .into_iter()
+            .filter(|id| *id > prev)
+            .min();
+
+        Ok(next.map(|id| format!("{}", id)))
+    }
+    fn flush(&self) -> MizeResult<()> {
+        // set leaves the item and version files in the page cache, so sync them and the
+        // directories, that hold their entries
+        // not all of self.path, the socket of the instance lives there too
+        for name in ["store", "versions", "next_id"] {
+            let path = self.path.join(name);
+            if path.exists() {
+                sync_tree(&path)?;
+            }
+        }
+        fs::File::open(&self.path)?.sync_all()?;
+        Ok(())
     }
+
     fn first_id(&self) -> MizeResult<String> {
         Ok("0".to_owned())
     }
 }
 
-fn valid_pid_file(path: &Path) -> MizeResult<Option<u32>> {
+fn sync_tree(path: &Path) -> MizeResult<()> {
+    if path.is_dir() {
+        for entry in fs::read_dir(path)? {
+            sync_tree(&entry?.path())?;
+        }
+    }
+    fs::File::open(path)
+        .and_then(|file| file.sync_all())
+        .mize_result_msg(format!("could not sync '{}'", path.display()))
+}
+
+pub(crate) fn valid_pid_file(path: &Path) -> MizeResult<Option<u32>> {
     let pid_file_path = path.join("pid");
 
     // if the pid file does not exist, there is no pid...
@@ -201,6 +348,7 @@ fn valid_pid_file(path: &Path) -> MizeResult<Option<u32>> {
         "Could not read contents of pid file at '{}'",
         pid_file_path.display()
     ))?)?
+    .trim()
     .parse()
     .mize_result_msg(format!(
         "Could not parse contents of pid file at '{}' to u32",
@@ -472,7 +472,8 @@ impl Subscriber for MinimalTracer {
             .map(|id| span_names.get(id).unwrap().to_owned())
             .collect::<Vec<String>>()
             .join("::");
-        println!("{level_str} {span_text} {text}");
+        // log to stderr, so stdout stays usable for output (eg: the messages of serve-stdio)
+        eprintln!("{level_str} {span_text} {text}");
     }
 
     fn enter(&self, _span: &tracing_core::span::Id) {
@@ -43,8 +43,8 @@ fn main() {
 
         Some(("is-running", sub_matches)) => cli::is_running(sub_matches),
 
-        // mi serve
-        Some(("serve", sub_matches)) => cli::stop(sub_matches),
+        // mi stop
+        Some(("stop", sub_matches)) => cli::stop(sub_matches),
 
         // mi mount
         Some(("mount", sub_matches)) => cli::mount(sub_matches),
@@ -64,12 +64,21 @@ fn main() {
         // mi create
         Some(("create", sub_matches)) => cli::create(sub_matches),
 
+        // mi serve-stdio
+        Some(("serve-stdio", sub_matches)) => cli::serve_stdio(sub_matches),
+
+        // mi connect
+        Some(("connect", sub_matches)) => cli::connect(sub_matches),
+
         // mi gui
         Some(("gui", sub_matches)) => cli::gui(sub_matches),
 
         // mi format-cbor
         Some(("format-cbor", sub_matches)) => cli::format_cbor(sub_matches),
 
+        // mi replay
+        Some(("replay", sub_matches)) => cli::replay(sub_matches),
+
         // some unknown command passed
         Some((cmd, sub_matches)) => cli::run_from_module(cmd, sub_matches),
 
@@ -133,7 +142,14 @@ fn cli_matches() -> clap::ArgMatches {
         .subcommand(
             Command::new("run")
                 .aliases(["r"])
-                .about("Run a MiZe Instance"),
+                .about("Run a MiZe Instance")
+                .arg(
+                    Arg::new("daemon")
+                        .long("daemon")
+                        .short('d')
+                        .action(ArgAction::SetTrue)
+                        .help("detach from the terminal, the pid and the log are in the store folder"),
+                ),
         )
         .subcommand(Command::new("stop").about("Stop a MiZe Instance"))
         .subcommand(Command::new("mount").aliases(["m"]))
@@ -158,7 +174,35 @@ fn cli_matches() -> clap::ArgMatches {
         .subcommand(Command::new("create").aliases(["cr"]))
         .subcommand(Command::new("is-running").aliases(["isr"]))
         .subcommand(Command::new("gui"))
-        .subcommand(Command::new("format-cbor"))
+        .subcommand(
+            Command::new("serve-stdio")
+                .about("Serve the Instance over stdin and stdout (eg: as the remote end of ssh)"),
+        )
+        .subcommand(
+            Command::new("connect")
+                .about("Connect to another Instance and join it's namespace")
+                .arg(
+                    Arg::new("stdio")
+                        .long("stdio")
+                        .action(ArgAction::SetTrue)
+                        .help("speak to the stdin and stdout of <cmd> (eg: ssh host mize serve-stdio)"),
+                )
+                .arg(
+                    Arg::new("cmd")
+                        .num_args(1..)
+                        .last(true)
+                        .value_parser(clap::value_parser!(OsString)),
+                ),
+        )
+        .subcommand(
+            Command::new("format-cbor")
+                .about("Pretty print cbor from stdin (eg: a log written with the record option)"),
+        )
+        .subcommand(
+            Command::new("replay")
+                .about("Send the incoming msgs of a recorded log to a fresh Instance")
+                .arg(Arg::new("file").required(true).help("The log to replay")),
+        )
         .arg_required_else_help(true);
 
     return main.get_matches();
@@ -15,85 +15,57 @@ use tracing::{debug, error, info, trace, warn};
 use crate::error::{IntoMizeResult, MizeError, MizeResult};
 use crate::id::MizeId;
 use crate::instance::module::EmptyModule;
+use crate::instance::rules;
 use crate::instance::store::Store;
-use crate::instance::Mize;
+use crate::instance::{self, Mize};
 use crate::item::{data_from_string, IntoItemData, ItemData};
 use crate::memstore::MemStore;
+
+pub use crate::config::config_flag;
 use crate::{mize_err, Module};
 
 use self::fsstore::FileStore;
 
+#[cfg(target_family = "unix")]
+pub mod daemon;
 pub mod fsstore;
 pub mod logging;
+pub mod stdio;
 
 #[cfg(target_family = "unix")]
-mod unix_socket;
+pub mod unix_socket;
 
 #[cfg(target_family = "unix")]
 use crate::platform::os::unix_socket::UnixListener;
-//mod web;
+
+#[cfg(target_family = "unix")]
+use nix::sys::signal::{SigSet, Signal};
+
+#[cfg(feature = "web")]
+pub mod web;
 
 pub fn os_instance_init(instance: &mut Mize) -> MizeResult<()> {
     // this is the code, that runs to initialize an Instance on a system with an os present.
 
-    ////// load MIZE_CONFIG_FILE as the instance, which is item 0
-    match std::env::var("MIZE_CONFIG_FILE") {
-        Ok(config_file_path) => {
-            let config = config_from_file(config_file_path)?;
-            debug!("env var MIZE_CONFIG_FILE present");
-            instance.set_blocking("0", config)?;
-            trace!(
-                "config after MIZE_CONFIG_FILE env var: {}",
-                instance.get("self/config")?
-            );
-        }
-        Err(var_err) => match var_err {
-            VarError::NotPresent => {
-                debug!("env var MIZE_CONFIG_FILE NOT present");
-            }
-            VarError::NotUnicode(_) => {
-                warn!("env var MIZE_CONFIG_FILE is not Unicode, so not reading it")
-            }
-        },
-    };
-
-    ////// load config from MIZE_CONFIG env var
-    match std::env::var("MIZE_CONFIG") {
-        Ok(config_string) => {
-            let config = data_from_string(config_string)?;
-            debug!("env var MIZE_CONFIG present");
-            instance.set_blocking("self/config", config)?;
-            trace!(
-                "config after MIZE_CONFIG env var: {}",
-                instance.get("self/config")?
-            );
-        }
-        Err(var_err) => match var_err {
-            VarError::NotPresent => {
-                debug!("env var MIZE_CONFIG NOT present")
-            }
-            VarError::NotUnicode(_) => {
-                warn!("env var MIZE_CONFIG is not Unicode, so not reading it")
-            }
-        },
-    };
+    load_env_config(instance)?;
+
+    ////// if config.record is set, record all msgs to that file
+    if let Ok(record_path) = instance.get("self/config/record")?.value_string() {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&record_path)
+            .mize_result_msg(format!("could not open record file '{}'", record_path))?;
+        debug!("recording msgs to: {}", record_path);
+        instance.record_to(Box::new(file))?;
+    }
 
     ////// if a config.store_path is set, upgrade to the filestore there
     let mut test = instance.get("0")?.as_data_full()?;
     let mut store_path = match instance.get("self/config/store_path")?.value_string() {
         Ok(path) => path,
         Err(e) => {
-            // the default store_path: $HOME/.mize
-            let home_dir = if let Some(dir) = std::env::home_dir() {
-                dir
-            } else {
-                return Err(mize_err!(
-                    "could not get home_dir and store_path is not set in the config"
-                ));
-            };
-
-            let store_path_buff = home_dir.join(".mize");
-            let store_path = store_path_buff.display().to_string();
+            let store_path = store_path(&instance.get("0")?.as_data_full()?)?;
 
             instance.set_blocking(
                 "self/config/store_path",
@@ -102,7 +74,7 @@ pub fn os_instance_init(instance: &mut Mize) -> MizeResult<()> {
 
             let store_path = instance.get("self/config/store_path")?.value_string()?;
 
-            println!("store_path: {}", store_path);
+            debug!("store_path: {}", store_path);
 
             store_path
         }
@@ -128,6 +100,8 @@ pub fn os_instance_init(instance: &mut Mize) -> MizeResult<()> {
         // else open it ourselves
         let file_store = FileStore::new(store_path.as_str())?;
         instance.migrate_to_store(Box::new(file_store))?;
+        mount_from_config(instance)?;
+        rules::load(instance)?;
 
         let path = Path::new(&store_path).to_owned();
 
@@ -140,11 +114,207 @@ pub fn os_instance_init(instance: &mut Mize) -> MizeResult<()> {
         {
             warn!("would add a Listener on a local socket, but that is not yet implemented for windows");
         }
+
+        #[cfg(feature = "web")]
+        if config_flag(instance, "self/config/web/enable")? {
+            instance.add_listener(web::WebListener::from_config(instance)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+// the stores in the config option mount, see mount.rs
+fn mount_from_config(instance: &Mize) -> MizeResult<()> {
+    let mounts = match instance.get("self/config/mount")?.as_data_full() {
+        Ok(mounts) => mounts,
+        Err(_) => return Ok(()),
+    };
+    let mounts = match mounts.cbor() {
+        CborValue::Map(mounts) => mounts.clone(),
+        CborValue::Null => return Ok(()),
+        other => {
+            return Err(mize_err!(
+                "config option mount is not a map of mounts: {:?}",
+                other
+            ))
+        }
+    };
+
+    for (name, options) in mounts {
+        let name = ItemData::from_cbor(name).value_string()?;
+        let options = ItemData::from_cbor(options);
+
+        let store: Box<dyn Store> = match options.get_path("store")?.value_string()?.as_str() {
+            "mem" => Box::new(MemStore::new()),
+            "file" => {
+                let path = options
+                    .get_path("path")?
+                    .value_string()
+                    .map_err(|err| err.msg(format!("the file mount '{}' has no path", name)))?;
+                Box::new(FileStore::open_dir(path.as_str())?)
+            }
+            other => {
+                return Err(mize_err!(
+                    "the mount '{}' has an unknown store '{}'",
+                    name,
+                    other
+                ))
+            }
+        };
+
+        let read_only = match options.get_path("read_only")?.cbor() {
+            CborValue::Bool(val) => *val,
+            CborValue::Text(text) => text == "true",
+            _ => false,
+        };
+
+        instance.mount(
+            name.as_str(),
+            mount_bound(&options, &name, "from")?,
+            mount_bound(&options, &name, "to")?,
+            store,
+            read_only,
+        )?;
     }
 
     Ok(())
 }
 
+fn mount_bound(options: &ItemData, name: &str, key: &str) -> MizeResult<u64> {
+    match options.get_path(key)?.cbor() {
+        CborValue::Integer(val) => u64::try_from(*val)
+            .map_err(|_| mize_err!("{} of the mount '{}' is not a valid store_part", key, name)),
+        CborValue::Text(val) => Ok(val.parse()?),
+        other => Err(mize_err!(
+            "{} of the mount '{}' is not a number: {:?}",
+            key,
+            name,
+            other
+        )),
+    }
+}
+
+// the config from the env vars MIZE_CONFIG_FILE and MIZE_CONFIG
+pub fn load_env_config(instance: &Mize) -> MizeResult<()> {
+    let config = env_config()?;
+    if config != ItemData::new() {
+        instance.set_blocking("0", config)?;
+    }
+    trace!(
+        "config after the env vars: {}",
+        instance.get("self/config")?
+    );
+    Ok(())
+}
+
+// MIZE_CONFIG_FILE is the whole instance, which is item 0, MIZE_CONFIG only the config of it
+fn env_config() -> MizeResult<ItemData> {
+    let mut config = ItemData::new();
+
+    match std::env::var("MIZE_CONFIG_FILE") {
+        Ok(config_file_path) => {
+            debug!("env var MIZE_CONFIG_FILE present");
+            config.merge(config_from_file(config_file_path)?);
+        }
+        Err(VarError::NotPresent) => debug!("env var MIZE_CONFIG_FILE NOT present"),
+        Err(VarError::NotUnicode(_)) => {
+            warn!("env var MIZE_CONFIG_FILE is not Unicode, so not reading it")
+        }
+    };
+
+    match std::env::var("MIZE_CONFIG") {
+        Ok(config_string) => {
+            debug!("env var MIZE_CONFIG present");
+            let mut env_config = ItemData::new();
+            env_config.set_path("config", data_from_string(config_string)?)?;
+            config.merge(env_config);
+        }
+        Err(VarError::NotPresent) => debug!("env var MIZE_CONFIG NOT present"),
+        Err(VarError::NotUnicode(_)) => {
+            warn!("env var MIZE_CONFIG is not Unicode, so not reading it")
+        }
+    };
+
+    Ok(config)
+}
+
+// the instance item, put together like Mize::with_config() does, but without an instance, which
+// starts threads (eg: before the fork of a daemon):
+// build time config < cli_config < MIZE_CONFIG_FILE < MIZE_CONFIG
+pub fn config_before_init(cli_config: ItemData) -> MizeResult<ItemData> {
+    let mut config = instance::build_time_config()?;
+    config.merge(cli_config);
+    config.merge(env_config()?);
+    Ok(config)
+}
+
+// the config option store_path of the instance item, or the default: $HOME/.mize
+pub fn store_path(instance_item: &ItemData) -> MizeResult<String> {
+    if let Ok(path) = instance_item.get_path("config/store_path")?.value_string() {
+        return Ok(path);
+    }
+
+    let home_dir = if let Some(dir) = std::env::home_dir() {
+        dir
+    } else {
+        return Err(mize_err!(
+            "could not get home_dir and store_path is not set in the config"
+        ));
+    };
+
+    Ok(home_dir.join(".mize").display().to_string())
+}
+
+pub fn os_instance_shutdown(instance: &Mize) -> MizeResult<()> {
+    // only the instance, that opened the store, listens on it's socket
+    let store_path = match instance.get("self/config/store_path")?.value_string() {
+        Ok(path) => path,
+        Err(_) => return Ok(()),
+    };
+    if !instance.we_are_namespace()? {
+        return Ok(());
+    }
+
+    #[cfg(target_family = "unix")]
+    unix_socket::stop_listening(Path::new(&store_path))?;
+
+    // `mize is-running` should not find us anymore
+    let pid_path = Path::new(&store_path).join("pid");
+    if fsstore::valid_pid_file(Path::new(&store_path))? == Some(std::process::id()) {
+        fs::remove_file(&pid_path)
+            .mize_result_msg(format!("could not remove '{}'", pid_path.display()))?;
+    }
+
+    Ok(())
+}
+
+// SIGINT and SIGTERM shut the instance down
+// the signals are blocked in the calling thread and all threads started after it, so this has to
+// be called, before the instance is created
+#[cfg(target_family = "unix")]
+pub fn block_stop_signals() -> MizeResult<SigSet> {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    signals
+        .thread_block()
+        .mize_result_msg("could not block SIGINT and SIGTERM")?;
+    Ok(signals)
+}
+
+#[cfg(target_family = "unix")]
+pub fn shutdown_on_signals(instance: &mut Mize, signals: SigSet) -> MizeResult<()> {
+    let instance_clone = instance.clone();
+    instance.spawn_detached("signal handler", move || {
+        let signal = signals
+            .wait()
+            .mize_result_msg("waiting for SIGINT or SIGTERM failed")?;
+        info!("got {}", signal);
+        instance_clone.shutdown()
+    })
+}
+
 pub fn seconds_since_modification(path: &Path) -> MizeResult<u64> {
     let metadata = fs::metadata(path)?;
 
@@ -163,7 +333,7 @@ pub fn get_module_hash(
     let mut selector_data = instance.get("self/config/selector")?.as_data_full()?;
 
     // handle the case, when we want a module for not the system we are running on...
-    println!("get_module_hash: name: {}", name);
+    debug!("get_module_hash: name: {}", name);
     let mut name_parts = name.split(".");
     if name_parts
         .next()
@@ -174,7 +344,7 @@ pub fn get_module_hash(
             .next()
             .ok_or(mize_err!("no 1th element in the modName"))?;
         let mod_name: String = name_parts.collect();
-        println!("get_module_hash: mod_name: {}", mod_name);
+        debug!("get_module_hash: mod_name: {}", mod_name);
         if mod_name == "" {
             return Err(mize_err!("no 2nd element in the modName"));
         }
@@ -332,6 +502,8 @@ pub fn load_module(instance: &mut Mize, module_name: &str, path: Option<String>)
     module.init(&instance)?;
 
     modules_inner.insert(module_name.to_owned(), module);
+    drop(modules_inner);
+    instance::introspect::changed(instance, "modules")?;
 
     unsafe {
         // dropping the lib, would (i suspect) free all memory, of the library's code, which would
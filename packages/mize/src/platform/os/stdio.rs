@@ -0,0 +1,128 @@
+use ciborium::Value as CborValue;
+use std::ffi::OsString;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::process::{Command, Stdio};
+use tracing::{debug, info, warn};
+
+use crate::error::{IntoMizeResult, MizeError, MizeResult};
+use crate::instance::auth::Principal;
+use crate::instance::queue::QueueReceiver;
+use crate::instance::Mize;
+use crate::mize_err;
+use crate::proto::MizeMessage;
+
+// a connection over any pair of byte streams (eg: the stdin and stdout of a process), which carry
+// the same raw concatenated cbor values as the unix socket does
+
+pub fn stream_connection<R, W>(instance: &mut Mize, read: R, write: W) -> MizeResult<u64>
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let (send_tx, send_rx) = instance.new_conn_queue()?;
+
+    let conn_id = instance.new_connection(send_tx)?;
+    instance.connection_set_principal(conn_id, Principal::Owner)?;
+
+    let cloned_instance = instance.clone();
+    instance.spawn_detached("stdio incomming", move || {
+        let result = stream_incomming(read, cloned_instance.clone(), conn_id);
+        // if reading fails, close the connection
+        if let Err(err) = result {
+            warn!("stdio connection {} closing: {:?}", conn_id, err.messages);
+        }
+        cloned_instance.remove_connection(conn_id)
+    })?;
+
+    instance.spawn_detached("stdio outgoing", move || {
+        let result = stream_outgoing(write, send_rx, conn_id);
+        // if writing fails, the incomming side will notice as well
+        if let Err(err) = result {
+            warn!("stdio connection {} closing: {:?}", conn_id, err.messages);
+        }
+        Ok(())
+    })?;
+
+    Ok(conn_id)
+}
+
+// serve the instance on our own stdin and stdout until stdin is closed
+// as stdout carries the messages, nothing else may be printed to it (the logger writes to stderr)
+pub fn serve(instance: &mut Mize) -> MizeResult<()> {
+    let (send_tx, send_rx) = instance.new_conn_queue()?;
+
+    let conn_id = instance.new_connection(send_tx)?;
+    // who can write to our stdin, could also run us
+    instance.connection_set_principal(conn_id, Principal::Owner)?;
+    info!("serving on stdio as connection {}", conn_id);
+
+    instance.spawn_detached("stdio outgoing", move || {
+        stream_outgoing(std::io::stdout(), send_rx, conn_id)
+    })?;
+
+    let result = stream_incomming(std::io::stdin(), instance.clone(), conn_id);
+    instance.remove_connection(conn_id)?;
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            info!("stdin closed: {:?}", err.messages);
+            Ok(())
+        }
+    }
+}
+
+// spawn cmd (eg: ssh host mize serve-stdio), speak to it over it's stdin and stdout and join the
+// namespace of the instance on the other side
+// returns, when the child exits
+pub fn connect_command(instance: &mut Mize, cmd: Vec<OsString>) -> MizeResult<()> {
+    let (program, args) = cmd
+        .split_first()
+        .ok_or(mize_err!("no command to connect over stdio given"))?;
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .mize_result_msg(format!("could not spawn {:?}", program))?;
+
+    let child_stdin = child.stdin.take().ok_or(mize_err!("child has no stdin"))?;
+    let child_stdout = child
+        .stdout
+        .take()
+        .ok_or(mize_err!("child has no stdout"))?;
+
+    let conn_id = stream_connection(instance, child_stdout, child_stdin)?;
+    instance.join_namespace_of_peer(conn_id)?;
+
+    let status = child.wait()?;
+    info!("stdio peer exited with {}", status);
+
+    instance.remove_connection(conn_id)
+}
+
+fn stream_outgoing<W: Write>(
+    write: W,
+    send_rx: QueueReceiver<MizeMessage>,
+    conn_id: u64,
+) -> MizeResult<()> {
+    let mut write = BufWriter::new(write);
+    for msg in send_rx {
+        debug!("stdio outgoing got msg: {}", msg);
+        ciborium::into_writer(&msg.value(), &mut write)?;
+        write.flush()?;
+    }
+    Ok(())
+}
+
+fn stream_incomming<R: Read>(read: R, instance: Mize, conn_id: u64) -> MizeResult<()> {
+    let mut read = BufReader::new(read);
+    loop {
+        let value: CborValue = ciborium::from_reader(&mut read)?;
+        let msg = MizeMessage::new(value, conn_id);
+        debug!("stdio incoming got msg: {}", msg);
+        instance.got_msg(msg)?;
+    }
+}
@@ -1,17 +1,26 @@
 use ciborium::Value as CborValue;
-use flume::{unbounded, Receiver, Sender};
+use flume::{Receiver, Sender};
 use std::fs;
+use std::io::{ErrorKind, Read};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, Interest};
 use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{UnixListener as TokioUnixListener, UnixStream};
 use tracing::{debug, info, warn};
 
 use crate::error::{IntoMizeResult, MizeError, MizeResult};
+use crate::instance::auth::Principal;
 use crate::instance::connection::{ConnListener, Connection};
+use crate::instance::queue::QueueReceiver;
 use crate::instance::{self, Mize};
+use crate::item::ItemData;
+use crate::mize_err;
 use crate::proto::{self, MizeMessage};
 
+// how long `mize stop` waits for the instance to stop
+static SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct UnixListener {
     sock_path: PathBuf,
 }
@@ -30,35 +39,23 @@ pub fn connect(instance: &mut Mize, store_path: PathBuf) -> MizeResult<()> {
         connect_async(instance.clone(), store_path),
     )?;
 
-    let ns_of_peer_str = instance
-        .get(format!(
-            "inst/con_by_id/{}/peer/0/config/namespace",
-            conn_id
-        ))?
-        .value_string()?;
-    let ns_of_peer = instance.namespace_from_string(ns_of_peer_str)?;
-
-    info!(
-        "connect_async ... ns_of_peer: {}",
-        ns_of_peer.clone().as_string()
-    );
-
-    instance.connection_set_namespace(conn_id, ns_of_peer.clone());
-    instance.set_namespace(ns_of_peer);
+    instance.join_namespace_of_peer(conn_id)?;
 
     Ok(())
 }
 
 async fn connect_async(mut instance: Mize, store_path: PathBuf) -> MizeResult<u64> {
     let mut stream = UnixStream::connect(store_path.join("sock")).await?;
+    let principal = principal_of(&stream)?;
     let (unix_read, unix_write) = stream.into_split();
 
-    let (send_tx, send_rx) = unbounded::<MizeMessage>();
+    let (send_tx, send_rx) = instance.new_conn_queue()?;
 
     let conn_id = instance.new_connection(send_tx)?;
+    instance.connection_set_principal(conn_id, principal)?;
 
     let cloned_instance = instance.clone();
-    instance.spawn_background("incomming", move || {
+    instance.spawn_detached("incomming", move || {
         let result = unix_incomming(unix_read, cloned_instance, conn_id);
         // if unix incomming fails, close the connection
         if let Err(err) = result {
@@ -68,7 +65,7 @@ async fn connect_async(mut instance: Mize, store_path: PathBuf) -> MizeResult<u6
     });
 
     let outgoing_cloned_instance = instance.clone();
-    instance.spawn_background("outgoing", move || {
+    instance.spawn_detached("outgoing", move || {
         let result = unix_outgoing(unix_write, send_rx, outgoing_cloned_instance, conn_id);
         // if writing fails, close this connection
         if let Err(err) = result {
@@ -101,14 +98,21 @@ async fn unix_listen(listener: UnixListener, mut instance: Mize) -> MizeResult<(
             .accept()
             .await
             .mize_result_msg("Error while accepting Unix sock connection")?;
+        // shutdown() connects once more, so that we get here
+        if instance.is_stopping()? {
+            info!("unix listener stopping");
+            return Ok(());
+        }
         info!("new connection");
 
-        let (send_tx, send_rx) = unbounded::<MizeMessage>();
+        let (send_tx, send_rx) = instance.new_conn_queue()?;
+        let principal = principal_of(&unix_sock)?;
         let (unix_read, unix_write) = unix_sock.into_split();
 
         let conn_id = instance.new_connection(send_tx)?;
+        instance.connection_set_principal(conn_id, principal)?;
         let cloned_instance = instance.clone();
-        instance.spawn_background("incomming", move || {
+        instance.spawn_detached("incomming", move || {
             let result = unix_incomming(unix_read, cloned_instance, conn_id);
             // if unix incomming fails, close the connection
             if let Err(err) = result {
@@ -118,7 +122,7 @@ async fn unix_listen(listener: UnixListener, mut instance: Mize) -> MizeResult<(
         });
 
         let outgoing_cloned_instance = instance.clone();
-        instance.spawn_background("outgoing", move || {
+        instance.spawn_detached("outgoing", move || {
             let result = unix_outgoing(unix_write, send_rx, outgoing_cloned_instance, conn_id);
             // if writing fails, close this connection
             if let Err(err) = result {
@@ -129,9 +133,72 @@ async fn unix_listen(listener: UnixListener, mut instance: Mize) -> MizeResult<(
     }
 }
 
+// wake the listener of the instance at store_path (it sees, that it is stopping) and remove the
+// socket
+pub fn stop_listening(store_path: &Path) -> MizeResult<()> {
+    let sock_path = store_path.join("sock");
+    if std::os::unix::net::UnixStream::connect(&sock_path).is_err() {
+        debug!("no one listens on '{}' anymore", sock_path.display());
+    }
+    if sock_path.exists() {
+        fs::remove_file(&sock_path)
+            .mize_result_msg(format!("could not remove '{}'", sock_path.display()))?;
+    }
+    Ok(())
+}
+
+// ask the instance listening at store_path to shut down, returns once it closed our connection,
+// which it does after it flushed it's store and removed it's socket (see Mize::shutdown)
+pub fn request_shutdown(instance: &Mize, store_path: &Path) -> MizeResult<()> {
+    let sock_path = store_path.join("sock");
+    let mut stream = std::os::unix::net::UnixStream::connect(&sock_path).mize_result_msg(
+        format!("no instance is running at '{}'", store_path.display()),
+    )?;
+
+    let msg = MizeMessage::new_update_request(
+        instance.new_id("inst/shutdown")?,
+        ItemData::from_cbor(CborValue::Bool(true)),
+        0,
+    );
+    ciborium::into_writer(&msg.value(), &mut stream)?;
+
+    // what it sends us until then is not needed
+    stream.set_read_timeout(Some(SHUTDOWN_TIMEOUT))?;
+    let mut buf = [0u8; 1024];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(mize_err!(
+                    "the instance at '{}' did not stop in time",
+                    store_path.display()
+                ));
+            }
+            // it closed the connection without reading everything, it's gone too
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+// a peer running as our own user is the owner
+fn principal_of(stream: &UnixStream) -> MizeResult<Principal> {
+    let uid = stream
+        .peer_cred()
+        .mize_result_msg("could not get the credentials of the unix socket peer")?
+        .uid();
+    if uid == nix::unistd::getuid().as_raw() {
+        Ok(Principal::Owner)
+    } else {
+        Ok(Principal::Uid(uid))
+    }
+}
+
 fn unix_outgoing(
     mut unix_write: OwnedWriteHalf,
-    send_rx: Receiver<MizeMessage>,
+    send_rx: QueueReceiver<MizeMessage>,
     mut instance: Mize,
     conn_id: u64,
 ) -> MizeResult<()> {
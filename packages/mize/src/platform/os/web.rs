@@ -1,239 +1,459 @@
+use axum::body::Bytes;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use ciborium::Value as CborValue;
+use futures_util::stream::{SplitSink, SplitStream, Stream};
+use futures_util::{SinkExt, StreamExt};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use tracing::{debug, info, warn};
+
+use crate::error::{IntoMizeResult, MizeError, MizeResult};
+use crate::instance::auth::{self, Right};
+use crate::instance::connection::ConnListener;
+use crate::instance::queue::QueueReceiver;
+use crate::instance::Mize;
+use crate::item::{IntoItemData, ItemData};
+use crate::mize_err;
+use crate::platform::os::config_flag;
+use crate::proto::MizeMessage;
 
 static SERVER_PORT: u16 = 3000;
 static API_ENDPOINT: &str = "@mize";
 
+static CBOR_MIME: &str = "application/cbor";
+static JSON_MIME: &str = "application/json";
 
-pub fn run() -> Result<Web, MizeError> {
-    /*
-     *  - Run a Webserver
-     *
-     *
-     *
-     */
+pub struct WebListener {
+    addr: SocketAddr,
+    // also serve the http rest gateway at /items and /events
+    rest: bool,
+}
+
+impl WebListener {
+    pub fn new(addr: SocketAddr, rest: bool) -> MizeResult<WebListener> {
+        Ok(WebListener { addr, rest })
+    }
+
+    // reads the web.addr and web.rest options from the config of the instance
+    // falls back to listening on localhost with the SERVER_PORT
+    pub fn from_config(instance: &Mize) -> MizeResult<WebListener> {
+        let addr_str = match instance.get("self/config/web/addr")?.value_string() {
+            Ok(addr) => addr,
+            Err(_) => format!("127.0.0.1:{}", SERVER_PORT),
+        };
+
+        let addr: SocketAddr = addr_str
+            .parse()
+            .mize_result_msg(format!("could not parse web.addr '{}'", addr_str))?;
+
+        let rest = config_flag(instance, "self/config/web/rest")?;
 
- 
-    // I will deal with renders later. cli only for now
-    // let renders = 
+        WebListener::new(addr, rest)
+    }
+}
 
-//    for render in &*renders {
-//        match render.render_type {
-//            RenderType::JsonUi => {},
-//            RenderType::WebComponent => {
-//                let url_path = String::from("/api/render/") + &render.name + "/webroot";
-//                let file_path = mutexes.mize_folder.clone() + "/mr/" + &render.folder[..] + "/" + &render.webroot.clone()[..];
-//                let serve_dir = get_service(ServeDir::new(&file_path)).handle_error(handle_error);
+impl ConnListener for WebListener {
+    fn listen(self, mut instance: Mize) -> MizeResult<()> {
+        let listen_instance = instance.clone();
+        instance.spawn_async("web listen async", async move {
+            if let Err(err) = web_listen(self, listen_instance.clone()).await {
+                listen_instance.report_err(err);
+            }
+        })
+    }
+}
 
-//                app = app.nest_service(&url_path, serve_dir);
-//            },
-//        }
-//    }
+pub fn web_router(instance: Mize, rest: bool) -> Router {
+    let mut router =
+        Router::new().route(&format!("/{}/socket", API_ENDPOINT), get(websocket_handler));
 
+    if rest {
+        router = router
+            .route("/items", post(create_item))
+            .route("/items/*id", get(get_item).put(set_item).patch(set_item))
+            .route("/events/*id", get(sub_item));
+    }
 
-    // let serve_client = get_service(ServeDir::new("js-client/src")).handle_error(handle_error);
+    router.with_state(instance)
+}
 
-    let mut app = Router::new()
-        .route(format!("/{}/socket", API_ENDPOINT), get(websocket_handler))
-//        .route(format!("/{}/render/:id", API_ENDPOINT), get(get_render_main))
-//        .route(format!("/{}/file", API_ENDPOINT), get(get_file_handler))
-//        .merge(SpaRouter::new(format!("/{}/client", API_ENDPOINT), "js-client/src").index_file("main.html"))
-//        .nest_service(format!("/{}/client", API_ENDPOINT), serve_client)
-        .with_state(main)
-        .fallback(render_item);
+async fn web_listen(listener: WebListener, instance: Mize) -> MizeResult<()> {
+    let stop_instance = instance.clone();
+    let app = web_router(instance, listener.rest);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], SERVER_PORT));
-    // tracing::debug!("listening on {}", addr);
+    info!("web listener on {}", listener.addr);
 
-    axum::Server::bind(&addr)
+    axum::Server::try_bind(&listener.addr)
+        .mize_result_msg(format!("Could not bind to '{}'", listener.addr))?
         .serve(app.into_make_service())
+        .with_graceful_shutdown(async move { stop_instance.stopped().await })
         .await
-        .expect(format!("Unable to bind axum::Server to {}", addr));
+        .mize_result_msg("web server stopped")?;
+
+    Ok(())
 }
 
-pub struct Web {
+async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    State(instance): State<Mize>,
+) -> Result<Response, HttpError> {
+    if !origin_allowed(&instance, &headers)? {
+        return Err(HttpError(
+            StatusCode::FORBIDDEN,
+            mize_err!("websocket connections from this origin are not allowed"),
+        ));
+    }
+    Ok(ws.on_upgrade(|socket| handle_websocket_connection(socket, instance)))
 }
 
-impl Web {
+// a browser sends the origin of the page, that opens the socket, so that a page of another site
+// can't talk to us as whoever visits it
+// allowed are our own origin and the ones in the config option web.origins, clients, that are not
+// browsers, send none
+pub(crate) fn origin_allowed(instance: &Mize, headers: &HeaderMap) -> MizeResult<bool> {
+    let origin = match headers.get(header::ORIGIN) {
+        Some(origin) => origin.to_str().unwrap_or_default(),
+        None => return Ok(true),
+    };
 
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    if host.is_some() && origin.split_once("://").map(|(_, rest)| rest) == host {
+        return Ok(true);
+    }
+
+    let allowed = match instance.get("self/config/web/origins")?.as_data_full() {
+        Ok(ItemData(CborValue::Array(origins))) => {
+            origins.contains(&CborValue::Text(origin.to_owned()))
+        }
+        _ => false,
+    };
+    if !allowed {
+        warn!(
+            "refusing a websocket connection from the origin '{}'",
+            origin
+        );
+    }
+    Ok(allowed)
 }
 
+async fn handle_websocket_connection(socket: WebSocket, instance: Mize) {
+    let (socket_tx, socket_rx) = socket.split();
+    let (send_tx, send_rx) = match instance.new_conn_queue() {
+        Ok(queue) => queue,
+        Err(err) => {
+            err.log();
+            return;
+        }
+    };
 
-async fn handle_error(_err: std::io::Error) -> impl IntoResponse {
-        MizeError::new(0).msg("internal server error").handle()
-        (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong...")
+    let conn_id = match instance.new_connection(send_tx) {
+        Ok(id) => id,
+        Err(err) => {
+            err.log();
+            return;
+        }
+    };
+    info!("new websocket connection: {}", conn_id);
+
+    // a peer on the network is no one, until it proves, that it has a key
+    if let Err(err) = auth::challenge(&instance, conn_id) {
+        err.log();
+    }
+
+    let out_instance = instance.clone();
+    tokio::spawn(async move {
+        if let Err(err) = ws_outgoing(socket_tx, send_rx, conn_id).await {
+            out_instance.report_err(err);
+        }
+    });
+
+    // if reading fails, close the connection
+    if let Err(err) = ws_incomming(socket_rx, instance.clone(), conn_id).await {
+        warn!(
+            "websocket connection {} closing: {:?}",
+            conn_id, err.messages
+        );
+    }
+
+    if let Err(err) = instance.remove_connection(conn_id) {
+        err.log();
+    }
 }
 
-async fn render_item(uri: Uri) -> impl IntoResponse {
-    Html(fs::read_to_string("js-client/src/main.html").unwrap())
+async fn ws_outgoing(
+    mut socket_tx: SplitSink<WebSocket, Message>,
+    send_rx: QueueReceiver<MizeMessage>,
+    conn_id: u64,
+) -> MizeResult<()> {
+    while let Ok(msg) = send_rx.recv_async().await {
+        debug!("ws outgoing got msg: {}", msg);
+        let mut buf: Vec<u8> = Vec::new();
+        ciborium::into_writer(&msg.value(), &mut buf)?;
+        socket_tx
+            .send(Message::Binary(buf))
+            .await
+            .mize_result_msg(format!("websocket connection {} closed", conn_id))?;
+    }
+
+    // the connection was removed from the instance, so tell the peer
+    // it may have closed the socket already, then there is no one to tell
+    let close = Message::Close(Some(CloseFrame {
+        code: 1000,
+        reason: "connection closed by instance".into(),
+    }));
+    if let Err(err) = socket_tx.send(close).await {
+        debug!("websocket connection {} already closed: {}", conn_id, err);
+    }
+
+    Ok(())
 }
 
-//#[axum_macros::debug_handler]
-//async fn get_render_main(extract::Path(id): extract::Path<String>, State(mutexes): State<Mutexes>) -> http::Response<String> {
-//    let renders = &*mutexes.renders.lock().await;
+async fn ws_incomming(
+    mut socket_rx: SplitStream<WebSocket>,
+    instance: Mize,
+    conn_id: u64,
+) -> MizeResult<()> {
+    while let Some(result) = socket_rx.next().await {
+        let ws_msg = result.mize_result_msg("websocket read failed")?;
+
+        match ws_msg {
+            Message::Binary(bytes) => {
+                let value: CborValue = ciborium::from_reader(bytes.as_slice())
+                    .mize_result_msg("websocket binary frame is not valid cbor")?;
+                let msg = MizeMessage::new(value, conn_id);
+                debug!("ws incoming got msg: {}", msg);
+                instance.got_msg(msg)?;
+            }
 
-//    let render = renders.iter().filter(|&render| render.name == id).nth(0)
-//        .unwrap_or(renders.iter().filter(|&render| render.name == "mize-mmejs").nth(0).expect("mize-mmejs"));
+            Message::Text(_) => {
+                warn!("websocket connection {} sent a text frame, only binary cbor frames are supported", conn_id);
+            }
 
-//    let file_path = mutexes.mize_folder.clone() + "/mr/" + &render.folder + "/" + &render.main;
+            Message::Close(_) => {
+                return Ok(());
+            }
 
-//    match render.render_type {
-//        RenderType::JsonUi => {
-//            return Response::builder()
-//                .header("content-type", "application/json")
-//                .status(StatusCode::OK)
-//                .body(fs::read_to_string(file_path).unwrap()).unwrap();
-//        },
-//        RenderType::WebComponent => {
-//            return Response::builder()
-//                .header("content-type", "application/javascript")
-//                .status(StatusCode::OK)
-//                .body(fs::read_to_string(file_path).unwrap()).unwrap();
-//        },
-//    }
-//}
+            // pings are answered by axum itself
+            Message::Ping(_) | Message::Pong(_) => {}
+        }
+    }
 
-async fn websocket_handler(
-        ws: WebSocketUpgrade,
-        State(main): State<&Main>,
-        headers: HeaderMap,
-) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_websocket_connection(socket, main, headers))
+    Ok(())
 }
 
-async fn handle_websocket_connection(
-    socket: WebSocket,
-    main: &Main,
-    headers: HeaderMap,
-){
-    //TODO: auth
-
-    //socket_tx.send() ... send out the socket
-    //socket_rx.read() ... read a message from the socket
-    let (mut socket_tx, mut socket_rx) = socket.split();
-
-    // msg_tx.send() ... send a MizeMessage to a Peer. should be in a Peer struct
-    // msg_rx.read() ... read MizeMessages that should be sent to the Peer that this ws connection is to
-    let (msg_tx, msg_rx): (Sender<proto::MizeMessage>, Receiver<proto::MizeMessage>) = mpsc::channel(SOCKET_CHANNEL_SIZE);
-
-    // why is this actually
-    let mut msg_rx = ReceiverStream::new(msg_rx);
-
-    // check if mize-module header exists
-//    let origin = if let Some(mod_header_val) = headers.get("mize-module"){
-
-//        let mut mods = mutexes.modules.lock().await;
-//        let mut client_id = mutexes.next_free_client_id.lock().await;
-//        let mut module = Module{
-//            tx: msg_tx.clone(),
-//            client_id: *client_id,
-//            name: String::from("tmp"),
-//            kind: ModuleKind::Extern(),
-//            registered_types: Vec::new(),
-//        };
-
-//        *client_id += 1;
-
-//        if let Ok(name) = mod_header_val.to_str() {
-//            module.name = name.to_string();
-//        } else {
-//            let err = MizeError::new(113)
-//                .extra_msg("The mize-module Header could not be decoded into a String.");
-
-//            msg_tx.send(proto::MizeMessage::Json(err.to_json_message())).await;
-//            return;
-//        };
-//        println!("module {} connected", module.name);
-//        mods.push(module.clone());
-//        drop(mods);
-//        Peer::Module(module)
-
-//    } else {
-//    };
-
-    // Forwarding messages
-    tokio::spawn(async move {
-        while let Some(msg) = msg_rx.next().await {
-            match msg {
-                proto::MizeMessage::Json(json_msg) => {
-                    if let Ok(msg) = serde_json::to_string(&json_msg) {
-                        let result = socket_tx.send(Message::Text(msg.clone())).await;
-                        if let Err(e) = result {
-                            println!("Client no longer Connected: {:?}", e);
-                            remove_peer(myorigin.clone(), mymutexes.clone()).await;
-                        } else {
-                            println!("SENDING: {}", msg);
-                        }
-                    } else {
-                        let err: MizeError = MizeError::new(11).extra_msg("error while serializing a json message").handle();
-                        if let Ok(text) = serde_json::to_string(&err.to_json()){
-                            socket_tx.send(Message::Text(text));
-                        } else {
-                            MizeError::new(11).extra_msg("not even being able to serialize an error, while failing to serializing a message").handle();
-                        }
-                    }
-                }
-                proto::MizeMessage::Bin(bin_msg) => {
-                    socket_tx.send(Message::Binary(bin_msg.raw)).await;
-                }
-                proto::MizeMessage::Pong() => {
-                    socket_tx.send(Message::Pong(Vec::new())).await;
-                }
-                proto::MizeMessage::Close((num, reason)) => {
-                    println!("SENDING Close message");
-                    socket_tx.send(Message::Close(Some(CloseFrame{code: num, reason: Cow::Owned(reason)}))).await;
-                }
-            }
-        }
-    });
+////// the http rest gateway
 
-    // Reading messages
-    while let Some(result) = socket_rx.next().await {
-        let msg = if let Ok(msg) = result {msg} else {
-            let err = MizeError::new(115).extra_msg("TCP Connection Closed");
-            remove_peer(origin.clone(), mutexes.clone());
-            return;
-        };
-        match msg {
-            Message::Binary(b) => {
-                println!("Recieved a Binary Message. Those are not implemented yet.")
-            },
-
-
-            Message::Text(text) => {
-                println!("GOT: {}", text);
-                let json_msg: proto::JsonMessage = serde_json::from_str(&text).expect("error parsing json");
-                if let Err(err) = proto::handle_json_msg(json_msg, origin.clone(), mutexes.clone()).await {
-                    let err_msg: MizeMessage = err.handle().into();
-                    origin.send(err_msg).await;
-                };
-            },
-
-            Message::Close(inner) => {
-                match inner {
-                    Some(CloseFrame{code, reason}) => {
-                        origin.send(MizeMessage::Close((code, reason.into_owned()))).await;
-                    },
-                    None => {
-                        origin.send(MizeMessage::Close((1000, "You didn't send a Close Reason".to_owned()))).await;
-                    }
-                };
-
-                println!("Close from Client");
-                remove_peer(origin.clone(), mutexes.clone()).await;
-            }
+struct HttpError(StatusCode, MizeError);
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        let HttpError(status, err) = self;
+        let body = err.messages.join("\n");
+        err.log();
+        (status, body).into_response()
+    }
+}
 
+impl From<MizeError> for HttpError {
+    fn from(err: MizeError) -> HttpError {
+        HttpError(StatusCode::INTERNAL_SERVER_ERROR, err)
+    }
+}
 
-            Message::Ping(_) => {
-                msg_tx.send(MizeMessage::Pong());
-            },
+fn wants_cbor(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|val| val.to_str().ok())
+        .map(|val| val.contains(CBOR_MIME))
+        .unwrap_or(false)
+}
 
+fn data_response(headers: &HeaderMap, data: ItemData) -> Result<Response, HttpError> {
+    if wants_cbor(headers) {
+        let mut buf: Vec<u8> = Vec::new();
+        ciborium::into_writer(data.cbor(), &mut buf).map_err(MizeError::from)?;
+        return Ok(([(header::CONTENT_TYPE, CBOR_MIME)], buf).into_response());
+    }
 
-            Message::Pong(_) => {
-                let err = MizeError::new(11)
-                    .extra_msg("unhandeld WebSocket-Message type: Pong")
-                    .handle();
-                msg_tx.send(proto::MizeMessage::Json(err.to_json_message())).await;
-            },
+    Ok(([(header::CONTENT_TYPE, JSON_MIME)], data.to_json()?).into_response())
+}
 
+// the body is interpreted by its content-type, defaulting to json
+fn data_from_body(headers: &HeaderMap, body: Bytes) -> Result<ItemData, HttpError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+        .unwrap_or(JSON_MIME);
+
+    let result = if content_type.starts_with(CBOR_MIME) {
+        ciborium::from_reader::<CborValue, _>(body.as_ref())
+            .map(|value| value.into_item_data())
+            .mize_result_msg("request body is not valid cbor")
+    } else if content_type.starts_with("text/plain") {
+        String::from_utf8(body.to_vec())
+            .map(ItemData::from_string)
+            .mize_result_msg("request body is not valid utf-8")
+    } else {
+        String::from_utf8(body.to_vec())
+            .mize_result_msg("request body is not valid utf-8")
+            .and_then(ItemData::from_json)
+    };
+
+    result.map_err(|err| HttpError(StatusCode::BAD_REQUEST, err))
+}
+
+// with auth enabled, a rest request needs the rights of an acl rule for it's key
+// id is None for a create in our namespace
+fn authorize(
+    instance: &Mize,
+    headers: &HeaderMap,
+    id: Option<&str>,
+    rights: &[Right],
+) -> Result<(), HttpError> {
+    if let (Some(id), true) = (id, rights.contains(&Right::Write)) {
+        let id = instance.new_id(id)?;
+        let path: Vec<String> = id.path().into_iter().map(|part| part.to_owned()).collect();
+        if auth::local_only(instance, &id.namespace(), &path)? {
+            return Err(HttpError(
+                StatusCode::FORBIDDEN,
+                mize_err!("'{}' can't be written over the web", id),
+            ));
+        }
+    }
+
+    if !auth::auth_enabled(instance)? {
+        let id = match id {
+            Some(id) => instance.new_id(id)?,
+            None => return Ok(()),
         };
+        let path: Vec<String> = id.path().into_iter().map(|part| part.to_owned()).collect();
+        if rights.contains(&Right::Write) && auth::owner_only(instance, &id.namespace(), &path)? {
+            return Err(HttpError(
+                StatusCode::FORBIDDEN,
+                mize_err!("only a local peer may write '{}'", id),
+            ));
+        }
+        return Ok(());
+    }
+
+    let unauthorized = |msg: &str| HttpError(StatusCode::UNAUTHORIZED, mize_err!("{}", msg));
+    let credentials = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|val| val.to_str().ok())
+        .and_then(|val| val.strip_prefix("Bearer "))
+        .and_then(|val| val.split_once(':'))
+        .ok_or_else(|| {
+            unauthorized("expected the header 'Authorization: Bearer <key>:<secret>'")
+        })?;
+    let principal = auth::key_principal(instance, credentials.0, credentials.1)?
+        .ok_or_else(|| unauthorized("unknown key or wrong secret"))?;
+
+    let (namespace, path) = match id {
+        Some(id) => {
+            let id = instance.new_id(id)?;
+            let path: Vec<String> = id.path().into_iter().map(|part| part.to_owned()).collect();
+            (id.namespace(), path)
+        }
+        None => (instance.get_self_namespace()?, Vec::new()),
+    };
+    for right in rights {
+        if !auth::allowed(instance, Some(&principal), &namespace, &path, *right)? {
+            return Err(HttpError(
+                StatusCode::FORBIDDEN,
+                mize_err!("{} may not {}", principal.as_string(), right.name()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn get_item(
+    State(instance): State<Mize>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, HttpError> {
+    authorize(&instance, &headers, Some(&id), &[Right::Read])?;
+    let data = instance.as_async().get(id).await?;
+    data_response(&headers, data)
+}
+
+// PUT and PATCH both map to Mize::set, which merges the data into the item
+async fn set_item(
+    State(instance): State<Mize>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, HttpError> {
+    authorize(&instance, &headers, Some(&id), &[Right::Write])?;
+    let data = data_from_body(&headers, body)?;
+    instance.as_async().set(id, data).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn create_item(
+    State(instance): State<Mize>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, HttpError> {
+    authorize(&instance, &headers, None, &[Right::Create, Right::Write])?;
+    let data = if body.is_empty() {
+        None
+    } else {
+        Some(data_from_body(&headers, body)?)
     };
+
+    let instance = instance.as_async();
+    let id = instance.new_item().await?.id();
+    if let Some(data) = data {
+        instance.set(id.clone(), data).await?;
+    }
+    let id = id.to_string();
+
+    let mut reply = ItemData::new();
+    reply.set_path("id", id)?;
+
+    let mut response = data_response(&headers, reply)?;
+    *response.status_mut() = StatusCode::CREATED;
+    Ok(response)
+}
+
+// server-sent events, one event with the json data of the item for every update
+async fn sub_item(
+    State(instance): State<Mize>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, HttpError> {
+    authorize(
+        &instance,
+        &headers,
+        Some(&id),
+        &[Right::Read, Right::Subscribe],
+    )?;
+    let instance = instance.as_async();
+    let current = instance.get(id.as_str()).await?;
+    let updates = instance.sub(id)?;
+
+    let first = futures_util::stream::once(async move { current.to_json() });
+    let updates = updates.then(move |update| {
+        let instance = instance.clone();
+        async move { instance.get(update.id).await?.to_json() }
+    });
+
+    let events = first.chain(updates).map(|result| match result {
+        Ok(json) => Ok(Event::default().data(json)),
+        Err(err) => Ok(Event::default()
+            .event("error")
+            .data(err.messages.join("\n"))),
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
 }
@@ -18,6 +18,7 @@ fn main() {
 #[cfg(feature = "target-os")]
 fn os_main(mize: &mut Mize) -> MizeResult<()> {
     marts::cli(mize)?;
+    marts::scheduler(mize)?;
     //marts::js(mize)?;
     marts::habitica(mize)?;
     marts::c2vi(mize)?;
@@ -28,11 +28,16 @@ pub fn server(mize: &mut Mize) -> MizeResult<()> {
     let mut cli = mize.get_part_native::<marts::CliPart>("cli")?;
     mize.add_name_only_part("ppc.server");
 
-    mize.new_opt("auth.issuer");
-    mize.new_opt("auth.client_id");
-    mize.new_opt("auth.client_secret");
-    mize.new_opt("auth.redirect");
-    mize.new_opt("auth.cookie_key");
+    mize.new_opt("auth.issuer")
+        .typed::<String>()
+        .doc("the url of the oidc provider");
+    mize.new_opt("auth.client_id").typed::<String>();
+    mize.new_opt("auth.client_secret").typed::<String>();
+    mize.new_opt("auth.redirect").typed::<String>();
+    mize.new_opt("auth.cookie_key").typed::<String>();
+    mize.new_opt("web.url")
+        .typed::<String>()
+        .doc("the url, the server is reached at");
 
     let mut mize = mize.clone();
     cli.subcommand(Command::new("server"), move |_, _| {
@@ -49,10 +54,10 @@ pub fn server(mize: &mut Mize) -> MizeResult<()> {
 pub async fn start_server(mize: &mut Mize) -> MizeResult<()> {
     dioxus::logger::initialize_default();
 
-    let issuer = mize.get_config("auth.issuer")?.value_string()?;
-    let client_id = mize.get_config("auth.client_id")?.value_string()?;
-    let client_secret = mize.get_config("auth.client_secret")?.value_string()?;
-    let url = mize.get_config("web.url")?.value_string()?;
+    let issuer = mize.get_config_as::<String>("auth.issuer")?;
+    let client_id = mize.get_config_as::<String>("auth.client_id")?;
+    let client_secret = mize.get_config_as::<String>("auth.client_secret")?;
+    let url = mize.get_config_as::<String>("web.url")?;
     let redirect_url = format!("{url}/oidc");
 
     let session_store = MemoryStore::default();